@@ -65,11 +65,29 @@ impl PPM {
     }
 }
 
+/// Write `pixels`, given row by row from the top, to a Portable Float Map,
+/// which keeps values outside of [0, 1] for compositing.
+pub fn save_pfm<F>(fp: F, width: u32, height: u32, pixels: &[Color]) -> std::io::Result<()>
+where
+    F: AsRef<Path>,
+{
+    let mut content = format!("PF\n{} {}\n-1.0\n", width, height).into_bytes();
+    // rows are stored from the bottom, -1.0 above means little endian
+    for row in pixels.chunks(width as usize).rev() {
+        for c in row {
+            for value in [c.0, c.1, c.2] {
+                content.extend_from_slice(&(value as f32).to_le_bytes());
+            }
+        }
+    }
+    std::fs::write(fp, content)
+}
+
 #[cfg(test)]
 mod test {
     use vec3::v3;
 
-    use crate::PPM;
+    use crate::{save_pfm, PPM};
 
     #[test]
     fn test_generate_img() {
@@ -86,4 +104,17 @@ mod test {
         }
         assert!(image.save("test.ppm").is_ok());
     }
+
+    #[test]
+    fn test_save_pfm() {
+        let pixels = [v3!(0., 0.5, 1.), v3!(2., -1., 0.), v3!(1., 1., 1.), v3!(0., 0., 0.)];
+        save_pfm("test.pfm", 2, 2, &pixels).unwrap();
+        let content = std::fs::read("test.pfm").unwrap();
+        std::fs::remove_file("test.pfm").unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&content[..header.len()], header);
+        assert_eq!(content.len(), header.len() + 4 * 3 * 4);
+        // the bottom row comes first
+        assert_eq!(content[header.len()..header.len() + 4], 1f32.to_le_bytes());
+    }
 }
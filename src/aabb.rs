@@ -0,0 +1,68 @@
+use vec3::{v3, Point3};
+
+use crate::ray::Ray;
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    /// Box spanned by two opposite corners, in any order.
+    pub fn new(a: Point3, b: Point3) -> Self {
+        Self {
+            min: v3!(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: v3!(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Self {
+        Self::new(
+            v3!(self.min.x().min(other.min.x()), self.min.y().min(other.min.y()), self.min.z().min(other.min.z())),
+            v3!(self.max.x().max(other.max.x()), self.max.y().max(other.max.y()), self.max.z().max(other.max.z())),
+        )
+    }
+
+    /// Box of what the two have in common. Boxes that don't meet leave
+    /// nothing, here a point.
+    pub fn intersection(&self, other: &Aabb) -> Self {
+        let min = v3!(self.min.x().max(other.min.x()), self.min.y().max(other.min.y()), self.min.z().max(other.min.z()));
+        let max = v3!(self.max.x().min(other.max.x()), self.max.y().min(other.max.y()), self.max.z().min(other.max.z()));
+        Self::new(min, v3!(max.x().max(min.x()), max.y().max(min.y()), max.z().max(min.z())))
+    }
+
+    pub fn center(&self) -> Point3 {
+        (self.min + self.max) / 2.
+    }
+
+    /// radius of the smallest sphere around `center` containing the box
+    pub fn radius(&self) -> f64 {
+        (self.max - self.min).length() / 2.
+    }
+
+    /// Whether `r` passes through the box somewhere in `t_min..t_max`.
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.clip(r, t_min, t_max).is_some()
+    }
+
+    /// The part of `t_min..t_max` for which `r` is inside the box.
+    pub fn clip(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+        for axis in 0..3 {
+            let inv_d = 1. / r.direction()[axis];
+            let mut t0 = (self.min[axis] - r.origin()[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - r.origin()[axis]) * inv_d;
+            if inv_d < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            // flat boxes around axis aligned quads still count when t_max == t_min
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
@@ -0,0 +1,179 @@
+//! Arbitrary output variables: buffers written next to the beauty image for
+//! compositing.
+
+use ppm::save_pfm;
+use vec3::{v3, Color, Point3, Vec3};
+
+use crate::{ray::Ray, scene::Scene};
+
+/// What a single camera sample saw, besides its radiance.
+#[derive(Clone, Copy)]
+pub struct Aov {
+    pub albedo: Color,
+    /// shading normal, facing the camera
+    pub normal: Vec3,
+    /// distance from the camera, 0 where nothing was hit
+    pub depth: f64,
+    pub position: Point3,
+    /// index of the hit object in the scene
+    pub object_id: Option<usize>,
+    /// light arriving straight from a light source, filled in by the integrator
+    pub direct: Color,
+    /// all other light, filled in by the integrator
+    pub indirect: Color,
+}
+
+impl Default for Aov {
+    fn default() -> Self {
+        Self {
+            albedo: v3!(0., 0., 0.),
+            normal: v3!(0., 0., 0.),
+            depth: 0.,
+            position: v3!(0., 0., 0.),
+            object_id: None,
+            direct: v3!(0., 0., 0.),
+            indirect: v3!(0., 0., 0.),
+        }
+    }
+}
+
+impl Aov {
+    /// The surface variables of the first hit along `ray`, with the lighting
+    /// passes left black.
+    pub fn first_hit(ray: &Ray, scene: &Scene) -> Self {
+        let mut aov = Self::default();
+        if let Some((id, rec)) = scene.world.hit_object(ray, 0.001, utils::INFINITY) {
+            aov.albedo = rec.material.albedo(&rec);
+            aov.normal = rec.normal;
+            aov.depth = rec.t * ray.direction().length();
+            aov.position = rec.p;
+            aov.object_id = Some(id);
+        }
+        aov
+    }
+}
+
+/// Per pixel sums of the samples' variables.
+pub struct AovImage {
+    width: usize,
+    pixels: Vec<Aov>,
+}
+
+impl AovImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            pixels: vec![Aov::default(); width * height],
+        }
+    }
+
+    /// Add a sample to the pixel at `row`, counted from the top, and `column`.
+    pub fn add(&mut self, row: usize, column: usize, aov: &Aov) {
+        let pixel = &mut self.pixels[row * self.width + column];
+        pixel.albedo = pixel.albedo + aov.albedo;
+        pixel.normal = pixel.normal + aov.normal;
+        pixel.depth += aov.depth;
+        pixel.position = pixel.position + aov.position;
+        // ids can't be averaged, so a pixel keeps the first one it sees
+        pixel.object_id = pixel.object_id.or(aov.object_id);
+        pixel.direct = pixel.direct + aov.direct;
+        pixel.indirect = pixel.indirect + aov.indirect;
+    }
+
+    /// Save the averages over `nsamples` samples as `<stem>.<pass>.pfm`.
+    /// Object ids are stored as grey levels, with -1 where nothing was hit.
+    pub fn save(&self, stem: &str, nsamples: usize) -> std::io::Result<()> {
+        let width = self.width as u32;
+        let height = (self.pixels.len() / self.width) as u32;
+        let n = nsamples as f64;
+        for name in ["albedo", "normal", "depth", "position", "direct", "indirect", "object_id"] {
+            let pixels: Vec<Color> = self
+                .pixels
+                .iter()
+                .map(|a| match name {
+                    "albedo" => a.albedo / n,
+                    "normal" => a.normal / n,
+                    "depth" => v3!(a.depth, a.depth, a.depth) / n,
+                    "position" => a.position / n,
+                    "direct" => a.direct / n,
+                    "indirect" => a.indirect / n,
+                    _ => {
+                        let id = a.object_id.map_or(-1., |id| id as f64);
+                        v3!(id, id, id)
+                    }
+                })
+                .collect();
+            save_pfm(format!("{}.{}.pfm", stem, name), width, height, &pixels)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::v3;
+
+    use crate::{
+        camera::Camera,
+        hittable::HittableList,
+        material::Lambertian,
+        ray::Ray,
+        scene::{Background, Scene},
+        sphere::Sphere,
+    };
+
+    use super::{Aov, AovImage};
+
+    #[test]
+    fn test_first_hit() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(v3!(0., 0., -2.), 0.5, Arc::new(Lambertian::new(&v3!(0.2, 0.4, 0.6))))));
+        let camera = Camera::new(v3!(0., 0., 0.), v3!(0., 0., -1.), v3!(0., 1., 0.), 40., 1., 0., 1.);
+        let scene = Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)));
+
+        // the depth is a distance, whatever the length of the direction
+        let aov = Aov::first_hit(&Ray::new(v3!(0., 0., 0.), v3!(0., 0., -2.)), &scene);
+        assert!((aov.depth - 1.5).abs() < 1e-9, "{}", aov.depth);
+        assert!((aov.normal - v3!(0., 0., 1.)).length() < 1e-9 && (aov.position - v3!(0., 0., -1.5)).length() < 1e-9);
+        assert_eq!((aov.albedo, aov.object_id), (v3!(0.2, 0.4, 0.6), Some(0)));
+
+        let aov = Aov::first_hit(&Ray::new(v3!(0., 0., 0.), v3!(0., 0., 1.)), &scene);
+        assert_eq!((aov.depth, aov.object_id, aov.normal), (0., None, v3!(0., 0., 0.)));
+    }
+
+    #[test]
+    fn test_aov_image() {
+        let mut image = AovImage::new(2, 1);
+        let sample = |depth: f64, object_id: Option<usize>| Aov {
+            depth,
+            object_id,
+            direct: v3!(depth, 0., 0.),
+            ..Aov::default()
+        };
+        image.add(0, 1, &sample(1., None));
+        image.add(0, 1, &sample(3., Some(4)));
+        image.add(0, 1, &sample(5., Some(2)));
+
+        let stem = std::env::temp_dir().join(format!("aov-test-{}", std::process::id()));
+        let stem = stem.to_str().unwrap();
+        image.save(stem, 3).unwrap();
+        let read = |pass: &str| {
+            let path = format!("{}.{}.pfm", stem, pass);
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let header = b"PF\n2 1\n-1.0\n";
+            assert_eq!(&bytes[..header.len()], header);
+            bytes[header.len()..].chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect::<Vec<_>>()
+        };
+        // averaged over the samples, next to an empty pixel
+        assert_eq!(read("depth"), [0., 0., 0., 3., 3., 3.]);
+        assert_eq!(read("direct"), [0., 0., 0., 3., 0., 0.]);
+        // the first id seen stays, and nothing seen is -1
+        assert_eq!(read("object_id"), [-1., -1., -1., 4., 4., 4.]);
+        for pass in ["albedo", "normal", "position", "indirect"] {
+            assert_eq!(read(pass), [0.; 6]);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable, HittableList}, ray::Ray};
+
+enum Node {
+    Leaf { bbox: Aabb, object: usize },
+    Interior { bbox: Aabb, left: usize, right: usize },
+}
+
+impl Node {
+    fn bbox(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bbox, .. } | Node::Interior { bbox, .. } => bbox,
+        }
+    }
+}
+
+/// Bounding volume hierarchy over the objects of a list, so that a ray only
+/// tests the objects whose boxes it passes through.
+pub struct Bvh {
+    objects: Vec<Arc<dyn Hittable>>,
+    nodes: Vec<Node>,
+    root: Option<usize>,
+    /// objects without a bounding box, tested against every ray
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(list: HittableList) -> Self {
+        let objects = list.objects().to_vec();
+        let mut bounded = vec![];
+        let mut unbounded = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((i, bbox)),
+                None => unbounded.push(i),
+            }
+        }
+        let mut nodes = vec![];
+        let root = if bounded.is_empty() { None } else { Some(Self::build(&mut nodes, &mut bounded)) };
+        Self {
+            objects,
+            nodes,
+            root,
+            unbounded,
+        }
+    }
+
+    /// Split `objects` in two halves along the axis their centers spread the
+    /// most, and return the index of the node holding both.
+    fn build(nodes: &mut Vec<Node>, objects: &mut [(usize, Aabb)]) -> usize {
+        let bbox = objects[1..].iter().fold(objects[0].1, |b, (_, o)| b.surrounding(o));
+        if let [(object, _)] = objects {
+            nodes.push(Node::Leaf { bbox, object: *object });
+            return nodes.len() - 1;
+        }
+        let centers = objects[1..]
+            .iter()
+            .fold(Aabb::new(objects[0].1.center(), objects[0].1.center()), |b, (_, o)| {
+                b.surrounding(&Aabb::new(o.center(), o.center()))
+            });
+        let extent = centers.max - centers.min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        let mid = objects.len() / 2;
+        objects.select_nth_unstable_by(mid, |a, b| a.1.center()[axis].total_cmp(&b.1.center()[axis]));
+        let (left, right) = objects.split_at_mut(mid);
+        let left = Self::build(nodes, left);
+        let right = Self::build(nodes, right);
+        nodes.push(Node::Interior { bbox, left, right });
+        nodes.len() - 1
+    }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
+
+    /// Closest hit along `r` and the index of the object it is on, counting
+    /// in `cost` the boxes and objects tested.
+    fn hit_counting(&self, r: &Ray, t_min: f64, t_max: f64, cost: &mut usize) -> Option<(usize, HitRecord<'_>)> {
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+        for &i in &self.unbounded {
+            *cost += 1;
+            if let Some(rec) = self.objects[i].hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some((i, rec));
+            }
+        }
+        if let Some(root) = self.root {
+            // the tree is balanced, so its depth is far below the stack size
+            let mut stack = [0; 64];
+            stack[0] = root;
+            let mut len = 1;
+            while len > 0 {
+                len -= 1;
+                let node = &self.nodes[stack[len]];
+                *cost += 1;
+                if !node.bbox().hit(r, t_min, closest_so_far) {
+                    continue;
+                }
+                match node {
+                    Node::Leaf { object, .. } => {
+                        *cost += 1;
+                        if let Some(rec) = self.objects[*object].hit(r, t_min, closest_so_far) {
+                            closest_so_far = rec.t;
+                            closest = Some((*object, rec));
+                        }
+                    }
+                    Node::Interior { left, right, .. } => {
+                        stack[len] = *right;
+                        stack[len + 1] = *left;
+                        len += 2;
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    /// Closest hit along `r`, along with the index in `objects` of the
+    /// object it is on.
+    pub fn hit_object(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(usize, HitRecord<'_>)> {
+        self.hit_counting(r, t_min, t_max, &mut 0)
+    }
+
+    /// Number of bounding boxes and objects a ray is tested against before
+    /// its closest hit is known.
+    pub fn traversal_cost(&self, r: &Ray, t_min: f64, t_max: f64) -> usize {
+        let mut cost = 0;
+        self.hit_counting(r, t_min, t_max, &mut cost);
+        cost
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.hit_object(r, t_min, t_max).map(|(_, rec)| rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.root.map(|root| *self.nodes[root].bbox())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::{random_double, random_double_range, INFINITY};
+    use vec3::{v3, Vec3};
+
+    use crate::{
+        hittable::{Hittable, HittableList},
+        material::Lambertian,
+        plane::Plane,
+        quad::Quad,
+        ray::Ray,
+        sphere::Sphere,
+        triangle::Triangle,
+    };
+
+    use super::Bvh;
+
+    /// Spheres, quads and triangles strewn about, above an infinite floor.
+    fn random_list() -> HittableList {
+        let material = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        list.add(Arc::new(Plane::new(v3!(0., -10., 0.), v3!(0.1, 1., 0.), material.clone())));
+        for i in 0..300 {
+            let p = Vec3::random_range(-10., 10.);
+            match i % 3 {
+                0 => list.add(Arc::new(Sphere::new(p, random_double_range(0.1, 2.), material.clone()))),
+                1 => list.add(Arc::new(Quad::new(p, Vec3::random_range(-2., 2.), Vec3::random_range(-2., 2.), material.clone()))),
+                _ => list.add(Arc::new(Triangle::new(p, p + Vec3::random_range(-2., 2.), p + Vec3::random_range(-2., 2.), material.clone()))),
+            }
+        }
+        list
+    }
+
+    /// Closest hit along `r` and the index of its object, one by one.
+    fn linear_hit(list: &HittableList, r: &Ray) -> Option<(usize, f64)> {
+        let mut closest = None;
+        let mut closest_so_far = INFINITY;
+        for (i, object) in list.objects().iter().enumerate() {
+            if let Some(rec) = object.hit(r, 0.001, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some((i, rec.t));
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let list = random_list();
+        let objects = list.objects().to_vec();
+        let bvh = Bvh::new(list);
+        let mut list = HittableList::new();
+        for object in objects {
+            list.add(object);
+        }
+        let mut hits = 0;
+        for _ in 0..5000 {
+            // from inside the scene as well as from far outside it
+            let origin = Vec3::random_range(-15., 15.) * if random_double() < 0.5 { 1. } else { 4. };
+            let r = Ray::new(origin, Vec3::random_range(-1., 1.));
+            let expected = linear_hit(&list, &r);
+            let found = bvh.hit_object(&r, 0.001, INFINITY).map(|(i, rec)| (i, rec.t));
+            assert_eq!(found, expected);
+            assert_eq!(bvh.hit(&r, 0.001, INFINITY).map(|rec| rec.t), list.hit(&r, 0.001, INFINITY).map(|rec| rec.t));
+            hits += expected.is_some() as usize;
+        }
+        // rays that miss everything but the floor tell nothing
+        assert!(hits > 2000);
+    }
+
+    #[test]
+    fn test_traversal_cost() {
+        let material = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        list.add(Arc::new(Sphere::new(v3!(0., 0., 0.), 1., material.clone())));
+        let bvh = Bvh::new(list);
+        // the root box, then the sphere in it
+        assert_eq!(bvh.traversal_cost(&Ray::new(v3!(0., 0., 5.), v3!(0., 0., -1.)), 0.001, INFINITY), 2);
+        assert_eq!(bvh.traversal_cost(&Ray::new(v3!(0., 5., 5.), v3!(0., 0., -1.)), 0.001, INFINITY), 1);
+
+        let mut list = random_list();
+        list.add(Arc::new(Plane::new(v3!(0., 20., 0.), v3!(0., 1., 0.), material)));
+        let n = list.objects().len();
+        let bvh = Bvh::new(list);
+        // the two planes are always tested, and far less than everything else
+        let r = Ray::new(v3!(0., 0., 100.), v3!(0., 1., 0.));
+        assert_eq!(bvh.traversal_cost(&r, 0.001, INFINITY), 3);
+        let mut total = 0;
+        for _ in 0..1000 {
+            let r = Ray::new(Vec3::random_range(-15., 15.), Vec3::random_range(-1., 1.));
+            total += bvh.traversal_cost(&r, 0.001, INFINITY);
+        }
+        assert!(total / 1000 < n / 4, "{} of {}", total / 1000, n);
+    }
+}
@@ -1,5 +1,5 @@
 use utils::degrees_to_radians;
-use vec3::{random_in_unit_disk, random_in_unit_sphere, v3, Point3, Vec3};
+use vec3::{random_in_unit_disk, Point3, Vec3};
 
 use crate::ray::Ray;
 
@@ -10,6 +10,7 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
+    #[allow(dead_code)]
     w: Vec3,
     lens_radius: f64,
 }
@@ -0,0 +1,216 @@
+//! Constructive solid geometry: closed shapes combined by boolean operations
+//! on the stretches of each ray inside them.
+
+use std::sync::Arc;
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable, Span}, ray::Ray};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    /// the first shape with the second cut out of it
+    Difference,
+}
+
+impl Operation {
+    fn inside(&self, a: bool, b: bool) -> bool {
+        match self {
+            Operation::Union => a || b,
+            Operation::Intersection => a && b,
+            Operation::Difference => a && !b,
+        }
+    }
+}
+
+/// Two closed shapes, those that say `is_closed`, combined. Surfaces
+/// keep the material of the shape they come from, so the walls of a cut
+/// are made of the shape cut away.
+pub struct Csg {
+    operation: Operation,
+    a: Arc<dyn Hittable>,
+    b: Arc<dyn Hittable>,
+}
+
+impl Csg {
+    /// Panics unless both shapes are closed: open ones have no inside to
+    /// combine, so they would never be hit.
+    pub fn new(operation: Operation, a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Self {
+        assert!(a.is_closed() && b.is_closed(), "csg needs closed shapes");
+        Self { operation, a, b }
+    }
+
+    pub fn union(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Self {
+        Self::new(Operation::Union, a, b)
+    }
+
+    pub fn intersection(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Self {
+        Self::new(Operation::Intersection, a, b)
+    }
+
+    pub fn difference(a: Arc<dyn Hittable>, b: Arc<dyn Hittable>) -> Self {
+        Self::new(Operation::Difference, a, b)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.spans(r, t_min, t_max)?
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|rec| (t_min..=t_max).contains(&rec.t))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (a, b) = (self.a.bounding_box()?, self.b.bounding_box()?);
+        Some(match self.operation {
+            Operation::Union => a.surrounding(&b),
+            Operation::Intersection => a.intersection(&b),
+            Operation::Difference => a,
+        })
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        // nothing of the result lies past its box, which is also cheaper to
+        // miss than both shapes
+        let t_max = match self.bounding_box() {
+            Some(bounds) => match bounds.clip(r, t_min, t_max) {
+                Some((_, t_max)) => t_max,
+                None => return Some(vec![]),
+            },
+            None => t_max,
+        };
+        // every boundary of either shape along the line, in order, marked
+        // with which shape it belongs to and whether it goes in
+        let mut events: Vec<(bool, HitRecord)> = vec![];
+        for (is_a, spans) in [(true, self.a.spans(r, t_min, t_max)?), (false, self.b.spans(r, t_min, t_max)?)] {
+            for span in spans {
+                events.push((is_a, span.enter));
+                events.push((is_a, span.exit));
+            }
+        }
+        events.sort_by(|x, y| x.1.t.total_cmp(&y.1.t));
+
+        let (mut in_a, mut in_b) = (false, false);
+        let mut enter = None;
+        let mut spans = vec![];
+        for (is_a, mut rec) in events {
+            let before = self.operation.inside(in_a, in_b);
+            if is_a {
+                in_a = rec.front_face;
+            } else {
+                in_b = rec.front_face;
+            }
+            let after = self.operation.inside(in_a, in_b);
+            if before == after {
+                continue;
+            }
+            // the normal already faces the ray, only which side it is on
+            // changes, as where the ray leaves the cutter it enters the result
+            rec.front_face = after;
+            if after {
+                enter = Some(rec);
+            } else if let Some(enter) = enter.take() {
+                // surfaces the shapes share leave nothing between them
+                if rec.t > enter.t {
+                    spans.push(Span { enter, exit: rec });
+                }
+            }
+        }
+        Some(spans)
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::v3;
+
+    use crate::{aabb::Aabb, hittable::Hittable, material::Lambertian, quad::Quad, quadric::Cylinder, ray::Ray, sphere::Sphere};
+
+    use super::Csg;
+
+    #[test]
+    fn test_csg() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let left = Arc::new(Sphere::new(v3!(-0.5, 0., 0.), 1., m.clone()));
+        let right = Arc::new(Sphere::new(v3!(0.5, 0., 0.), 1., m.clone()));
+        let along = Ray::new(v3!(-5., 0., 0.), v3!(1., 0., 0.));
+        let boundaries = |csg: &Csg, r: &Ray| -> Vec<(f64, bool)> {
+            csg.spans(r, -f64::INFINITY, f64::INFINITY).unwrap().into_iter().flat_map(|s| [s.enter, s.exit]).map(|rec| (rec.t, rec.front_face)).collect()
+        };
+
+        // the union has no wall where the spheres overlap
+        let union = Csg::union(left.clone(), right.clone());
+        assert_eq!(boundaries(&union, &along), vec![(3.5, true), (6.5, false)]);
+        // the lens between them
+        let lens = Csg::intersection(left.clone(), right.clone());
+        assert_eq!(boundaries(&lens, &along), vec![(4.5, true), (5.5, false)]);
+        // the crescent left over, entered again where the ray leaves the cut
+        let hollow = Csg::difference(left.clone(), Arc::new(Sphere::new(v3!(-0.5, 0., 0.), 0.5, m)));
+        assert_eq!(boundaries(&hollow, &along), vec![(3.5, true), (4., false), (5., true), (5.5, false)]);
+        let rec = hollow.hit(&along, 4.5, f64::INFINITY).unwrap();
+        assert!(rec.t == 5. && rec.front_face && (rec.normal - v3!(-1., 0., 0.)).length() < 1e-12);
+
+        // only what reaches into the range is looked for
+        assert_eq!(union.spans(&along, 6., 7.).unwrap().len(), 1);
+        assert!(union.spans(&along, 7., f64::INFINITY).unwrap().is_empty());
+        assert!(union.spans(&along, -1., 3.).unwrap().is_empty());
+
+        // from inside the lens the first thing met is its far side
+        let inside = Ray::new(v3!(0., 0., 0.), v3!(1., 0., 0.));
+        let rec = lens.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 0.5).abs() < 1e-12 && !rec.front_face);
+        // and combinations combine
+        let nested = Csg::difference(Arc::new(union), Arc::new(lens));
+        assert_eq!(boundaries(&nested, &along), vec![(3.5, true), (4.5, false), (5.5, true), (6.5, false)]);
+        assert!(nested.hit(&Ray::new(v3!(0., 5., 0.), v3!(0., -1., 0.)), 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_walked_shapes() {
+        // a capped cylinder is found by walking from hit to hit, starting
+        // inside it when the ray does
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let rod = Arc::new(Cylinder::new(v3!(-1., 0., 0.), v3!(1., 0., 0.), 0.5, true, m.clone()));
+        let capsule = Csg::intersection(rod, Arc::new(Sphere::new(v3!(0., 0., 0.), 0.75, m)));
+        let inside = Ray::new(v3!(0., 0., 0.), v3!(1., 0., 0.));
+        let rec = capsule.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 0.75).abs() < 1e-9 && !rec.front_face, "{}", rec.t);
+        let from_behind = Ray::new(v3!(-5., 0.1, 0.), v3!(1., 0., 0.));
+        let rec = capsule.hit(&from_behind, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - (5. - 0.5525_f64.sqrt())).abs() < 1e-9 && rec.front_face, "{}", rec.t);
+        let rec = capsule.hit(&from_behind, rec.t + 1e-3, f64::INFINITY).unwrap();
+        assert!((rec.t - (5. + 0.5525_f64.sqrt())).abs() < 1e-9 && !rec.front_face, "{}", rec.t);
+    }
+
+    #[test]
+    fn test_closed_shapes_only() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let ball: Arc<dyn Hittable> = Arc::new(Sphere::new(v3!(0., 0., 0.), 1., m.clone()));
+        let can: Arc<dyn Hittable> = Arc::new(Cylinder::new(v3!(0., -2., 0.), v3!(0., 2., 0.), 0.5, true, m.clone()));
+        let tube: Arc<dyn Hittable> = Arc::new(Cylinder::new(v3!(0., -2., 0.), v3!(0., 2., 0.), 0.5, false, m.clone()));
+        let card: Arc<dyn Hittable> = Arc::new(Quad::new(v3!(0., 0., 0.), v3!(1., 0., 0.), v3!(0., 1., 0.), m));
+        assert!(ball.is_closed() && can.is_closed() && !tube.is_closed() && !card.is_closed());
+        let csg = Csg::difference(ball.clone(), can.clone());
+        assert!(csg.is_closed());
+        // the box of an intersection is what the boxes share
+        let b = Csg::intersection(ball.clone(), can).bounding_box().unwrap();
+        assert!((b.min - v3!(-0.5, -1., -0.5)).length() < 1e-9 && (b.max - v3!(0.5, 1., 0.5)).length() < 1e-9);
+        let apart = Aabb::new(v3!(0., 0., 0.), v3!(1., 1., 1.)).intersection(&Aabb::new(v3!(2., 2., 2.), v3!(3., 3., 3.)));
+        assert_eq!(apart.min, apart.max);
+    }
+
+    #[test]
+    #[should_panic(expected = "csg needs closed shapes")]
+    fn test_open_shapes_are_refused() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let ball = Arc::new(Sphere::new(v3!(0., 0., 0.), 1., m.clone()));
+        Csg::union(ball, Arc::new(Quad::new(v3!(0., 0., 0.), v3!(1., 0., 0.), v3!(0., 1., 0.), m)));
+    }
+}
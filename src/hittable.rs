@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
-use vec3::{Point3, Vec3};
+use utils::{random_double, INFINITY};
+use vec3::{v3, Point3, Vec3};
 
-use crate::{ray::Ray, material::Material};
+use crate::{aabb::Aabb, ray::Ray, material::Material};
 
 #[derive(Clone)]
 pub struct HitRecord<'a> {
@@ -10,6 +11,12 @@ pub struct HitRecord<'a> {
     pub normal: Vec3,
     pub material: &'a dyn Material,
     pub t: f64,
+    /// surface coordinates of the hit point
+    pub u: f64,
+    pub v: f64,
+    /// direction in which `u` grows along the surface, zero where the shape
+    /// has none
+    pub dpdu: Vec3,
     pub front_face: bool,
 }
 
@@ -26,21 +33,115 @@ impl<'a> HitRecord<'a> {
             normal,
             material,
             t,
+            u: 0.,
+            v: 0.,
+            dpdu: v3!(0., 0., 0.),
             front_face,
         }
     }
+
+    pub fn with_uv(mut self, u: f64, v: f64) -> Self {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    pub fn with_tangent(mut self, dpdu: Vec3) -> Self {
+        self.dpdu = dpdu;
+        self
+    }
+}
+
+/// Where a ray goes into a closed object and where it next comes out.
+#[derive(Clone)]
+pub struct Span<'a> {
+    pub enter: HitRecord<'a>,
+    pub exit: HitRecord<'a>,
 }
 
-pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+/// Spans of a closed object reaching into `t_min..t_max`, found by following
+/// `r` from hit to hit and pairing each entry with the next exit, for shapes
+/// without a quicker way. A ray already inside at `t_min` never sees where
+/// it went in, so the exit stands in for the entry, at minus infinity.
+pub fn walk_spans<'a>(object: &'a dyn Hittable, r: &Ray, t_min: f64, t_max: f64) -> Vec<Span<'a>> {
+    let mut spans = vec![];
+    let mut enter = None;
+    let mut t = t_min;
+    // enough for anything that isn't trapping the ray in a crack
+    for i in 0..64 {
+        let Some(rec) = object.hit(r, t, INFINITY) else {
+            break;
+        };
+        if rec.front_face && rec.t > t_max {
+            break;
+        }
+        t = rec.t + 1e-7 * (1. + rec.t.abs());
+        if rec.front_face {
+            enter = Some(rec);
+        } else if let Some(enter) = enter.take() {
+            spans.push(Span { enter, exit: rec });
+        } else if i == 0 {
+            let enter = HitRecord { t: -INFINITY, front_face: true, ..rec.clone() };
+            spans.push(Span { enter, exit: rec });
+        }
+    }
+    spans
+}
+
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    /// Box enclosing the object, `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Solid angle density of [`Hittable::random`] returning `direction` when
+    /// sampled from `origin`.
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.
+    }
+
+    /// Direction from `origin` towards a random point on the object.
+    fn random(&self, _origin: &Point3) -> Vec3 {
+        v3!(1., 0., 0.)
+    }
+
+    /// Surface area, used to pick lights in proportion to their size.
+    fn area(&self) -> f64 {
+        0.
+    }
+
+    /// Uniformly distributed point on the surface, as a hit record seen from
+    /// the outside so that `normal` points outwards.
+    fn sample_surface(&self) -> Option<HitRecord<'_>> {
+        None
+    }
+
+    /// Stretches of the line through `r` that lie inside the object, in
+    /// order, or `None` if it doesn't enclose a volume. Only those reaching
+    /// into `t_min..t_max` are needed, and only their ends within it need to
+    /// be right. Unlike `hit` this takes a negative `t_min`, so that it can
+    /// look behind the origin.
+    fn spans(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> Option<Vec<Span<'_>>> {
+        None
+    }
+
+    /// Whether the object encloses a volume, so that `spans` is never `None`.
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Whether the object should be put on the light list. Only objects that
+    /// implement `pdf_value`, `random`, `area` and `sample_surface` should
+    /// return true.
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
 
 pub struct HittableList {
     objects: Vec<Arc<dyn Hittable>>,
 }
-unsafe impl Send for HittableList {}
-unsafe impl Sync for HittableList {}
 
 impl HittableList {
     pub fn new() -> Self {
@@ -48,24 +149,66 @@ impl HittableList {
             objects: vec![],
         }
     }
+    #[allow(dead_code)]
     pub fn clear(&mut self) {
         self.objects.clear();
     }
     pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object);
     }
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
+
+    /// Collect the emissive objects so they can be sampled directly.
+    pub fn lights(&self) -> HittableList {
+        Self {
+            objects: self.objects.iter().filter(|o| o.is_emissive()).cloned().collect(),
+        }
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut temp_rec = None;
         let mut closest_so_far = t_max;
         for obj in &self.objects {
             if let Some(hit_rec) = obj.hit(r, t_min, closest_so_far) {
-                closest_so_far = hit_rec.t.clone();
+                closest_so_far = hit_rec.t;
                 temp_rec = Some(hit_rec);
             }
         }
         temp_rec
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.objects.iter();
+        let mut output_box = objects.next()?.bounding_box()?;
+        for obj in objects {
+            output_box = output_box.surrounding(&obj.bounding_box()?);
+        }
+        Some(output_box)
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self.objects.iter().map(|o| o.pdf_value(origin, direction)).sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let i = (random_double() * self.objects.len() as f64) as usize;
+        self.objects[i.min(self.objects.len() - 1)].random(origin)
+    }
 }
@@ -0,0 +1,501 @@
+//! Bidirectional path tracing, following Veach's thesis and pbrt-v3.
+//!
+//! A camera subpath and a light subpath are traced independently and every
+//! prefix of one is connected to every prefix of the other. Each resulting
+//! path is weighted by the balance heuristic over all strategies that could
+//! have produced it.
+//!
+//! Compared to pbrt two strategies are left out: light subpaths are never
+//! connected directly to the lens (t = 1), which would need splatting onto
+//! other pixels, and point, spot and directional lights are only reached by
+//! light sampling from the camera subpath. Both are simply excluded from the
+//! weights, so the estimate stays unbiased.
+//!
+//! Media inside objects, as of `Subsurface`, aren't walked: light crosses
+//! them as if they were clear, and a warning says so.
+
+use std::sync::Once;
+
+use utils::{random_double, PI};
+use vec3::{random_cosine_direction, v3, Color, Onb, Point3};
+
+use crate::{
+    hittable::{HitRecord, Hittable},
+    ray::Ray,
+    scene::Scene,
+};
+
+use super::{path::sample_delta_lights, Integrator};
+
+static MEDIA_WARNING: Once = Once::new();
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex<'a> {
+    kind: VertexKind,
+    p: Point3,
+    /// none for the camera vertex
+    rec: Option<HitRecord<'a>>,
+    /// the ray that reached this vertex
+    ray: Ray,
+    /// throughput from the start of the subpath up to this vertex
+    beta: Color,
+    /// area density of sampling this vertex from its predecessor on the
+    /// subpath
+    pdf_fwd: f64,
+    /// area density of sampling it from its successor, i.e. had the path been
+    /// traced from the other end
+    pdf_rev: f64,
+    /// scattering at this vertex is specular
+    delta: bool,
+}
+
+impl<'a> Vertex<'a> {
+    fn camera(ray: &Ray) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            p: *ray.origin(),
+            rec: None,
+            ray: *ray,
+            beta: v3!(1., 1., 1.),
+            pdf_fwd: 1.,
+            pdf_rev: 0.,
+            delta: false,
+        }
+    }
+
+    fn light(rec: HitRecord<'a>, pdf_fwd: f64) -> Self {
+        let n = rec.normal;
+        Self {
+            kind: VertexKind::Light,
+            p: rec.p,
+            ray: Ray::new(rec.p + n, -n),
+            rec: Some(rec),
+            beta: v3!(0., 0., 0.),
+            pdf_fwd,
+            pdf_rev: 0.,
+            delta: false,
+        }
+    }
+
+    fn surface(rec: HitRecord<'a>, ray: Ray, beta: Color) -> Self {
+        Self {
+            kind: VertexKind::Surface,
+            p: rec.p,
+            rec: Some(rec),
+            ray,
+            beta,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            delta: false,
+        }
+    }
+
+    /// Hit record with the normal facing `r_in`, for asking the material
+    /// about light arriving from another direction than `self.ray`.
+    fn rec_towards(&self, r_in: &Ray) -> HitRecord<'a> {
+        let rec = self.rec.as_ref().unwrap();
+        let outward = if rec.front_face {rec.normal} else {-rec.normal};
+        HitRecord::new(rec.p, rec.t, outward, *r_in, rec.material).with_uv(rec.u, rec.v).with_tangent(rec.dpdu)
+    }
+
+    /// Turn a solid angle density of sampling `next` from here into an area
+    /// density at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0. {
+            return 0.;
+        }
+        let mut pdf = pdf / distance_squared;
+        if let Some(rec) = &next.rec {
+            pdf *= rec.normal.dot(&w).abs() / distance_squared.sqrt();
+        }
+        pdf
+    }
+
+    /// BSDF times cosine for light arriving from `next` and leaving towards
+    /// `prev`, as on camera subpaths.
+    fn f(&self, prev: &Point3, next: &Point3) -> Color {
+        let r_in = Ray::new(*prev, self.p - prev);
+        let rec = self.rec_towards(&r_in);
+        rec.material.eval(&r_in, &rec, &(next - self.p))
+    }
+
+    /// The same for light arriving from `prev` and leaving towards `next`, as
+    /// on light subpaths. Refraction isn't symmetric: the BSDF of the way back
+    /// differs by the η² that radiance is squeezed by, so it is the one
+    /// evaluated, with the cosine moved over to `next`.
+    fn f_light(&self, prev: &Point3, next: &Point3) -> Color {
+        let n = self.rec.as_ref().unwrap().normal;
+        let cosine = |q: &Point3| n.dot(&(q - self.p).unit_vector()).abs();
+        let cos_prev = cosine(prev);
+        if cos_prev == 0. {
+            return v3!(0., 0., 0.);
+        }
+        self.f(next, prev) * (cosine(next) / cos_prev)
+    }
+
+    /// Area density at `next` of scattering towards it after arriving from
+    /// `prev`. Light vertices ignore `prev`.
+    fn pdf(&self, prev: &Point3, next: &Vertex) -> f64 {
+        match self.kind {
+            VertexKind::Light => self.pdf_light(next),
+            VertexKind::Surface => {
+                let r_in = Ray::new(*prev, self.p - prev);
+                let rec = self.rec_towards(&r_in);
+                let pdf = rec.material.scattering_pdf(&r_in, &rec, &(next.p - self.p));
+                self.convert_density(pdf, next)
+            }
+            // the strategies that would need it are not used
+            VertexKind::Camera => 0.,
+        }
+    }
+
+    /// Area density at `next` of this emitter sending light towards it.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let rec = self.rec.as_ref().unwrap();
+        let outward = if rec.front_face {rec.normal} else {-rec.normal};
+        let cosine = outward.dot(&(next.p - self.p).unit_vector());
+        if cosine <= 0. {
+            return 0.;
+        }
+        self.convert_density(cosine / PI, next)
+    }
+}
+
+/// Bidirectional path tracer.
+pub struct Bdpt {
+    /// bounces a subpath always survives before russian roulette may end it
+    rr_depth: u32,
+    /// running sum of the areas of `scene.lights`, to pick lights by size
+    light_cdf: Vec<f64>,
+    /// every point on every light is picked with density `1 / light_area`
+    light_area: f64,
+}
+
+impl Bdpt {
+    pub fn new(scene: &Scene, rr_depth: u32) -> Self {
+        let mut light_cdf = Vec::new();
+        let mut light_area = 0.;
+        for light in scene.lights.objects() {
+            light_area += light.area();
+            light_cdf.push(light_area);
+        }
+        Self {
+            rr_depth,
+            light_cdf,
+            light_area,
+        }
+    }
+
+    fn pdf_light_origin(&self) -> f64 {
+        1. / self.light_area
+    }
+
+    /// Extend `path` by following `ray` until it escapes, is absorbed or is
+    /// ended by russian roulette. Returns the background seen on escape.
+    fn random_walk<'a>(&self, scene: &'a Scene, mut ray: Ray, mut beta: Color, mut pdf_dir: f64, path: &mut Vec<Vertex<'a>>) -> Color {
+        let mut bounces = 0;
+        loop {
+            let rec = match scene.world.hit(&ray, 0.001, utils::INFINITY) {
+                Some(rec) => rec,
+                None => return beta * scene.background.color(&ray),
+            };
+            let mut vertex = Vertex::surface(rec, ray, beta);
+            vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_dir, &vertex);
+
+            let rec = vertex.rec.as_ref().unwrap();
+            let srec = match rec.material.scatter(&ray, rec) {
+                Some(srec) => srec,
+                None => {
+                    path.push(vertex);
+                    return v3!(0., 0., 0.);
+                }
+            };
+            if rec.material.medium().is_some() {
+                MEDIA_WARNING.call_once(|| eprintln!("bdpt renders the media inside objects as clear; use path or spectral for them"));
+            }
+            let wo = *srec.scattered.direction();
+            let pdf_rev = if srec.is_specular {
+                pdf_dir = 0.;
+                0.
+            } else {
+                pdf_dir = rec.material.scattering_pdf(&ray, rec, &wo);
+                let reversed = Ray::new(rec.p + wo, -wo);
+                let rec_rev = vertex.rec_towards(&reversed);
+                rec.material.scattering_pdf(&reversed, &rec_rev, &-ray.direction())
+            };
+            vertex.delta = srec.is_specular;
+            let mut attenuation = srec.attenuation;
+            if !srec.is_specular && path[0].kind == VertexKind::Light {
+                // the material weighs light going the camera's way; specular
+                // refraction is left alone, its η² cancels on the way out
+                let prev = *ray.origin();
+                let next = vertex.p + wo;
+                let (f, f_light) = (vertex.f(&prev, &next), vertex.f_light(&prev, &next));
+                let ratio = |f: f64, f_light: f64| if f > 0. {f_light / f} else {1.};
+                attenuation = attenuation * v3!(ratio(f.x(), f_light.x()), ratio(f.y(), f_light.y()), ratio(f.z(), f_light.z()));
+            }
+            let prev = path.last_mut().unwrap();
+            prev.pdf_rev = vertex.convert_density(pdf_rev, prev);
+            path.push(vertex);
+
+            beta = beta * attenuation;
+            ray = srec.scattered;
+            bounces += 1;
+            if bounces >= self.rr_depth {
+                let survive = beta.max_component().min(0.95);
+                if random_double() >= survive {
+                    return v3!(0., 0., 0.);
+                }
+                beta = beta / survive;
+            }
+        }
+    }
+
+    /// Point on a light, picked with density `pdf_light_origin` over the
+    /// total light area. Both light subpaths and light sampling start here,
+    /// so that the weights see the density each strategy really uses.
+    fn sample_light_point<'a>(&self, scene: &'a Scene) -> Option<HitRecord<'a>> {
+        if self.light_area <= 0. {
+            return None;
+        }
+        let target = random_double() * self.light_area;
+        let i = self.light_cdf.partition_point(|&c| c <= target).min(self.light_cdf.len() - 1);
+        scene.lights.objects()[i].sample_surface()
+    }
+
+    fn light_subpath<'a>(&self, scene: &'a Scene, path: &mut Vec<Vertex<'a>>) {
+        let rec = match self.sample_light_point(scene) {
+            Some(rec) => rec,
+            None => return,
+        };
+        let le = rec.material.emitted(&Ray::new(rec.p + rec.normal, -rec.normal), &rec);
+        if le.near_zero() {
+            return;
+        }
+        let direction = Onb::build_from_w(&rec.normal).local(&random_cosine_direction());
+        let pdf_dir = rec.normal.dot(&direction) / PI;
+        let ray = Ray::new(rec.p, direction);
+        path.push(Vertex::light(rec, self.pdf_light_origin()));
+        // Le * cos / (pdf_origin * pdf_dir)
+        let beta = le * PI * self.light_area;
+        self.random_walk(scene, ray, beta, pdf_dir, path);
+    }
+
+    /// Unweighted contribution of the path made of the first `s` light and
+    /// `t` camera vertices, along with the light vertex sampled for `s == 1`.
+    fn connect<'a>(&self, scene: &'a Scene, light_path: &[Vertex<'a>], camera_path: &[Vertex<'a>], s: usize, t: usize) -> Option<(Color, Option<Vertex<'a>>)> {
+        let pt = &camera_path[t - 1];
+        let pt_rec = pt.rec.as_ref().unwrap();
+        match s {
+            0 => {
+                let le = pt_rec.material.emitted(&pt.ray, pt_rec);
+                if le.near_zero() {
+                    return None;
+                }
+                Some((pt.beta * le, None))
+            }
+            1 => {
+                if pt.delta {
+                    return None;
+                }
+                let light_rec = self.sample_light_point(scene)?;
+                let d = light_rec.p - pt.p;
+                let distance = d.length();
+                let direction = d / distance;
+                let cosine = light_rec.normal.dot(&-direction);
+                if cosine <= 0. {
+                    return None;
+                }
+                let f = pt_rec.material.eval(&pt.ray, pt_rec, &direction);
+                if f.near_zero() {
+                    return None;
+                }
+                let shadow_ray = Ray::new(pt.p, direction);
+                let le = light_rec.material.emitted(&shadow_ray, &light_rec);
+                if le.near_zero() || scene.world.hit(&shadow_ray, 0.001, distance - 0.001).is_some() {
+                    return None;
+                }
+                let sampled = Vertex::light(light_rec, self.pdf_light_origin());
+                // the area density turned into one over solid angle at `pt`
+                let light_pdf = self.pdf_light_origin() * distance * distance / cosine;
+                Some((pt.beta * f * le / light_pdf, Some(sampled)))
+            }
+            _ => {
+                let qs = &light_path[s - 1];
+                if qs.delta || pt.delta {
+                    return None;
+                }
+                let f = pt.f(&camera_path[t - 2].p, &qs.p) * qs.f_light(&light_path[s - 2].p, &pt.p);
+                if f.near_zero() {
+                    return None;
+                }
+                let d = qs.p - pt.p;
+                let distance = d.length();
+                if scene.world.hit(&Ray::new(pt.p, d / distance), 0.001, distance - 0.001).is_some() {
+                    return None;
+                }
+                Some((pt.beta * f * qs.beta / (distance * distance), None))
+            }
+        }
+    }
+
+    /// Balance heuristic weight of strategy (`s`, `t`) against all others
+    /// that could have sampled the same path.
+    fn mis_weight(&self, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+        if s + t == 2 {
+            return 1.;
+        }
+        // (pdf_fwd, pdf_rev, delta) of the vertices, with the values around
+        // the connection replaced by those of the full path
+        let mut camera: Vec<(f64, f64, bool)> = camera_path[..t].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        let mut light: Vec<(f64, f64, bool)> = match sampled {
+            Some(v) => vec![(v.pdf_fwd, v.pdf_rev, v.delta)],
+            None => light_path[..s].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect(),
+        };
+        let qs = if s == 1 { sampled } else if s > 1 { Some(&light_path[s - 1]) } else { None };
+        let pt = &camera_path[t - 1];
+        let pt_minus = &camera_path[t - 2];
+
+        camera[t - 1].2 = false;
+        match qs {
+            Some(qs) => {
+                light[s - 1].2 = false;
+                camera[t - 1].1 = if s > 1 { qs.pdf(&light_path[s - 2].p, pt) } else { qs.pdf_light(pt) };
+                camera[t - 2].1 = pt.pdf(&qs.p, pt_minus);
+                light[s - 1].1 = pt.pdf(&pt_minus.p, qs);
+                if s > 1 {
+                    light[s - 2].1 = qs.pdf(&pt.p, &light_path[s - 2]);
+                }
+            }
+            None => {
+                // `pt` is on a light that was hit by the camera subpath
+                camera[t - 1].1 = self.pdf_light_origin();
+                camera[t - 2].1 = pt.pdf_light(pt_minus);
+            }
+        }
+
+        let remap0 = |pdf: f64| if pdf != 0. {pdf} else {1.};
+        let mut sum_ri = 0.;
+        // strategies with fewer camera vertices, stopping before t = 1
+        let mut ri = 1.;
+        for i in (2..t).rev() {
+            ri *= remap0(camera[i].1) / remap0(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum_ri += ri;
+            }
+        }
+        // strategies with fewer light vertices, down to hitting the light
+        let mut ri = 1.;
+        for i in (0..s).rev() {
+            ri *= remap0(light[i].1) / remap0(light[i].0);
+            let delta_prev = i > 0 && light[i - 1].2;
+            if !light[i].2 && !delta_prev {
+                sum_ri += ri;
+            }
+        }
+        1. / (1. + sum_ri)
+    }
+}
+
+impl Integrator for Bdpt {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let mut camera_path = vec![Vertex::camera(ray)];
+        let mut color = self.random_walk(scene, *ray, v3!(1., 1., 1.), 1., &mut camera_path);
+        let mut light_path = Vec::new();
+        self.light_subpath(scene, &mut light_path);
+
+        for t in 2..=camera_path.len() {
+            let pt = &camera_path[t - 1];
+            if !pt.delta {
+                color = color + pt.beta * sample_delta_lights(&pt.ray, pt.rec.as_ref().unwrap(), scene, None);
+            }
+            for s in 0..=light_path.len().max(1) {
+                if let Some((l, sampled)) = self.connect(scene, &light_path, &camera_path, s, t) {
+                    // emitters off the light list can only be hit by the camera subpath
+                    let weight = if s == 0 && !scene.is_light(&pt.ray, pt.rec.as_ref().unwrap()) {
+                        1.
+                    } else {
+                        self.mis_weight(&light_path, &camera_path, sampled.as_ref(), s, t)
+                    };
+                    color = color + l * weight;
+                }
+            }
+        }
+        color
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::random_double;
+    use vec3::{v3, Color, Point3};
+
+    use crate::{
+        hittable::HitRecord,
+        integrator::{Integrator, PathTracer},
+        material::{Lambertian, Material, RoughDielectric},
+        ray::Ray,
+        scene::Scene,
+    };
+
+    use super::{Bdpt, Vertex};
+
+    /// Average unclamped radiance over the lower three quarters of a small
+    /// image, which leaves out the directly visible light.
+    fn mean_radiance(integrator: &dyn Integrator, scene: &Scene, size: usize, nsamples: usize) -> Color {
+        let mut color = v3!(0., 0., 0.);
+        for j in 0..size {
+            for i in 0..size {
+                for _ in 0..nsamples {
+                    let u = (i as f64 + random_double()) / size as f64;
+                    let v = 0.75 * (j as f64 + random_double()) / size as f64;
+                    color = color + integrator.li(&scene.camera.get_ray(u, v), scene);
+                }
+            }
+        }
+        color / (size * size * nsamples) as f64
+    }
+
+    #[test]
+    fn test_bdpt_matches_path_tracer() {
+        let scene = Scene::by_name("cornell", 1.).unwrap();
+        let path = mean_radiance(&PathTracer { rr_depth: 5 }, &scene, 16, 128);
+        let bdpt = mean_radiance(&Bdpt::new(&scene, 5), &scene, 16, 128);
+        for (a, b) in [(path.0, bdpt.0), (path.1, bdpt.1), (path.2, bdpt.2)] {
+            assert!((a - b).abs() < 0.05 * a, "path tracer {:?}, bdpt {:?}", path, bdpt);
+        }
+    }
+
+    #[test]
+    fn test_light_subpaths_see_the_adjoint_bsdf() {
+        let (above, below) = (v3!(0.3, 1., 0.2), v3!(-0.5, -1., 0.4));
+        let glass = RoughDielectric::new(1.5, 0.5);
+        let lambertian = Lambertian::new(&v3!(0.5, 0.5, 0.5));
+        fn vertex(material: &dyn Material, p: Point3) -> Vertex<'_> {
+            let ray = Ray::new(p, -p);
+            Vertex::surface(HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, material), ray, v3!(1., 1., 1.))
+        }
+        let (glass, lambertian) = (vertex(&glass, above), vertex(&lambertian, above));
+
+        // radiance refracted into the glass is squeezed by eta², and spread
+        // out again on the way out
+        for (prev, next, scale) in [(above, below, 1. / 2.25), (below, above, 2.25)] {
+            let (f, f_light) = (glass.f(&prev, &next), glass.f_light(&prev, &next));
+            assert!(f.x() > 0. && (f_light.x() / f.x() - scale).abs() < 1e-9, "{:?} {:?}", f, f_light);
+        }
+        let reflected = v3!(-0.4, 1., 0.1);
+        for vertex in [&glass, &lambertian] {
+            let (f, f_light) = (vertex.f(&above, &reflected), vertex.f_light(&above, &reflected));
+            assert!((f - f_light).length() < 1e-9 * f.length(), "{:?} {:?}", f, f_light);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Quick integrators for looking at a scene rather than rendering it. Apart
+//! from ambient occlusion they only look at the first hit of each camera ray.
+
+use utils::clamp;
+use vec3::{random_cosine_direction, v3, Color, Onb};
+
+use crate::{hittable::Hittable, ray::Ray, scene::Scene};
+
+use super::Integrator;
+
+/// traversal cost shown as the hottest colour of the heatmap
+const HEATMAP_MAX_COST: f64 = 100.;
+
+/// The render loop gamma corrects its output, so colours meant to be shown
+/// as they are need squaring first.
+fn display(c: Color) -> Color {
+    c * c
+}
+
+/// Fraction of the hemisphere above the first hit that is open within `radius`.
+pub struct AmbientOcclusion {
+    pub radius: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let rec = match scene.world.hit(ray, 0.001, utils::INFINITY) {
+            Some(rec) => rec,
+            None => return v3!(1., 1., 1.),
+        };
+        let direction = Onb::build_from_w(&rec.normal).local(&random_cosine_direction());
+        match scene.world.hit(&Ray::new(rec.p, direction), 0.001, self.radius) {
+            Some(_) => v3!(0., 0., 0.),
+            None => v3!(1., 1., 1.),
+        }
+    }
+}
+
+/// Surface normals, facing the camera, mapped from [-1, 1] to [0, 1].
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        match scene.world.hit(ray, 0.001, utils::INFINITY) {
+            Some(rec) => display((rec.normal + v3!(1., 1., 1.)) / 2.),
+            None => v3!(0., 0., 0.),
+        }
+    }
+}
+
+/// Distance to the first hit, white up close fading to black at `max_distance`.
+pub struct Depth {
+    pub max_distance: f64,
+}
+
+impl Integrator for Depth {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        match scene.world.hit(ray, 0.001, utils::INFINITY) {
+            Some(rec) => {
+                let distance = rec.t * ray.direction().length();
+                let grey = 1. - clamp(distance / self.max_distance, 0., 1.);
+                display(v3!(grey, grey, grey))
+            }
+            None => v3!(0., 0., 0.),
+        }
+    }
+}
+
+/// Surface coordinates as red and green.
+pub struct Uv;
+
+impl Integrator for Uv {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        match scene.world.hit(ray, 0.001, utils::INFINITY) {
+            Some(rec) => display(v3!(rec.u, rec.v, 0.)),
+            None => v3!(0., 0., 0.),
+        }
+    }
+}
+
+/// A colour made up for every material, so that shared materials stand out.
+pub struct MaterialId;
+
+impl Integrator for MaterialId {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let rec = match scene.world.hit(ray, 0.001, utils::INFINITY) {
+            Some(rec) => rec,
+            None => return v3!(0., 0., 0.),
+        };
+        // splitmix64 of the material's address
+        let mut h = rec.material as *const _ as *const () as u64;
+        h = h.wrapping_add(0x9e3779b97f4a7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+        let channel = |shift: u64| ((h >> shift) & 0xff) as f64 / 255.;
+        display(v3!(channel(0), channel(8), channel(16)))
+    }
+}
+
+/// Number of boxes and objects tested to find the first hit, from blue for
+/// none through green to red for `HEATMAP_MAX_COST` and above.
+pub struct BvhHeatmap;
+
+impl Integrator for BvhHeatmap {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let cost = scene.world.traversal_cost(ray, 0.001, utils::INFINITY) as f64;
+        let t = clamp(cost / HEATMAP_MAX_COST, 0., 1.);
+        let color = if t < 0.5 {
+            v3!(0., 2. * t, 1. - 2. * t)
+        } else {
+            v3!(2. * t - 1., 2. - 2. * t, 0.)
+        };
+        display(color)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::{v3, Color};
+
+    use crate::{
+        camera::Camera,
+        hittable::HittableList,
+        integrator::Integrator,
+        material::Lambertian,
+        plane::Plane,
+        quad::Quad,
+        ray::Ray,
+        scene::{Background, Scene},
+    };
+
+    use super::{display, AmbientOcclusion, BvhHeatmap, Depth, MaterialId, Normals, Uv};
+
+    /// A floor with a unit square tile lying on it and a ceiling 1 above.
+    fn room() -> Scene {
+        let mut world = HittableList::new();
+        let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor.clone())));
+        world.add(Arc::new(Plane::new(v3!(0., 1., 0.), v3!(0., -1., 0.), floor)));
+        let tile = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Quad::new(v3!(2., 1e-3, 0.), v3!(1., 0., 0.), v3!(0., 0., 1.), tile)));
+        let camera = Camera::new(v3!(0., 0.5, 0.), v3!(1., 0.5, 0.), v3!(0., 1., 0.), 90., 1., 0., 1.);
+        Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+    }
+
+    fn down_at(x: f64, z: f64) -> Ray {
+        Ray::new(v3!(x, 0.5, z), v3!(0., -1., 0.))
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let scene = room();
+        let mean = |radius: f64| {
+            let ao = AmbientOcclusion { radius };
+            let n = 20000;
+            (0..n).map(|_| ao.li(&down_at(0., 0.), &scene).y()).sum::<f64>() / n as f64
+        };
+        // the ceiling is 1 / cos away, which a cosine weighted direction
+        // finds within 2 when cos > 1/2, three quarters of the time
+        assert_eq!(mean(0.5), 1.);
+        assert_eq!(mean(1e6), 0.);
+        assert!((mean(2.) - 0.25).abs() < 0.02);
+        let ao = AmbientOcclusion { radius: 1. };
+        assert_eq!(ao.li(&Ray::new(v3!(0., 0.5, 0.), v3!(1., 0., 0.)), &scene), v3!(1., 1., 1.));
+    }
+
+    #[test]
+    fn test_first_hit_views() {
+        let scene = room();
+        let miss = Ray::new(v3!(0., 0.5, 0.), v3!(1., 0., 0.));
+        assert_eq!(Normals.li(&down_at(0., 0.), &scene), display(v3!(0.5, 1., 0.5)));
+        assert_eq!(Normals.li(&miss, &scene), v3!(0., 0., 0.));
+
+        let depth = Depth { max_distance: 2. };
+        assert_eq!(depth.li(&down_at(0., 0.), &scene), display(v3!(0.75, 0.75, 0.75)));
+        assert_eq!(Depth { max_distance: 0.1 }.li(&down_at(0., 0.), &scene), v3!(0., 0., 0.));
+
+        let uv = Uv.li(&down_at(2.25, 0.5), &scene);
+        assert!((uv - display(v3!(0.25, 0.5, 0.))).near_zero(), "{:?}", uv);
+
+        // the two planes share a material and the tile has its own
+        let (floor, ceiling) = (MaterialId.li(&down_at(0., 0.), &scene), MaterialId.li(&Ray::new(v3!(0., 0.5, 0.), v3!(0., 1., 0.)), &scene));
+        let tile = MaterialId.li(&down_at(2.5, 0.5), &scene);
+        assert_eq!(floor, ceiling);
+        assert_ne!(floor, tile);
+    }
+
+    #[test]
+    fn test_bvh_heatmap() {
+        let scene = room();
+        let cost = |r: &Ray| scene.world.traversal_cost(r, 0.001, utils::INFINITY);
+        // both planes and the tile's box, plus the tile where the ray meets it
+        assert_eq!(cost(&down_at(0., 0.)), 3);
+        assert_eq!(cost(&down_at(2.5, 0.5)), 4);
+        let heat = |cost: usize| {
+            let t = cost as f64 / 100.;
+            if t < 0.5 { v3!(0., 2. * t, 1. - 2. * t) } else { v3!(2. * t - 1., 2. - 2. * t, 0.) }
+        };
+        assert_eq!(BvhHeatmap.li(&down_at(2.5, 0.5), &scene), display(heat(4)));
+
+        let empty = Scene::new(HittableList::new(), Camera::new(v3!(0., 0., 0.), v3!(1., 0., 0.), v3!(0., 1., 0.), 90., 1., 0., 1.), Background::Sky);
+        let blue: Color = v3!(0., 0., 1.);
+        assert_eq!(BvhHeatmap.li(&down_at(0., 0.), &empty), blue);
+    }
+}
@@ -0,0 +1,229 @@
+//! Primary sample space Metropolis light transport, after Kelemen et al.,
+//! "A Simple and Robust Mutation Strategy for the Metropolis Light Transport
+//! Algorithm".
+//!
+//! A path traced image is a function of the random numbers drawn for each
+//! camera sample. Markov chains wander through those numbers, mostly with
+//! small perturbations that explore the neighbourhood of a bright path and
+//! sometimes with fresh numbers that jump anywhere, so that the time spent
+//! in each pixel is proportional to its brightness.
+
+use rayon::prelude::*;
+use utils::{random_double, replay_samples, Rng, SeedableRng, StdRng};
+use vec3::{v3, Color};
+
+use crate::{ray::Ray, scene::Scene};
+
+use super::{Integrator, PathTracer};
+
+/// paths traced to estimate the image brightness and seed the chains
+const BOOTSTRAP_SAMPLES: usize = 100_000;
+/// independent Markov chains the mutations are shared between
+const NCHAINS: usize = 1024;
+/// chance of proposing fresh samples instead of perturbing the current ones
+const LARGE_STEP_PROBABILITY: f64 = 0.3;
+/// smallest and largest size of a small step
+const S1: f64 = 1. / 1024.;
+const S2: f64 = 1. / 64.;
+
+/// A point of the chain: the samples a path was made from and where it landed.
+#[derive(Clone)]
+struct State {
+    samples: Vec<f64>,
+    pixel: usize,
+    radiance: Color,
+}
+
+impl State {
+    fn importance(&self) -> f64 {
+        self.radiance.luminance().max(0.)
+    }
+}
+
+pub struct Mlt {
+    path: PathTracer,
+    /// every random number of a render follows from this, so that the same
+    /// seed renders the same image
+    seed: u64,
+}
+
+impl Mlt {
+    pub fn new(rr_depth: u32, seed: u64) -> Self {
+        Self {
+            path: PathTracer { rr_depth },
+            seed,
+        }
+    }
+
+    /// Generator for the `stream`th independent part of a render.
+    fn rng(&self, stream: u64) -> StdRng {
+        StdRng::seed_from_u64(self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ stream)
+    }
+
+    /// Trace the camera sample made from `samples`, drawing fresh random
+    /// numbers from `rng` once they run out.
+    fn evaluate(&self, scene: &Scene, width: usize, height: usize, samples: Vec<f64>, rng: &mut StdRng) -> State {
+        let ((pixel, radiance), samples) = replay_samples(samples, rng, || {
+            let x = random_double() * width as f64;
+            let y = random_double() * height as f64;
+            // the same mapping to the image plane as `main` uses
+            let r = scene.camera.get_ray(x / (width as f64 - 1.), y / (height as f64 - 1.));
+            let row = height - 1 - (y as usize).min(height - 1);
+            let column = (x as usize).min(width - 1);
+            (row * width + column, self.path.li(&r, scene))
+        });
+        State { samples, pixel, radiance }
+    }
+
+    /// Move every sample by an exponentially distributed amount between
+    /// `S1` and `S2`, wrapping around the unit interval.
+    fn perturb(samples: &[f64], rng: &mut StdRng) -> Vec<f64> {
+        samples
+            .iter()
+            .map(|&u| {
+                let step = S2 * (-(S2 / S1).ln() * rng.gen::<f64>()).exp();
+                let u = if rng.gen::<f64>() < 0.5 { u + step } else { u - step };
+                u - u.floor()
+            })
+            .collect()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_chain(&self, scene: &Scene, width: usize, height: usize, mut current: State, mutations: usize, rng: &mut StdRng, image: &mut [Color]) {
+        for _ in 0..mutations {
+            let large_step = rng.gen::<f64>() < LARGE_STEP_PROBABILITY;
+            let samples = if large_step { Vec::new() } else { Self::perturb(&current.samples, rng) };
+            let proposed = self.evaluate(scene, width, height, samples, rng);
+
+            let accept = if current.importance() > 0. {
+                (proposed.importance() / current.importance()).min(1.)
+            } else {
+                1.
+            };
+            // record both states weighted by their expected share instead of
+            // only the one the chain moves to
+            if current.importance() > 0. {
+                image[current.pixel] = image[current.pixel] + (1. - accept) / current.importance() * current.radiance;
+            }
+            if proposed.importance() > 0. {
+                image[proposed.pixel] = image[proposed.pixel] + accept / proposed.importance() * proposed.radiance;
+            }
+            if rng.gen::<f64>() < accept {
+                current = proposed;
+            }
+        }
+    }
+}
+
+impl Integrator for Mlt {
+    /// Without the rest of the image to compare against, a single camera
+    /// ray is simply path traced.
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        self.path.li(ray, scene)
+    }
+
+    fn render(&self, scene: &Scene, width: usize, height: usize, nsamples: usize) -> Option<Vec<Color>> {
+        let npixels = width * height;
+
+        // the average brightness over primary sample space, and the paths
+        // that contribute to it to start the chains from
+        let bootstrap: Vec<State> = (0..BOOTSTRAP_SAMPLES)
+            .into_par_iter()
+            .map(|i| self.evaluate(scene, width, height, Vec::new(), &mut self.rng(i as u64)))
+            .filter(|state| state.importance() > 0.)
+            .collect();
+        let mut cdf = Vec::with_capacity(bootstrap.len());
+        let mut total = 0.;
+        for state in &bootstrap {
+            total += state.importance();
+            cdf.push(total);
+        }
+        if bootstrap.is_empty() {
+            return Some(vec![v3!(0., 0., 0.); npixels]);
+        }
+        let brightness = total / BOOTSTRAP_SAMPLES as f64;
+
+        // as many mutations as the path tracer would trace camera rays
+        let mutations = npixels * nsamples;
+        let nchains = NCHAINS.min(mutations);
+        let image = (0..nchains)
+            .into_par_iter()
+            .fold(
+                || vec![v3!(0., 0., 0.); npixels],
+                |mut image, chain| {
+                    let mut rng = self.rng((BOOTSTRAP_SAMPLES + chain) as u64);
+                    let target = rng.gen::<f64>() * total;
+                    let seed = cdf.partition_point(|&c| c < target).min(bootstrap.len() - 1);
+                    let start = bootstrap[seed].clone();
+                    let chain_mutations = mutations / nchains + usize::from(chain < mutations % nchains);
+                    self.run_chain(scene, width, height, start, chain_mutations, &mut rng, &mut image);
+                    image
+                },
+            )
+            .reduce(
+                || vec![v3!(0., 0., 0.); npixels],
+                |a, b| a.iter().zip(&b).map(|(a, b)| *a + *b).collect(),
+            );
+
+        // each chain visit stands for brightness / importance of a pixel's
+        // total over its `nsamples` samples
+        Some(image.into_iter().map(|c| brightness * c).collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::{random_double, replay_samples, SeedableRng, StdRng};
+    use vec3::{v3, Color};
+
+    use crate::{integrator::{Integrator, PathTracer}, scene::Scene};
+
+    use super::Mlt;
+
+    /// Average of the lower three quarters of `image`, which leaves out the
+    /// directly visible light of the Cornell box.
+    fn lower_mean(image: &[Color], size: usize, nsamples: usize) -> Color {
+        let lower = &image[size * size / 4..];
+        lower.iter().fold(v3!(0., 0., 0.), |a, b| a + *b) / (lower.len() * nsamples) as f64
+    }
+
+    #[test]
+    fn test_mlt_is_deterministic() {
+        let scene = Scene::by_name("cornell", 1.).unwrap();
+        let render = |seed| Mlt::new(5, seed).render(&scene, 8, 8, 16).unwrap();
+        let (first, again, other) = (render(1), render(1), render(2));
+        // equal up to the order rayon sums the chains' images in
+        for (a, b) in first.iter().zip(&again) {
+            assert!((*a - *b).length() <= 1e-9 * a.length(), "{:?} {:?}", a, b);
+        }
+        assert!(first.iter().zip(&other).any(|(a, b)| (*a - *b).length() > 1e-3 * a.length()));
+    }
+
+    #[test]
+    fn test_mlt_matches_path_tracer() {
+        // both estimates are seeded, so this compares the same two numbers
+        // on every run; over seeds 0 to 8 they are at most 9% apart
+        let scene = Scene::by_name("cornell", 1.).unwrap();
+        let (size, nsamples) = (16, 128);
+        let image = Mlt::new(5, 0).render(&scene, size, size, nsamples).unwrap();
+        let mlt = lower_mean(&image, size, nsamples);
+
+        let path = PathTracer { rr_depth: 5 };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut expected = vec![v3!(0., 0., 0.); size * size];
+        for _ in 0..size * size * nsamples {
+            let ((pixel, color), _) = replay_samples(vec![], &mut rng, || {
+                let x = random_double() * size as f64;
+                let y = random_double() * size as f64;
+                let r = scene.camera.get_ray(x / (size as f64 - 1.), y / (size as f64 - 1.));
+                let row = size - 1 - (y as usize).min(size - 1);
+                (row * size + (x as usize).min(size - 1), path.li(&r, &scene))
+            });
+            expected[pixel] = expected[pixel] + color;
+        }
+        let expected = lower_mean(&expected, size, nsamples);
+        for (a, b) in [(expected.0, mlt.0), (expected.1, mlt.1), (expected.2, mlt.2)] {
+            assert!((a - b).abs() < 0.1 * a, "path tracer {:?}, mlt {:?}", expected, mlt);
+        }
+    }
+}
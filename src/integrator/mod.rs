@@ -0,0 +1,68 @@
+//! Light transport algorithms. They all share the render loop in `main.rs`,
+//! which only asks them for the radiance arriving along each camera ray.
+
+use utils::{Rng, SeedableRng, StdRng};
+use vec3::Color;
+
+use crate::{aov::Aov, hittable::Hittable, options::Options, ray::Ray, scene::Scene};
+
+mod bdpt;
+mod debug;
+mod mlt;
+mod path;
+mod photon;
+mod spectral;
+
+pub use bdpt::Bdpt;
+pub use debug::{AmbientOcclusion, BvhHeatmap, Depth, MaterialId, Normals, Uv};
+pub use mlt::Mlt;
+pub use path::PathTracer;
+pub use photon::PhotonMapper;
+pub use spectral::SpectralPathTracer;
+
+pub trait Integrator: Send + Sync {
+    /// Estimate the radiance arriving at the camera along `ray`.
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color;
+
+    /// `li`, also filling in the lighting passes of `aov`. Integrators that
+    /// can't tell direct from indirect light leave them black.
+    fn li_aov(&self, ray: &Ray, scene: &Scene, _aov: &mut Aov) -> Color {
+        self.li(ray, scene)
+    }
+
+    /// Integrators that can't work one camera ray at a time render the whole
+    /// `width` by `height` image here instead, returning for each pixel, row
+    /// by row from the top, the sum of `nsamples` samples.
+    fn render(&self, _scene: &Scene, _width: usize, _height: usize, _nsamples: usize) -> Option<Vec<Color>> {
+        None
+    }
+}
+
+/// Look up an integrator by name, as given on the command line.
+pub fn by_name(name: &str, options: &Options, scene: &Scene) -> Option<Box<dyn Integrator>> {
+    // scale for the debug views: how far away the middle of the image is
+    let centre = scene.camera.get_ray(0.5, 0.5);
+    let distance = match scene.world.hit(&centre, 0.001, utils::INFINITY) {
+        Some(rec) => rec.t * centre.direction().length(),
+        None => scene.world.bounding_box().map_or(100., |b| b.radius()),
+    };
+    match name {
+        "path" => Some(Box::new(PathTracer { rr_depth: options.rr_depth })),
+        "spectral" => Some(Box::new(SpectralPathTracer::new(options.rr_depth))),
+        "bdpt" => Some(Box::new(Bdpt::new(scene, options.rr_depth))),
+        "mlt" => {
+            let seed = options.seed.unwrap_or_else(|| StdRng::from_entropy().gen());
+            Some(Box::new(Mlt::new(options.rr_depth, seed)))
+        }
+        "photon" => Some(Box::new(PhotonMapper::new(scene, options.rr_depth, options.photons))),
+        "ao" => Some(Box::new(AmbientOcclusion {
+            radius: options.ao_radius.unwrap_or(distance / 10.),
+        })),
+        "normals" => Some(Box::new(Normals)),
+        "depth" => Some(Box::new(Depth { max_distance: 2. * distance })),
+        "uv" => Some(Box::new(Uv)),
+        "material" => Some(Box::new(MaterialId)),
+        "bvh" => Some(Box::new(BvhHeatmap)),
+        _ => None,
+    }
+}
@@ -0,0 +1,375 @@
+use utils::random_double;
+use vec3::{v3, Color};
+
+use crate::{aov::Aov, hittable::{HitRecord, Hittable}, medium::{Medium, Walk}, ray::Ray, scene::Scene, spectrum::Wavelengths};
+
+use super::{photon::PhotonMap, Integrator};
+
+/// How light and BSDF samples are weighted against each other, either
+/// `utils::power_heuristic` or `utils::balance_heuristic`.
+const MIS_HEURISTIC: fn(f64, f64) -> f64 = utils::power_heuristic;
+
+/// Unidirectional path tracer with next-event estimation.
+pub struct PathTracer {
+    /// bounces a path always survives before russian roulette may end it
+    pub rr_depth: u32,
+}
+
+impl PathTracer {
+    /// Trace a path from `ray`, gathering direct light at every non-specular
+    /// vertex. Paths are ended by russian roulette once they are `rr_depth`
+    /// bounces long, which keeps the estimate unbiased.
+    ///
+    /// With a caustic photon map, light reaching a non-specular vertex over
+    /// specular bounces is looked up in the map instead, and the lights
+    /// found along such specular chains are not counted again. Chains broken
+    /// by scattering inside a medium aren't in the map.
+    ///
+    /// Returns the direct light, which is what the camera sees of lights
+    /// and the background, and what arrives at the first non-specular vertex
+    /// straight from them, and separately all the rest.
+    ///
+    /// Given `wavelengths`, the colours returned hold the radiance at each
+    /// of them instead of RGB.
+    pub(super) fn trace(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        caustics: Option<&PhotonMap>,
+        wavelengths: Option<&Wavelengths>,
+    ) -> (Color, Color) {
+        let mut direct = v3!(0., 0., 0.);
+        let mut indirect = v3!(0., 0., 0.);
+        let mut throughput = v3!(1., 1., 1.);
+        let mut ray = *ray;
+        ray.wavelength = wavelengths.map(Wavelengths::hero);
+        // set once a dispersive bounce has left only the hero wavelength
+        let mut hero_only = false;
+        // the walk through the medium inside an object, if the ray is in one
+        let mut walk: Option<Walk> = None;
+        // density with which the last bounce sampled `ray`, or `None` for camera
+        // rays and specular bounces; lights found this way are weighted against
+        // `sample_lights` with multiple importance sampling
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut non_specular_bounces = 0;
+        // whether lights `ray` finds after specular bounces are in the
+        // photon map, having been looked up at the last non-specular surface
+        let mut in_photon_map = false;
+        let mut depth = 0;
+        loop {
+            // whether lights found by `ray` count as direct light
+            let direct_ray = non_specular_bounces == 0 || (non_specular_bounces == 1 && bsdf_pdf.is_some());
+            let hit = scene.world.hit(&ray, 0.001, utils::INFINITY);
+            if let Some(walk) = &mut walk {
+                let max_distance = hit.as_ref().map_or(utils::INFINITY, |rec| rec.t * ray.direction().length());
+                if let Some(distance) = walk.step(max_distance) {
+                    // the boundary is smooth, so lights can't be sampled from inside
+                    ray = Ray {
+                        orig: ray.at(distance / ray.direction().length()),
+                        dir: walk.medium.sample_phase(ray.direction()),
+                        ..ray
+                    };
+                    bsdf_pdf = None;
+                    non_specular_bounces += 1;
+                    in_photon_map = false;
+                    // every walk leaves the object in the end, so unlike
+                    // bounces these need no cap on the chance of going on
+                    let survive = (throughput * walk.throughput()).max_component().min(1.);
+                    if random_double() >= survive {
+                        break;
+                    }
+                    walk.scale(1. / survive);
+                    continue;
+                }
+                throughput = throughput * walk.throughput();
+            }
+            walk = None;
+            let rec = match hit {
+                Some(rec) => rec,
+                None => {
+                    let mut background = throughput * lift(wavelengths, &scene.background.color(&ray));
+                    // the photon map also holds the background's caustics
+                    if in_photon_map && bsdf_pdf.is_none() {
+                        background = v3!(0., 0., 0.);
+                    }
+                    if direct_ray {
+                        direct = direct + background;
+                    } else {
+                        indirect = indirect + background;
+                    }
+                    break;
+                }
+            };
+            let mut emitted = emitted(wavelengths, &ray, &rec);
+            if !emitted.near_zero() {
+                match bsdf_pdf {
+                    Some(bsdf_pdf) => {
+                        let light_pdf = scene.lights.pdf_value(ray.origin(), ray.direction());
+                        emitted = emitted * MIS_HEURISTIC(bsdf_pdf, light_pdf);
+                    }
+                    None if in_photon_map && scene.is_light(&ray, &rec) => {
+                        emitted = v3!(0., 0., 0.);
+                    }
+                    None => {}
+                }
+            }
+            if direct_ray {
+                direct = direct + throughput * emitted;
+            } else {
+                indirect = indirect + throughput * emitted;
+            }
+
+            let srec = match rec.material.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+            if wavelengths.is_some() && !hero_only && rec.material.is_dispersive() {
+                // the hero carries on for all three, from the lights sampled
+                // here on, as the material only answered for it
+                throughput = v3!(3. * throughput.x(), 0., 0.);
+                hero_only = true;
+            }
+            bsdf_pdf = if srec.is_specular {
+                None
+            } else {
+                let light = throughput
+                    * (sample_lights(&ray, &rec, scene, wavelengths) + sample_delta_lights(&ray, &rec, scene, wavelengths));
+                if non_specular_bounces == 0 {
+                    direct = direct + light;
+                } else {
+                    indirect = indirect + light;
+                }
+                if let Some(caustics) = caustics {
+                    indirect = indirect + throughput * caustics.radiance(&ray, &rec);
+                    in_photon_map = true;
+                }
+                non_specular_bounces += 1;
+                Some(rec.material.scattering_pdf(&ray, &rec, srec.scattered.direction()))
+            };
+            throughput = throughput * lift(wavelengths, &srec.attenuation);
+            if let Some(interior) = rec.material.medium() {
+                // into the object, or back into it off the inside of its surface
+                let crossing = srec.scattered.direction().dot(&rec.normal) < 0.;
+                if crossing == rec.front_face {
+                    walk = Some(Walk::new(Medium {
+                        sigma_a: lift(wavelengths, &interior.sigma_a),
+                        sigma_s: lift(wavelengths, &interior.sigma_s),
+                        g: interior.g,
+                    }));
+                }
+            }
+            ray = Ray {
+                wavelength: ray.wavelength,
+                ..srec.scattered
+            };
+
+            depth += 1;
+            if !self.survives(depth, &mut throughput) {
+                break;
+            }
+        }
+        (direct, indirect)
+    }
+
+    /// Russian roulette after `depth` bounces: whether the path goes on,
+    /// with `throughput` raised to make up for the paths ended.
+    fn survives(&self, depth: u32, throughput: &mut Color) -> bool {
+        if depth < self.rr_depth {
+            return true;
+        }
+        // capped below one so that lossless loops still terminate
+        let survive = throughput.max_component().min(0.95);
+        if random_double() >= survive {
+            return false;
+        }
+        *throughput = *throughput / survive;
+        true
+    }
+}
+
+impl Integrator for PathTracer {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let (direct, indirect) = self.trace(ray, scene, None, None);
+        direct + indirect
+    }
+
+    fn li_aov(&self, ray: &Ray, scene: &Scene, aov: &mut Aov) -> Color {
+        (aov.direct, aov.indirect) = self.trace(ray, scene, None, None);
+        aov.direct + aov.indirect
+    }
+}
+
+/// The spectrum of `rgb` at `wavelengths`, or `rgb` itself without them.
+fn lift(wavelengths: Option<&Wavelengths>, rgb: &Color) -> Color {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.lift(rgb),
+        None => *rgb,
+    }
+}
+
+/// What `rec` gives off towards `ray`, at `wavelengths` when given.
+fn emitted(wavelengths: Option<&Wavelengths>, ray: &Ray, rec: &HitRecord) -> Color {
+    match wavelengths {
+        Some(wavelengths) => rec.material.emitted_spectrum(ray, rec, wavelengths),
+        None => rec.material.emitted(ray, rec),
+    }
+}
+
+/// Next-event estimation: pick a point on a light, trace a shadow ray to it
+/// and return the light arriving at `rec` along that direction, weighted
+/// against the chance of the BSDF sampling the same direction.
+pub(super) fn sample_lights(ray: &Ray, rec: &HitRecord, scene: &Scene, wavelengths: Option<&Wavelengths>) -> Color {
+    if scene.lights.is_empty() {
+        return v3!(0., 0., 0.);
+    }
+    let direction = scene.lights.random(&rec.p);
+    let light_pdf = scene.lights.pdf_value(&rec.p, &direction);
+    if light_pdf <= 0. {
+        return v3!(0., 0., 0.);
+    }
+    let f = rec.material.eval(ray, rec, &direction);
+    if f.near_zero() {
+        return v3!(0., 0., 0.);
+    }
+    let bsdf_pdf = rec.material.scattering_pdf(ray, rec, &direction);
+    let weight = MIS_HEURISTIC(light_pdf, bsdf_pdf);
+    let shadow_ray = Ray::new(rec.p, direction);
+    match scene.world.hit(&shadow_ray, 0.001, utils::INFINITY) {
+        Some(light_rec) => {
+            lift(wavelengths, &f) * emitted(wavelengths, &shadow_ray, &light_rec) * weight / light_pdf
+        }
+        None => v3!(0., 0., 0.),
+    }
+}
+
+/// Direct light from every point, spot and directional light in the scene.
+pub(super) fn sample_delta_lights(ray: &Ray, rec: &HitRecord, scene: &Scene, wavelengths: Option<&Wavelengths>) -> Color {
+    let mut color = v3!(0., 0., 0.);
+    for light in &scene.delta_lights {
+        let sample = match light.sample_li(&rec.p) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        let f = rec.material.eval(ray, rec, &sample.direction);
+        if f.near_zero() {
+            continue;
+        }
+        let shadow_ray = Ray::new(rec.p, sample.direction);
+        if scene.world.hit(&shadow_ray, 0.001, sample.distance * (1. - 1e-6)).is_none() {
+            color = color + lift(wavelengths, &f) * lift(wavelengths, &sample.radiance);
+        }
+    }
+    color
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::{v3, Color, Vec3};
+
+    use crate::{
+        camera::Camera,
+        hittable::{HitRecord, Hittable, HittableList},
+        integrator::Integrator,
+        light::PointLight,
+        material::{Dielectric, Lambertian, Material, ScatterRecord},
+        plane::Plane,
+        ray::Ray,
+        scene::{Background, Scene},
+        spectrum::Wavelengths,
+        sphere::Sphere,
+    };
+
+    use super::{sample_delta_lights, PathTracer};
+
+    /// Diffuse surface that also glows, without being put on the light list.
+    struct Glowing {
+        surface: Lambertian,
+        emit: Color,
+    }
+
+    impl Material for Glowing {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+            self.surface.scatter(r_in, rec)
+        }
+
+        fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+            self.surface.scattering_pdf(r_in, rec, direction)
+        }
+
+        fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+            self.emit
+        }
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        // inside a closed sphere of albedo 1/2 giving off 1/2 everywhere the
+        // radiance is 1/2 + 1/4 + ... = 1, which paths only reach in full
+        // when they aren't ended by russian roulette
+        let mut world = HittableList::new();
+        let material = Glowing {
+            surface: Lambertian::new(&v3!(0.5, 0.5, 0.5)),
+            emit: v3!(0.5, 0.5, 0.5),
+        };
+        world.add(Arc::new(Sphere::new(v3!(0., 0., 0.), 10., Arc::new(material))));
+        let camera = Camera::new(v3!(0., 0., 0.), v3!(0., 0., -1.), v3!(0., 1., 0.), 90., 1., 0., 1.);
+        let scene = Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)));
+        for rr_depth in [0, 1, 40] {
+            let path = PathTracer { rr_depth };
+            let n = 20000;
+            let mut sum = 0.;
+            for i in 0..n {
+                let u = (i % 100) as f64 / 100.;
+                sum += path.li(&scene.camera.get_ray(u, 0.5), &scene).y();
+            }
+            let mean = sum / n as f64;
+            assert!((mean - 1.).abs() < 0.02, "rr depth {}: {}", rr_depth, mean);
+        }
+    }
+
+    #[test]
+    fn test_delta_lights_are_occluded() {
+        // a ball hangs between a point light and the floor right below it
+        let mut world = HittableList::new();
+        let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor.clone())));
+        world.add(Arc::new(Sphere::new(v3!(0., 2., 0.), 0.5, floor)));
+        let camera = Camera::new(v3!(0., 1., 5.), v3!(0., 0., 0.), v3!(0., 1., 0.), 90., 1., 0., 1.);
+        let mut scene = Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)));
+        scene.add_light(Arc::new(PointLight::new(v3!(0., 4., 0.), v3!(25., 25., 25.))));
+
+        let light_at = |x: f64| {
+            let ray = Ray::new(v3!(x, 1., 0.), v3!(0., -1., 0.));
+            let rec = scene.world.hit(&ray, 0.001, utils::INFINITY).unwrap();
+            assert!(rec.p.y().abs() < 1e-9);
+            sample_delta_lights(&ray, &rec, &scene, None)
+        };
+        assert_eq!(light_at(0.), v3!(0., 0., 0.));
+        // 5 away at a cosine of 4/5
+        let expected = 0.5 / utils::PI * 0.8;
+        assert!((light_at(3.).y() - expected).abs() < 1e-9, "{:?}", light_at(3.));
+    }
+
+    #[test]
+    fn test_dispersion_leaves_the_hero() {
+        // through a glass ball into a white sky, which dispersive glass lets
+        // only the hero see, for all three wavelengths
+        for (glass, dispersive) in [(Dielectric::sf11(), true), (Dielectric::new(1.7), false)] {
+            let mut world = HittableList::new();
+            world.add(Arc::new(Sphere::new(v3!(0., 0., -3.), 1., Arc::new(glass))));
+            let camera = Camera::new(v3!(0., 0., 0.), v3!(0., 0., -1.), v3!(0., 1., 0.), 40., 1., 0., 1.);
+            let scene = Scene::new(world, camera, Background::Solid(v3!(1., 1., 1.)));
+            let path = PathTracer { rr_depth: 100 };
+            for i in 0..1000 {
+                let wavelengths = Wavelengths::sample((i as f64 + 0.5) / 1000.);
+                let ray = scene.camera.get_ray(0.5, 0.5);
+                let (direct, indirect) = path.trace(&ray, &scene, None, Some(&wavelengths));
+                let radiance = direct + indirect;
+                let expected = if dispersive {v3!(3., 0., 0.)} else {v3!(1., 1., 1.)};
+                assert!((radiance - expected).length() < 1e-9, "{:?}", radiance);
+            }
+        }
+    }
+}
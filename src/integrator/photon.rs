@@ -0,0 +1,422 @@
+//! Photon mapping for caustics, after Jensen's "Realistic Image Synthesis
+//! Using Photon Mapping".
+//!
+//! Before rendering, photons are shot from every light, and from the
+//! background when it isn't black, and stored where they land on a
+//! non-specular surface after one or more specular bounces, having crossed
+//! any medium inside an object without scattering. The path tracer then
+//! looks up the photon density at each non-specular vertex rather than
+//! hoping to find those light paths by itself, and ignores the ones it does
+//! find so they aren't counted twice.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use utils::{random_double, PI};
+use vec3::{random_cosine_direction, random_unit_vector, v3, Color, Onb, Point3, Vec3};
+
+use crate::{
+    aov::Aov,
+    hittable::{HitRecord, Hittable},
+    light::sample_from_infinity,
+    medium::Medium,
+    ray::Ray,
+    scene::Scene,
+};
+
+use super::{Integrator, PathTracer};
+
+/// photons used for each density estimate
+const GATHER_COUNT: usize = 50;
+
+struct Photon {
+    p: Point3,
+    /// normal of the surface, facing the side the photon arrived from
+    normal: Vec3,
+    /// unit vector towards where the photon came from
+    direction: Vec3,
+    power: Color,
+}
+
+/// Candidate for the k nearest photons, ordered by distance.
+struct Neighbour {
+    distance_squared: f64,
+    index: usize,
+}
+
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance_squared == other.distance_squared
+    }
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_squared.total_cmp(&other.distance_squared)
+    }
+}
+
+/// Photons kept in an implicit kd-tree: each range of the array is split at
+/// its middle element along the axis stored for it in `axes`.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+    /// density estimates never look further than this
+    max_radius_squared: f64,
+}
+
+impl PhotonMap {
+    /// Shoot `nphotons` photons into `scene` and keep the caustic ones.
+    pub fn build(scene: &Scene, nphotons: usize, rr_depth: u32) -> Self {
+        let mut photons = Vec::new();
+        let area_lights = scene.lights.objects();
+        let nsources = area_lights.len() + scene.delta_lights.len() + usize::from(!scene.background.is_black());
+        if nsources > 0 {
+            let targets: Vec<(Point3, f64)> = scene
+                .world
+                .objects()
+                .iter()
+                .filter_map(|o| o.bounding_box())
+                .map(|b| (b.center(), b.radius()))
+                .collect();
+            // lights and the background are picked uniformly
+            let scale = nsources as f64 / nphotons as f64;
+            for _ in 0..nphotons {
+                let i = ((random_double() * nsources as f64) as usize).min(nsources - 1);
+                let emitted = if i < area_lights.len() {
+                    Self::emit_from_surface(&*area_lights[i])
+                } else if i < area_lights.len() + scene.delta_lights.len() {
+                    scene.delta_lights[i - area_lights.len()].sample_le(&targets)
+                } else {
+                    Self::emit_from_background(scene, &targets)
+                };
+                if let Some((ray, flux)) = emitted {
+                    Self::trace_photon(scene, ray, flux * scale, rr_depth, &mut photons);
+                }
+            }
+        }
+        Self::from_photons(photons)
+    }
+
+    fn from_photons(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        let len = photons.len();
+        Self::build_tree(&mut photons, &mut axes, 0, len);
+        let mut map = Self {
+            photons,
+            axes,
+            max_radius_squared: utils::INFINITY,
+        };
+        map.max_radius_squared = map.typical_radius_squared();
+        map
+    }
+
+    fn emit_from_surface(light: &dyn Hittable) -> Option<(Ray, Color)> {
+        let rec = light.sample_surface()?;
+        let le = rec.material.emitted(&Ray::new(rec.p + rec.normal, -rec.normal), &rec);
+        let direction = Onb::build_from_w(&rec.normal).local(&random_cosine_direction());
+        // Le * cos / (pdf_position * pdf_direction)
+        Some((Ray::new(rec.p, direction), le * PI * light.area()))
+    }
+
+    /// The background as a light at infinity, seen over the whole sphere of
+    /// directions.
+    fn emit_from_background(scene: &Scene, targets: &[(Point3, f64)]) -> Option<(Ray, Color)> {
+        let direction = random_unit_vector();
+        let (ray, pdf_area) = sample_from_infinity(&direction, targets)?;
+        let radiance = scene.background.color(&Ray::new(*ray.origin(), -direction));
+        // radiance / (pdf_area * pdf_direction), directions having density 1 / 4pi
+        Some((ray, radiance * 4. * PI / pdf_area))
+    }
+
+    fn trace_photon(scene: &Scene, mut ray: Ray, mut power: Color, rr_depth: u32, photons: &mut Vec<Photon>) {
+        let mut depth = 0;
+        // the medium inside the object the photon is in, if any
+        let mut interior: Option<Medium> = None;
+        while let Some(rec) = scene.world.hit(&ray, 0.001, utils::INFINITY) {
+            if let Some(medium) = interior.take() {
+                // light scattered on the way is no longer a caustic, and the
+                // path tracer finds it instead
+                power = power * medium.transmittance(rec.t * ray.direction().length());
+            }
+            let srec = match rec.material.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => return,
+            };
+            if !srec.is_specular {
+                if depth > 0 {
+                    photons.push(Photon {
+                        p: rec.p,
+                        normal: rec.normal,
+                        direction: -ray.direction().unit_vector(),
+                        power,
+                    });
+                }
+                return;
+            }
+            power = power * srec.attenuation;
+            if let Some(medium) = rec.material.medium() {
+                let crossing = srec.scattered.direction().dot(&rec.normal) < 0.;
+                if crossing == rec.front_face {
+                    interior = Some(*medium);
+                }
+            }
+            ray = srec.scattered;
+            depth += 1;
+            if depth >= rr_depth {
+                let survive = srec.attenuation.max_component().min(0.95);
+                if random_double() >= survive {
+                    return;
+                }
+                power = power / survive;
+            }
+        }
+    }
+
+    /// Arrange `photons[lo..hi]` into a kd-tree.
+    fn build_tree(photons: &mut [Photon], axes: &mut [usize], lo: usize, hi: usize) {
+        if hi <= lo + 1 {
+            return;
+        }
+        let (mut min, mut max) = (photons[lo].p, photons[lo].p);
+        for photon in &photons[lo..hi] {
+            min = v3!(min.x().min(photon.p.x()), min.y().min(photon.p.y()), min.z().min(photon.p.z()));
+            max = v3!(max.x().max(photon.p.x()), max.y().max(photon.p.y()), max.z().max(photon.p.z()));
+        }
+        let extent = max - min;
+        let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        };
+        let mid = (lo + hi) / 2;
+        photons[lo..hi].select_nth_unstable_by(mid - lo, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+        axes[mid] = axis;
+        Self::build_tree(photons, axes, lo, mid);
+        Self::build_tree(photons, axes, mid + 1, hi);
+    }
+
+    /// Collect into `heap` the at most `k` photons in `lo..hi` nearest to
+    /// `p` that lie within `max_distance_squared`.
+    fn nearest(&self, p: &Point3, k: usize, max_distance_squared: f64, lo: usize, hi: usize, heap: &mut BinaryHeap<Neighbour>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid];
+        let delta = p[axis] - photon.p[axis];
+        let (near, far) = if delta < 0. { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+
+        self.nearest(p, k, max_distance_squared, near.0, near.1, heap);
+
+        let distance_squared = (photon.p - p).length_squared();
+        if distance_squared < max_distance_squared {
+            if heap.len() < k {
+                heap.push(Neighbour { distance_squared, index: mid });
+            } else if distance_squared < heap.peek().unwrap().distance_squared {
+                heap.pop();
+                heap.push(Neighbour { distance_squared, index: mid });
+            }
+        }
+
+        let bound = if heap.len() < k { max_distance_squared } else { heap.peek().unwrap().distance_squared };
+        if delta * delta < bound {
+            self.nearest(p, k, max_distance_squared, far.0, far.1, heap);
+        }
+    }
+
+    /// Twice the median distance to the `GATHER_COUNT`th neighbour of a few
+    /// stored photons, so that the search radius follows the scene's scale.
+    fn typical_radius_squared(&self) -> f64 {
+        if self.photons.is_empty() {
+            return 0.;
+        }
+        let step = (self.photons.len() / 128).max(1);
+        let mut radii: Vec<f64> = (0..self.photons.len())
+            .step_by(step)
+            .map(|i| {
+                let mut heap = BinaryHeap::new();
+                self.nearest(&self.photons[i].p, GATHER_COUNT, utils::INFINITY, 0, self.photons.len(), &mut heap);
+                heap.peek().map_or(0., |n| n.distance_squared)
+            })
+            .collect();
+        radii.sort_by(|a, b| a.total_cmp(b));
+        4. * radii[radii.len() / 2]
+    }
+
+    /// Caustic radiance leaving `rec` back along `r_in`, estimated from the
+    /// density of nearby photons.
+    pub fn radiance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let mut heap = BinaryHeap::new();
+        self.nearest(&rec.p, GATHER_COUNT, self.max_radius_squared, 0, self.photons.len(), &mut heap);
+        if heap.is_empty() {
+            return v3!(0., 0., 0.);
+        }
+        // the farthest of a full set of neighbours only marks the radius, as
+        // counting it too would overestimate the density by k / (k - 1)
+        let radius_squared = if heap.len() < GATHER_COUNT {
+            self.max_radius_squared
+        } else {
+            heap.pop().unwrap().distance_squared
+        };
+        let mut color = v3!(0., 0., 0.);
+        for neighbour in heap {
+            let photon = &self.photons[neighbour.index];
+            // skip photons on the other side of the surface or around a corner
+            if photon.normal.dot(&rec.normal) < 0.9 {
+                continue;
+            }
+            let cosine = rec.normal.dot(&photon.direction);
+            if cosine <= 0. {
+                continue;
+            }
+            // `eval` includes the cosine, which the photon's power already has
+            let f = rec.material.eval(r_in, rec, &photon.direction) / cosine;
+            color = color + f * photon.power;
+        }
+        color / (PI * radius_squared)
+    }
+}
+
+/// Path tracer taking its caustics from a photon map.
+pub struct PhotonMapper {
+    path: PathTracer,
+    caustics: PhotonMap,
+}
+
+impl PhotonMapper {
+    pub fn new(scene: &Scene, rr_depth: u32, nphotons: usize) -> Self {
+        Self {
+            path: PathTracer { rr_depth },
+            caustics: PhotonMap::build(scene, nphotons, rr_depth),
+        }
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let (direct, indirect) = self.path.trace(ray, scene, Some(&self.caustics), None);
+        direct + indirect
+    }
+
+    fn li_aov(&self, ray: &Ray, scene: &Scene, aov: &mut Aov) -> Color {
+        (aov.direct, aov.indirect) = self.path.trace(ray, scene, Some(&self.caustics), None);
+        aov.direct + aov.indirect
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BinaryHeap, sync::Arc};
+
+    use utils::{random_double, PI};
+    use vec3::{v3, Vec3};
+
+    use crate::{
+        camera::Camera,
+        hittable::{Hittable, HittableList},
+        light::PointLight,
+        material::{Lambertian, Metal, Subsurface},
+        plane::Plane,
+        ray::Ray,
+        scene::{Background, Scene},
+        sphere::Sphere,
+    };
+
+    use super::{Photon, PhotonMap};
+
+    #[test]
+    fn test_nearest_matches_brute_force() {
+        let photons = (0..2000)
+            .map(|i| Photon {
+                // some on a plane, where the tree must split on two axes only
+                p: if i % 2 == 0 { Vec3::random() } else { v3!(random_double(), 0.5, random_double()) },
+                normal: v3!(0., 1., 0.),
+                direction: v3!(0., 1., 0.),
+                power: v3!(1., 1., 1.),
+            })
+            .collect();
+        let map = PhotonMap::from_photons(photons);
+        for _ in 0..200 {
+            let p = Vec3::random_range(-0.2, 1.2);
+            let max_distance_squared = 0.2 * random_double();
+            let mut heap = BinaryHeap::new();
+            map.nearest(&p, 20, max_distance_squared, 0, map.photons.len(), &mut heap);
+            let mut found: Vec<f64> = heap.into_iter().map(|n| n.distance_squared).collect();
+            found.sort_by(|a, b| a.total_cmp(b));
+
+            let mut expected: Vec<f64> = map
+                .photons
+                .iter()
+                .map(|photon| (photon.p - p).length_squared())
+                .filter(|&d| d < max_distance_squared)
+                .collect();
+            expected.sort_by(|a, b| a.total_cmp(b));
+            expected.truncate(20);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_density_estimate_flux() {
+        // a point light above a mirror, whose reflection lights a diffuse
+        // ceiling as if from a light twice as far below it
+        let mut world = HittableList::new();
+        world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), Arc::new(Metal::new(&v3!(1., 1., 1.), 0.)))));
+        let ceiling = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Plane::new(v3!(0., 2., 0.), v3!(0., -1., 0.), ceiling)));
+        let camera = Camera::new(v3!(0., 1., 0.), v3!(0., 2., 0.), v3!(1., 0., 0.), 90., 1., 0., 1.);
+        let mut scene = Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)));
+        let intensity = 10.;
+        scene.add_light(Arc::new(PointLight::new(v3!(0., 1., 0.), v3!(intensity, intensity, intensity))));
+        let map = PhotonMap::build(&scene, 400_000, 5);
+
+        let mut ratio = 0.;
+        // spread out on a spiral so that they share few photons
+        let n = 200;
+        for i in 0..n {
+            let angle = 2.4 * i as f64;
+            let radius = 2. * ((i as f64 + 0.5) / n as f64).sqrt();
+            let p = v3!(radius * angle.cos(), 1., radius * angle.sin());
+            let ray = Ray::new(p, v3!(0., 1., 0.));
+            let rec = scene.world.hit(&ray, 0.001, utils::INFINITY).unwrap();
+            // irradiance from the mirror image of the light, 3 below
+            let distance = (rec.p - v3!(0., -1., 0.)).length();
+            let irradiance = intensity * 3. / distance.powi(3);
+            ratio += map.radiance(&ray, &rec).y() / (0.5 / PI * irradiance);
+        }
+        let ratio = ratio / n as f64;
+        assert!((ratio - 1.).abs() < 0.05, "{}", ratio);
+    }
+
+    #[test]
+    fn test_photons_cross_media_unscattered() {
+        // a boundary of index 1 lets every photon straight through the
+        // sphere, keeping what the medium neither absorbs nor scatters
+        let mut world = HittableList::new();
+        let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor)));
+        let (sigma_a, sigma_s) = (v3!(0.1, 0.2, 0.3), v3!(0.5, 0.5, 0.5));
+        world.add(Arc::new(Sphere::new(v3!(0., 2., 0.), 1., Arc::new(Subsurface::new(1., &sigma_a, &sigma_s, 0.)))));
+        let camera = Camera::new(v3!(0., 1., 5.), v3!(0., 1., 0.), v3!(0., 1., 0.), 40., 1., 0., 1.);
+        let scene = Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)));
+
+        let mut photons = Vec::new();
+        PhotonMap::trace_photon(&scene, Ray::new(v3!(0., 5., 0.), v3!(0., -1., 0.)), v3!(1., 1., 1.), 10, &mut photons);
+        assert_eq!(photons.len(), 1);
+        let sigma_t = sigma_a + sigma_s;
+        let expected = v3!((-2. * sigma_t.x()).exp(), (-2. * sigma_t.y()).exp(), (-2. * sigma_t.z()).exp());
+        assert!((photons[0].power - expected).length() < 1e-9, "{:?}", photons[0].power);
+        assert!(photons[0].p.length() < 1e-9);
+    }
+}
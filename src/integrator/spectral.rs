@@ -0,0 +1,73 @@
+use utils::random_double;
+use vec3::Color;
+
+use crate::{aov::Aov, ray::Ray, scene::Scene, spectrum::Wavelengths};
+
+use super::{path::PathTracer, Integrator};
+
+/// The path tracer following three wavelengths at a time instead of RGB, so
+/// that dispersive glass splits white light into its colours.
+pub struct SpectralPathTracer {
+    path: PathTracer,
+}
+
+impl SpectralPathTracer {
+    pub fn new(rr_depth: u32) -> Self {
+        Self {
+            path: PathTracer { rr_depth },
+        }
+    }
+}
+
+impl Integrator for SpectralPathTracer {
+    fn li(&self, ray: &Ray, scene: &Scene) -> Color {
+        let wavelengths = Wavelengths::sample(random_double());
+        let (direct, indirect) = self.path.trace(ray, scene, None, Some(&wavelengths));
+        wavelengths.rgb(&(direct + indirect))
+    }
+
+    fn li_aov(&self, ray: &Ray, scene: &Scene, aov: &mut Aov) -> Color {
+        let wavelengths = Wavelengths::sample(random_double());
+        let (direct, indirect) = self.path.trace(ray, scene, None, Some(&wavelengths));
+        aov.direct = wavelengths.rgb(&direct);
+        aov.indirect = wavelengths.rgb(&indirect);
+        aov.direct + aov.indirect
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::random_double;
+    use vec3::{v3, Color};
+
+    use crate::{integrator::{Integrator, PathTracer}, scene::Scene};
+
+    use super::SpectralPathTracer;
+
+    /// Average radiance over the lower three quarters of a small image, which
+    /// leaves out the directly visible light.
+    fn mean_radiance(integrator: &dyn Integrator, scene: &Scene, size: usize, nsamples: usize) -> Color {
+        let mut color = v3!(0., 0., 0.);
+        for j in 0..size {
+            for i in 0..size {
+                for _ in 0..nsamples {
+                    let u = (i as f64 + random_double()) / size as f64;
+                    let v = 0.75 * (j as f64 + random_double()) / size as f64;
+                    color = color + integrator.li(&scene.camera.get_ray(u, v), scene);
+                }
+            }
+        }
+        color / (size * size * nsamples) as f64
+    }
+
+    #[test]
+    fn test_spectral_matches_path_tracer() {
+        // without dispersion only the noise tells the two apart
+        let scene = Scene::by_name("cornell", 1.).unwrap();
+        let path = mean_radiance(&PathTracer { rr_depth: 5 }, &scene, 16, 128);
+        let spectral = mean_radiance(&SpectralPathTracer::new(5), &scene, 16, 128);
+        for (a, b) in [(path.0, spectral.0), (path.1, spectral.1), (path.2, spectral.2)] {
+            assert!((a - b).abs() < 0.05 * a, "path tracer {:?}, spectral {:?}", path, spectral);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use utils::{clamp, degrees_to_radians, random_double, PI};
+use vec3::{random_in_unit_disk, random_unit_vector, v3, Color, Onb, Point3, Vec3};
+
+use crate::ray::Ray;
+
+/// Light arriving at a point from a delta light.
+pub struct LightSample {
+    /// unit vector pointing from the shaded point towards the light
+    pub direction: Vec3,
+    /// distance to the light, infinite for directional lights
+    pub distance: f64,
+    pub radiance: Color,
+}
+
+/// A light without a surface. Rays can never hit one, so it is only reached
+/// through next-event estimation and needs no importance sampling.
+pub trait DeltaLight: Send + Sync {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample>;
+
+    /// Emit a photon, returning its ray and the flux it carries divided by
+    /// the density it was sampled with. `targets` are bounding spheres of the
+    /// scene's objects, which lights at infinity aim for.
+    fn sample_le(&self, targets: &[(Point3, f64)]) -> Option<(Ray, Color)>;
+}
+
+/// Isotropic light at a single point, falling off with the squared distance.
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl DeltaLight for PointLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / distance_squared,
+        })
+    }
+
+    fn sample_le(&self, _targets: &[(Point3, f64)]) -> Option<(Ray, Color)> {
+        Some((Ray::new(self.position, random_unit_vector()), self.intensity * 4. * PI))
+    }
+}
+
+/// Point light restricted to a cone around `direction`. Full intensity within
+/// `falloff_start` degrees of the axis, smoothly fading out at `total_width`.
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    pub fn new(position: Point3, lookat: Point3, intensity: Color, total_width: f64, falloff_start: f64) -> Self {
+        Self {
+            position,
+            direction: (lookat - position).unit_vector(),
+            intensity,
+            cos_total_width: degrees_to_radians(total_width).cos(),
+            cos_falloff_start: degrees_to_radians(falloff_start.min(total_width)).cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta <= self.cos_total_width {
+            return 0.;
+        }
+        if cos_theta >= self.cos_falloff_start {
+            return 1.;
+        }
+        let t = clamp((cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width), 0., 1.);
+        t * t * (3. - 2. * t)
+    }
+}
+
+impl DeltaLight for SpotLight {
+    fn sample_li(&self, p: &Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        let distance = distance_squared.sqrt();
+        let direction = to_light / distance;
+        let falloff = self.falloff((-direction).dot(&self.direction));
+        if falloff <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity * falloff / distance_squared,
+        })
+    }
+
+    fn sample_le(&self, _targets: &[(Point3, f64)]) -> Option<(Ray, Color)> {
+        // uniform over the cone
+        let solid_angle = 2. * PI * (1. - self.cos_total_width);
+        let cos_theta = 1. - random_double() * (1. - self.cos_total_width);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * random_double();
+        let local = v3!(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+        let direction = Onb::build_from_w(&self.direction).local(&local);
+        Some((Ray::new(self.position, direction), self.intensity * self.falloff(cos_theta) * solid_angle))
+    }
+}
+
+/// Infinitely distant light such as the sun, arriving from one direction
+/// with constant irradiance everywhere.
+pub struct DirectionalLight {
+    /// towards the light
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    /// `direction` is the way the light travels.
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction: -direction.unit_vector(),
+            irradiance,
+        }
+    }
+}
+
+impl DeltaLight for DirectionalLight {
+    fn sample_li(&self, _p: &Point3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.direction,
+            distance: utils::INFINITY,
+            radiance: self.irradiance,
+        })
+    }
+
+    fn sample_le(&self, targets: &[(Point3, f64)]) -> Option<(Ray, Color)> {
+        let (ray, pdf) = sample_from_infinity(&-self.direction, targets)?;
+        Some((ray, self.irradiance / pdf))
+    }
+}
+
+/// Ray travelling along the unit vector `direction` from infinitely far
+/// away, for emitting photons from lights at infinity, along with its
+/// density per unit area of the plane facing it.
+///
+/// Rays start on a disk facing the light across one of the `targets`. The
+/// density of a ray's line sums over all target disks it passes through.
+pub fn sample_from_infinity(direction: &Vec3, targets: &[(Point3, f64)]) -> Option<(Ray, f64)> {
+    if targets.is_empty() {
+        return None;
+    }
+    let i = ((random_double() * targets.len() as f64) as usize).min(targets.len() - 1);
+    let (center, radius) = targets[i];
+    let uvw = Onb::build_from_w(direction);
+    let d = random_in_unit_disk();
+    let p = center + radius * (d.x() * uvw.u() + d.y() * uvw.v());
+    // start outside of everything the ray could hit
+    let far = targets.iter().map(|(c, r)| (c - p).length() + r).fold(0., f64::max);
+    let ray = Ray::new(p - far * direction, *direction);
+
+    let mut pdf = 0.;
+    for (c, r) in targets {
+        let oc = c - ray.origin();
+        let perpendicular = oc - oc.dot(ray.direction()) * ray.direction();
+        if perpendicular.length_squared() <= r * r {
+            pdf += 1. / (PI * r * r);
+        }
+    }
+    Some((ray, pdf / targets.len() as f64))
+}
+
+#[cfg(test)]
+mod test {
+    use utils::PI;
+    use vec3::v3;
+
+    use super::{DeltaLight, DirectionalLight, PointLight, SpotLight};
+
+    #[test]
+    fn test_point_light_falls_off_with_squared_distance() {
+        let light = PointLight::new(v3!(0., 4., 0.), v3!(8., 8., 8.));
+        let near = light.sample_li(&v3!(0., 2., 0.)).unwrap();
+        let far = light.sample_li(&v3!(0., -4., 0.)).unwrap();
+        assert_eq!(near.distance, 2.);
+        assert_eq!(near.direction, v3!(0., 1., 0.));
+        assert!((near.radiance.y() - 2.).abs() < 1e-12);
+        assert!((far.radiance.y() - 0.125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_spot_light_cone() {
+        // full intensity up to 20 degrees off the axis, none past 40
+        let light = SpotLight::new(v3!(0., 1., 0.), v3!(0., 0., 0.), v3!(1., 1., 1.), 40., 20.);
+        let at = |degrees: f64| {
+            let p = v3!(degrees.to_radians().tan(), 0., 0.);
+            light.sample_li(&p).map_or(0., |s| s.radiance.y() * s.distance * s.distance)
+        };
+        assert!((at(0.) - 1.).abs() < 1e-12);
+        assert!((at(19.9) - 1.).abs() < 1e-12);
+        assert_eq!(at(40.1), 0.);
+        assert!(light.sample_li(&v3!(0., 2., 0.)).is_none());
+        // smoothstep in the cosine in between, rising from the outer edge
+        let (cos_total, cos_start) = (40_f64.to_radians().cos(), 20_f64.to_radians().cos());
+        let t = (30_f64.to_radians().cos() - cos_total) / (cos_start - cos_total);
+        assert!((at(30.) - t * t * (3. - 2. * t)).abs() < 1e-12);
+        assert!(at(25.) > at(30.) && at(30.) > at(35.) && at(35.) > 0.);
+    }
+
+    #[test]
+    fn test_directional_light() {
+        let light = DirectionalLight::new(v3!(0., -2., 0.), v3!(3., 3., 3.));
+        for p in [v3!(0., 0., 0.), v3!(100., -50., 7.)] {
+            let sample = light.sample_li(&p).unwrap();
+            assert_eq!(sample.direction, v3!(0., 1., 0.));
+            assert_eq!(sample.distance, utils::INFINITY);
+            assert_eq!(sample.radiance, v3!(3., 3., 3.));
+        }
+    }
+
+    #[test]
+    fn test_directional_light_photon_flux() {
+        // the flux through each target is the irradiance over its area, even
+        // where targets overlap
+        let irradiance = 2.;
+        let light = DirectionalLight::new(v3!(0., -1., 0.), v3!(irradiance, irradiance, irradiance));
+        let targets = [(v3!(0., 0., 0.), 1.), (v3!(0.5, 3., 0.), 0.5), (v3!(5., -2., 1.), 2.)];
+        let n = 200_000;
+        let mut flux = [0.; 3];
+        for _ in 0..n {
+            let (ray, power) = light.sample_le(&targets).unwrap();
+            assert_eq!(*ray.direction(), v3!(0., -1., 0.));
+            for (i, (center, radius)) in targets.iter().enumerate() {
+                let oc = center - ray.origin();
+                if oc.y() < 0. && oc.x() * oc.x() + oc.z() * oc.z() <= radius * radius {
+                    flux[i] += power.y();
+                }
+            }
+        }
+        for (i, (_, radius)) in targets.iter().enumerate() {
+            let expected = irradiance * PI * radius * radius;
+            assert!((flux[i] / n as f64 - expected).abs() < 0.02 * expected, "{} {:?}", i, flux);
+        }
+    }
+}
@@ -1,112 +1,129 @@
 use std::sync::{Arc, Mutex};
 
-use camera::Camera;
-use hittable::{Hittable, HittableList};
-use material::{Dielectric, Lambertian, Metal, Material};
+use aov::{Aov, AovImage};
+use options::Options;
 use ppm::PPM;
-use ray::Ray;
-use sphere::Sphere;
-use utils::{random_double, random_double_range};
-use vec3::{v3, Color, Vec3};
+use scene::Scene;
+use utils::random_double;
+use vec3::v3;
 use rayon::prelude::*;
 
+mod aabb;
+mod aov;
+mod bvh;
 mod camera;
+mod csg;
 mod hittable;
+mod integrator;
+mod light;
 mod material;
+mod medium;
+mod merl;
+mod microfacet;
+mod options;
+mod plane;
+mod principled;
+mod quad;
+mod quadric;
+mod quartic;
 mod ray;
+mod scene;
+mod sdf;
+mod spectrum;
 mod sphere;
+mod texture;
+mod triangle;
 
-const MAX_DEPTH: u32 = 50;
 const NSAMPLES: usize = 100;
 
 fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, options::USAGE);
+            std::process::exit(1);
+        }
+    };
+
     // image
     let aspect_ratio = 3.0 / 2.0;
     let image_width = 800_u32;
     let image_height = (image_width as f64 / aspect_ratio) as u32;
     let image = PPM::new(image_width, image_height);
     let the_image = Arc::new(Mutex::new(image));
+    let aovs = Mutex::new(AovImage::new(image_width as usize, image_height as usize));
 
     // World
-    let world = random_scene();
-
-    // camera
-    let lookfrom = v3!(13., 2., 3.);
-    let lookat = v3!(0., 0., 0.);
-    let vup = v3!(0., 1., 0.);
-    let dist_to_focus = 10.;
-    let aperture = 0.1;
-    let camera = Camera::new(
-        lookfrom, lookat ,vup, 20., aspect_ratio, aperture, dist_to_focus);
-
-    // render
-    (0..image_height).collect::<Vec<_>>().par_iter().rev().for_each(|j| {
-    // for j in (0..image_height).rev() {
-        println!("{}/{}", j, image_height);
-        for i in 0..image_width {
-            let mut color = v3!(0., 0., 0.);
-            // println!("{} {}", j, i);
-            for _i in 0..NSAMPLES {
-                let u = (i as f64 + random_double()) / (image_width as f64 - 1.);
-                let v = (*j as f64 + random_double()) / (image_height as f64 - 1.);
-                let r = camera.get_ray(u, v);
-                color = color + ray_color(&r, &world, MAX_DEPTH);
+    let scene = match (options.scene.as_str(), &options.brdf) {
+        ("measured", Some(path)) => match merl::Merl::load(path) {
+            Ok(brdf) => scene::measured(aspect_ratio, Arc::new(brdf)),
+            Err(e) => {
+                eprintln!("can't load {:?}: {}", path, e);
+                std::process::exit(1);
             }
-            the_image.lock().unwrap().set_with_samples((image_height - j - 1) as usize, i as usize, color, NSAMPLES);
+        },
+        ("measured", None) => {
+            eprintln!("the measured scene needs --brdf\n{}", options::USAGE);
+            std::process::exit(1);
         }
-    });
-    the_image.lock().unwrap().save("test.ppm").unwrap();
-}
-
-fn random_scene() -> HittableList {
-    let mut world = HittableList::new();
-    let ground_material = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
-    world.add(Arc::new(Sphere::new(v3!(0., -1000., 0.), 1000., ground_material)));
-
-    for a in -11..=11 {
-        for b in -11..=11 {
-            let choose_mat = random_double();
-            let center = v3!(a as f64 + 0.9 * random_double(), 0.2, b as f64 + 0.9 * random_double());
-            if (center - v3!(4., 0.2, 0.)).length() > 0.9 {
-                let sphere_material: Arc<dyn Material> = if choose_mat < 0.8 {
-                    let albedo = Vec3::random() * Vec3::random();
-                    Arc::new(Lambertian::new(&albedo))
-                } else if choose_mat < 0.95 {
-                    let albedo = Vec3::random_range(0.5, 1.);
-                    let fuzz = random_double_range(0., 0.5);
-                    Arc::new(Metal::new(&albedo, fuzz))
-                } else {
-                    Arc::new(Dielectric::new(1.5))
-                };
-                world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
+        (name, _) => match Scene::by_name(name, aspect_ratio) {
+            Some(scene) => scene,
+            None => {
+                eprintln!("unknown scene {:?}\n{}", options.scene, options::USAGE);
+                std::process::exit(1);
             }
+        },
+    };
+    let integrator = match integrator::by_name(&options.integrator, &options, &scene) {
+        Some(integrator) => integrator,
+        None => {
+            eprintln!("unknown integrator {:?}\n{}", options.integrator, options::USAGE);
+            std::process::exit(1);
         }
-    }
-    let material1 = Arc::new(Dielectric::new(1.5));
-    world.add(Arc::new(Sphere::new(v3!(0., 1., 0.), 1., material1)));
-    let material2 = Arc::new(Lambertian::new(&v3!(0.4, 0.2, 0.1)));
-    world.add(Arc::new(Sphere::new(v3!(-4., 1., 0.), 1., material2)));
-    let material3 = Arc::new(Metal::new(&v3!(0.7, 0.6, 0.5), 0.0));
-    world.add(Arc::new(Sphere::new(v3!(4., 1., 0.), 1., material3)));
-    world
-}
+    };
 
-fn ray_color(ray: &Ray, world: &dyn Hittable, depth: u32) -> Color {
-    if depth <= 0 {
-        return v3!(0., 0., 0.);
-    }
-    if let Some(rec) = world.hit(ray, 0.001, utils::INFINITY) {
-        let mut scattered = Ray::new(v3!(0., 0., 0.), v3!(0., 0., 0.));
-        let mut attenuation = v3!(1., 1., 1.);
-        if rec
-            .material
-            .scatter(ray, &rec, &mut attenuation, &mut scattered)
-        {
-            return attenuation * ray_color(&scattered, world, depth - 1);
+    // render
+    if let Some(pixels) = integrator.render(&scene, image_width as usize, image_height as usize, NSAMPLES) {
+        if options.aovs {
+            eprintln!("{} renders the whole image at once and can't save AOVs", options.integrator);
         }
-        return v3!(0., 0., 0.);
+        let mut image = the_image.lock().unwrap();
+        for (k, color) in pixels.into_iter().enumerate() {
+            image.set_with_samples(k / image_width as usize, k % image_width as usize, color, NSAMPLES);
+        }
+    } else {
+        (0..image_height).collect::<Vec<_>>().par_iter().rev().for_each(|j| {
+        // for j in (0..image_height).rev() {
+            println!("{}/{}", j, image_height);
+            for i in 0..image_width {
+                let mut color = v3!(0., 0., 0.);
+                let mut samples = vec![];
+                // println!("{} {}", j, i);
+                for _i in 0..NSAMPLES {
+                    let u = (i as f64 + random_double()) / (image_width as f64 - 1.);
+                    let v = (*j as f64 + random_double()) / (image_height as f64 - 1.);
+                    let r = scene.camera.get_ray(u, v);
+                    if options.aovs {
+                        let mut aov = Aov::first_hit(&r, &scene);
+                        color = color + integrator.li_aov(&r, &scene, &mut aov);
+                        samples.push(aov);
+                    } else {
+                        color = color + integrator.li(&r, &scene);
+                    }
+                }
+                let row = (image_height - j - 1) as usize;
+                the_image.lock().unwrap().set_with_samples(row, i as usize, color, NSAMPLES);
+                if options.aovs {
+                    let mut aovs = aovs.lock().unwrap();
+                    for aov in &samples {
+                        aovs.add(row, i as usize, aov);
+                    }
+                }
+            }
+        });
+    }
+    the_image.lock().unwrap().save("test.ppm").unwrap();
+    if options.aovs {
+        aovs.lock().unwrap().save("test", NSAMPLES).unwrap();
     }
-    let unit_direction = ray.direction().unit_vector();
-    let t = 0.5 * (unit_direction.y() + 1.);
-    (1. - t) * v3!(1., 1., 1.) + t * v3!(0.5, 0.7, 1.0)
 }
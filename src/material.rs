@@ -1,10 +1,69 @@
-use utils::random_double;
-use vec3::{Color, random_unit_vector, reflect, random_in_unit_sphere, v3, refract};
+use std::sync::Arc;
 
-use crate::{hittable::HitRecord, ray::Ray};
+use utils::{random_double, PI};
+use vec3::{random_cosine_direction, random_in_unit_sphere, reflect, refract, v3, Color, Onb, Vec3};
 
-pub trait Material {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool;
+use crate::{hittable::HitRecord, medium::Medium, microfacet::{self, fresnel_conductor, fresnel_dielectric, Ggx, ThinFilm}, ray::Ray, spectrum::{blackbody, planck, planck_luminance, Wavelengths, LAMBDA_RGB, LUMENS_PER_WATT}, texture::{Constant, Texture}};
+
+/// Result of sampling a material at a hit point.
+pub struct ScatterRecord {
+    /// throughput carried by `scattered`, i.e. BSDF * cos / pdf
+    pub attenuation: Color,
+    pub scattered: Ray,
+    /// delta lobes (mirror, smooth glass) can't be reached by light sampling
+    pub is_specular: bool,
+}
+
+pub trait Material: Send + Sync {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+
+    /// BSDF times the cosine term for light leaving along `direction`. Only
+    /// non-specular materials need to implement this.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> Color {
+        v3!(0., 0., 0.)
+    }
+
+    /// Solid angle density with which `scatter` picks `direction`.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> f64 {
+        0.
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        v3!(0., 0., 0.)
+    }
+
+    /// `emitted` at each of `wavelengths`, for spectral rendering. Lifted
+    /// from the colour, unless the material knows its spectrum.
+    fn emitted_spectrum(&self, r_in: &Ray, rec: &HitRecord, wavelengths: &Wavelengths) -> Color {
+        wavelengths.lift(&self.emitted(r_in, rec))
+    }
+
+    /// Overall colour of the surface, for the albedo AOV.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        v3!(0., 0., 0.)
+    }
+
+    /// Share of the light arriving along `r_in` that crosses the surface
+    /// rather than being reflected, for materials used as a coating. Opaque
+    /// ones let nothing through.
+    fn transmittance(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        v3!(0., 0., 0.)
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+    /// What fills objects made of this material, for rays `scatter` sends
+    /// inside.
+    fn medium(&self) -> Option<&Medium> {
+        None
+    }
+
+    /// Whether what `scatter` does depends on the wavelength a ray carries,
+    /// so that a spectral path can only carry one wavelength past it.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
@@ -20,14 +79,103 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
-        let mut scatter_direction = rec.normal + random_unit_vector();
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&rec.normal);
+        Some(ScatterRecord {
+            attenuation: self.albedo,
+            scattered: Ray::new(rec.p, uvw.local(&random_cosine_direction())),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        self.albedo * self.scattering_pdf(r_in, rec, direction)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(&direction.unit_vector());
+        if cosine < 0. {0.} else {cosine / PI}
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+/// Diffuse surface made of tiny V-shaped grooves, after Oren and Nayar,
+/// "Generalization of Lambert's Reflectance Model". Rough materials like
+/// clay and concrete look flatter than Lambertian ones, brighter towards the
+/// rim and back towards the light. `sigma` is the standard deviation of the
+/// groove angles in degrees, with 0 giving back `Lambertian`. Directions
+/// are sampled by the cosine alone, as for `Lambertian`, rather than by the
+/// whole BSDF, which leaves the grooves' factor in the weights.
+pub struct OrenNayar {
+    albedo: Color,
+    a: f64,
+    b: f64,
+}
+
+impl OrenNayar {
+    pub fn new(c: &Color, sigma: f64) -> Self {
+        let sigma2 = sigma.to_radians() * sigma.to_radians();
+        Self {
+            albedo: *c,
+            a: 1. - sigma2 / (2. * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
         }
-        *scattered = Ray::new(rec.p, scatter_direction);
-        *attenuation = self.albedo;
-        return true;
+    }
+
+    /// The BSDF over that of a Lambertian surface of the same albedo, for
+    /// local directions on the same side.
+    fn factor(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let sin_o = (1. - wo.z() * wo.z()).max(0.).sqrt();
+        let sin_i = (1. - wi.z() * wi.z()).max(0.).sqrt();
+        // cosine of the azimuth between the two directions
+        let cos_phi = if sin_o > 1e-4 && sin_i > 1e-4 {
+            ((wo.x() * wi.x() + wo.y() * wi.y()) / (sin_o * sin_i)).max(0.)
+        } else {
+            0.
+        };
+        // sin of the larger polar angle times tan of the smaller one
+        let (sin_alpha, tan_beta) = if wi.z().abs() > wo.z().abs() {
+            (sin_o, sin_i / wi.z().abs())
+        } else {
+            (sin_i, sin_o / wo.z().abs())
+        };
+        self.a + self.b * cos_phi * sin_alpha * tan_beta
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        // cosine sampling, as for Lambertian, leaves just the factor as weight
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = random_cosine_direction();
+        Some(ScatterRecord {
+            attenuation: self.albedo * self.factor(&wo, &wi),
+            scattered: Ray::new(rec.p, uvw.local(&wi)),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = uvw.to_local(&direction.unit_vector());
+        if wi.z() <= 0. {
+            return v3!(0., 0., 0.);
+        }
+        self.albedo * self.factor(&wo, &wi) * wi.z() / PI
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let cosine = rec.normal.dot(&direction.unit_vector());
+        if cosine < 0. {0.} else {cosine / PI}
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
     }
 }
 
@@ -43,26 +191,270 @@ impl Metal {
             fuzz: if f < 1. {f} else {1.},
         }
     }
+
+    /// Density of `scatter` picking `direction`: the reflected direction is
+    /// jittered by a ball of radius `fuzz`, so this is the share of that ball's
+    /// volume lying along `direction`.
+    fn fuzz_pdf(&self, reflected: &Vec3, direction: &Vec3) -> f64 {
+        if self.fuzz <= 0. {
+            return 0.;
+        }
+        let b = direction.unit_vector().dot(reflected);
+        let discriminant = b * b - reflected.length_squared() + self.fuzz * self.fuzz;
+        if discriminant <= 0. {
+            return 0.;
+        }
+        let sqrtd = discriminant.sqrt();
+        let t_near = (b - sqrtd).max(0.);
+        let t_far = b + sqrtd;
+        if t_far <= 0. {
+            return 0.;
+        }
+        (t_far.powi(3) - t_near.powi(3)) / (4. * PI * self.fuzz.powi(3))
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let reflected = reflect(&r_in.direction().unit_vector(), &rec.normal);
+        let scattered = Ray::new(rec.p, reflected + self.fuzz * random_in_unit_sphere());
+        if scattered.direction().dot(&rec.normal) <= 0. {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.albedo,
+            scattered,
+            is_specular: self.fuzz <= 0.,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        // rays jittered below the surface are absorbed, so the lobe is
+        // exactly albedo times the sampling density
+        self.albedo * self.scattering_pdf(r_in, rec, direction)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        if direction.dot(&rec.normal) <= 0. {
+            return 0.;
+        }
         let reflected = reflect(&r_in.direction().unit_vector(), &rec.normal);
-        *scattered = Ray::new(rec.p, reflected + self.fuzz * random_in_unit_sphere());
-        *attenuation = self.albedo;
-        scattered.direction().dot(&rec.normal) > 0.
+        self.fuzz_pdf(&reflected, direction)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+/// Rough metal with a GGX microfacet distribution and the Fresnel
+/// reflectance of a complex index of refraction `eta` + i `k`, given per
+/// colour channel. The two roughnesses go from 0 for a mirror to 1, along
+/// the direction the surface's `u` coordinate grows in and across it, and
+/// are squared into GGX alphas so that they look evenly spaced. Shapes
+/// without surface coordinates leave that direction arbitrary, so keep the
+/// two equal on them.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+    film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    pub fn new(eta: &Color, k: &Color, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            eta: *eta,
+            k: *k,
+            distribution: Ggx::new(roughness_u * roughness_u, roughness_v * roughness_v),
+            film: None,
+        }
+    }
+
+    /// Coat with a transparent film `thickness` nanometres thick, like
+    /// anodised or heat tinted metal.
+    pub fn with_thin_film(mut self, thickness: f64, ior: f64) -> Self {
+        self.film = Some(ThinFilm { thickness, ior });
+        self
+    }
+
+    fn fresnel(&self, cos_theta: f64, wavelength: Option<f64>) -> Color {
+        match self.film {
+            Some(film) => film.reflectance_at(cos_theta, 1., &self.eta, &self.k, wavelength),
+            None => fresnel_conductor(cos_theta, &self.eta, &self.k),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Self::new(&v3!(0.143, 0.374, 1.442), &v3!(3.983, 2.385, 1.603), roughness, roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Self::new(&v3!(0.200, 0.924, 1.102), &v3!(3.912, 2.452, 2.142), roughness, roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Self::new(&v3!(1.657, 0.880, 0.521), &v3!(9.224, 6.270, 4.837), roughness, roughness)
+    }
+
+    /// Outgoing and incoming directions in the shading frame of `rec`, whose
+    /// tangent is the direction `u` grows in.
+    fn local(r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> (Onb, Vec3, Vec3) {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = uvw.to_local(&direction.unit_vector());
+        (uvw, wo, wi)
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (uvw, wo, _) = Self::local(r_in, rec, &rec.normal);
+        if wo.z() <= 0. {
+            return None;
+        }
+        if self.distribution.is_smooth() {
+            return Some(ScatterRecord {
+                attenuation: self.fresnel(wo.z(), r_in.wavelength),
+                scattered: Ray::new(rec.p, uvw.local(&v3!(-wo.x(), -wo.y(), wo.z()))),
+                is_specular: true,
+            });
+        }
+        let wm = self.distribution.sample_visible(&wo);
+        let wi = reflect(&-wo, &wm);
+        if wi.z() <= 0. {
+            return None;
+        }
+        // f * cos / pdf, with most of the terms cancelling
+        let fresnel = self.fresnel(wo.dot(&wm), r_in.wavelength);
+        Some(ScatterRecord {
+            attenuation: fresnel * self.distribution.g(&wo, &wi) / self.distribution.g1(&wo),
+            scattered: Ray::new(rec.p, uvw.local(&wi)),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let (_, wo, wi) = Self::local(r_in, rec, direction);
+        if wo.z() <= 0. || wi.z() <= 0. || self.distribution.is_smooth() {
+            return v3!(0., 0., 0.);
+        }
+        let wm = (wo + wi).unit_vector();
+        let fresnel = self.fresnel(wo.dot(&wm), r_in.wavelength);
+        fresnel * self.distribution.d(&wm) * self.distribution.g(&wo, &wi) / (4. * wo.z())
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let (_, wo, wi) = Self::local(r_in, rec, direction);
+        if wo.z() <= 0. || wi.z() <= 0. || self.distribution.is_smooth() {
+            return 0.;
+        }
+        let wm = (wo + wi).unit_vector();
+        self.distribution.visible_d(&wo, &wm) / (4. * wo.dot(&wm))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.fresnel(1., None)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.film.is_some()
+    }
+}
+
+/// How the index of refraction of a dielectric depends on wavelength.
+enum Ior {
+    Constant(f64),
+    /// `a + b / λ²`, with `b` in µm²
+    Cauchy { a: f64, b: f64 },
+    /// `n² = 1 + Σ b λ² / (λ² - c)`, with `c` in µm²
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    fn at(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.).powi(2);
+        match self {
+            Ior::Constant(ir) => *ir,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt(),
+        }
     }
 }
 
 pub struct Dielectric {
-    ir: f64,
+    ir: Ior,
+    /// extinction coefficient inside, per channel: light travelling a
+    /// distance d keeps exp(-absorption * d) of itself
+    absorption: Color,
+    film: Option<ThinFilm>,
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Self {
+        Self::with_ior(Ior::Constant(ir))
+    }
+
+    fn with_ior(ir: Ior) -> Self {
         Self {
             ir,
+            absorption: v3!(0., 0., 0.),
+            film: None,
+        }
+    }
+
+    /// Index of refraction following Cauchy's equation `a + b / λ²`, with
+    /// the wavelength in micrometres.
+    pub fn cauchy(a: f64, b: f64) -> Self {
+        Self::with_ior(Ior::Cauchy { a, b })
+    }
+
+    /// Index of refraction following the Sellmeier equation, with the `c`
+    /// coefficients in square micrometres, as glass catalogues give them.
+    pub fn sellmeier(b: [f64; 3], c: [f64; 3]) -> Self {
+        Self::with_ior(Ior::Sellmeier { b, c })
+    }
+
+    /// Schott N-BK7, the common crown glass of lenses.
+    pub fn bk7() -> Self {
+        Self::sellmeier([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653])
+    }
+
+    /// Schott SF11, a dense flint glass that spreads colours widely.
+    pub fn sf11() -> Self {
+        Self::sellmeier([1.73759695, 0.313747346, 1.89878101], [0.013188707, 0.0623068142, 155.23629])
+    }
+
+    /// Index of refraction for the wavelength `r_in` carries.
+    fn ir(&self, r_in: &Ray) -> f64 {
+        self.ir.at(r_in.wavelength.unwrap_or(LAMBDA_RGB))
+    }
+
+    /// Tint the inside following the Beer–Lambert law, so that light keeps
+    /// `exp(-absorption * distance)` of itself after travelling `distance`.
+    pub fn with_absorption(mut self, absorption: &Color) -> Self {
+        self.absorption = *absorption;
+        self
+    }
+
+    /// Coat with a transparent film `thickness` nanometres thick. Over an
+    /// index of 1 this makes a soap bubble.
+    pub fn with_thin_film(mut self, thickness: f64, ior: f64) -> Self {
+        self.film = Some(ThinFilm { thickness, ior });
+        self
+    }
+
+    /// Share of the light surviving the way to `rec` through the inside.
+    fn absorption(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face {
+            return v3!(1., 1., 1.);
         }
+        let distance = rec.t * r_in.direction().length();
+        v3!(
+            (-self.absorption.x() * distance).exp(),
+            (-self.absorption.y() * distance).exp(),
+            (-self.absorption.z() * distance).exp()
+        )
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -74,19 +466,819 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
-        *attenuation = v3!(1., 1., 1.);
-        let refraction_ratio = if rec.front_face {1. / self.ir} else {self.ir};
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let ir = self.ir(r_in);
+        let refraction_ratio = if rec.front_face {1. / ir} else {ir};
         let unit_direction = r_in.direction().unit_vector();
         let cos_theta = (-unit_direction).dot(&rec.normal).min(1.);
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        if let Some(film) = self.film {
+            // the film reflects each colour differently, so pick by the
+            // average and weight the colours back
+            let (eta_i, eta_t) = if rec.front_face {(1., ir)} else {(ir, 1.)};
+            let reflectance = film.reflectance_at(cos_theta, eta_i, &v3!(eta_t, eta_t, eta_t), &v3!(0., 0., 0.), r_in.wavelength);
+            let p = (reflectance.x() + reflectance.y() + reflectance.z()) / 3.;
+            let (direction, weight) = if p >= 1. || random_double() < p {
+                (reflect(&unit_direction, &rec.normal), reflectance / p)
+            } else {
+                (refract(&unit_direction, &rec.normal, refraction_ratio), (v3!(1., 1., 1.) - reflectance) / (1. - p))
+            };
+            return Some(ScatterRecord {
+                attenuation: weight * self.absorption(r_in, rec),
+                scattered: Ray::new(rec.p, direction),
+                is_specular: true,
+            });
+        }
         let direction = if refraction_ratio * sin_theta > 1. || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double() {
             reflect(&unit_direction, &rec.normal)
         } else {
             refract(&unit_direction, &rec.normal, refraction_ratio)
         };
 
-        *scattered = Ray::new(rec.p, direction);
-        return true;
+        Some(ScatterRecord {
+            attenuation: self.absorption(r_in, rec),
+            scattered: Ray::new(rec.p, direction),
+            is_specular: true,
+        })
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        v3!(1., 1., 1.)
+    }
+
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        // the chance `scatter` refracts
+        let ir = self.ir(r_in);
+        let cos_theta = (-r_in.direction().unit_vector()).dot(&rec.normal).min(1.);
+        if let Some(film) = self.film {
+            let (eta_i, eta_t) = if rec.front_face {(1., ir)} else {(ir, 1.)};
+            return v3!(1., 1., 1.) - film.reflectance_at(cos_theta, eta_i, &v3!(eta_t, eta_t, eta_t), &v3!(0., 0., 0.), r_in.wavelength);
+        }
+        let refraction_ratio = if rec.front_face {1. / ir} else {ir};
+        if refraction_ratio * (1. - cos_theta * cos_theta).sqrt() > 1. {
+            return v3!(0., 0., 0.);
+        }
+        let t = 1. - Dielectric::reflectance(cos_theta, refraction_ratio);
+        v3!(t, t, t)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        !matches!(self.ir, Ior::Constant(_)) || self.film.is_some()
+    }
+}
+
+/// Translucent material such as wax, marble or skin: a smooth dielectric
+/// boundary around a scattering medium. Light wanders inside from one
+/// scattering event to the next, so it leaves somewhere else than it went
+/// in. Needs a closed object.
+pub struct Subsurface {
+    boundary: Dielectric,
+    medium: Medium,
+}
+
+impl Subsurface {
+    pub fn new(ir: f64, sigma_a: &Color, sigma_s: &Color, g: f64) -> Self {
+        Self {
+            boundary: Dielectric::new(ir),
+            medium: Medium {
+                sigma_a: *sigma_a,
+                sigma_s: *sigma_s,
+                g,
+            },
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.boundary.scatter(r_in, rec)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.medium.sigma_s / self.medium.sigma_t()
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+}
+
+/// Frosted glass: a dielectric interface with a GGX distribution of
+/// microfacets that both reflect and refract, after Walter et al.,
+/// "Microfacet Models for Refraction through Rough Surfaces". `roughness` is
+/// as for `Conductor`.
+pub struct RoughDielectric {
+    ir: f64,
+    distribution: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> Self {
+        Self {
+            ir,
+            distribution: Ggx::new(roughness * roughness, roughness * roughness),
+        }
+    }
+
+    /// Index of refraction of the far side of the surface over the near one.
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {self.ir} else {1. / self.ir}
+    }
+
+    /// Microfacet normal that turns `wo` into `wi`, on the side of `wo`,
+    /// and whether that is a reflection.
+    fn half_vector(&self, wo: &Vec3, wi: &Vec3, eta: f64) -> Option<(Vec3, bool)> {
+        let reflection = wi.z() > 0.;
+        let wm = if reflection { *wo + *wi } else { *wo + eta * *wi };
+        if wm.near_zero() {
+            return None;
+        }
+        let wm = wm.unit_vector();
+        let wm = if wm.z() < 0. { -wm } else { wm };
+        // microfacets seen from behind take no part
+        if wo.dot(&wm) <= 0. || wi.dot(&wm) * wi.z() <= 0. {
+            return None;
+        }
+        Some((wm, reflection))
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let eta = self.eta(rec);
+        let smooth = self.distribution.is_smooth();
+        let wm = if smooth { v3!(0., 0., 1.) } else { self.distribution.sample_visible(&wo) };
+        // picking reflection with the Fresnel probability cancels it out
+        let wi = if random_double() < fresnel_dielectric(wo.dot(&wm), eta) {
+            reflect(&-wo, &wm)
+        } else {
+            microfacet::refract(&wo, &wm, eta)?
+        };
+        if smooth {
+            return Some(ScatterRecord {
+                attenuation: v3!(1., 1., 1.),
+                scattered: Ray::new(rec.p, uvw.local(&wi)),
+                is_specular: true,
+            });
+        }
+        // reflections must stay above the surface and refractions below
+        if (wi.z() > 0.) != (wi.dot(&wm) > 0.) {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: v3!(1., 1., 1.) * self.distribution.g(&wo, &wi) / self.distribution.g1(&wo),
+            scattered: Ray::new(rec.p, uvw.local(&wi)),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return v3!(0., 0., 0.);
+        }
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = uvw.to_local(&direction.unit_vector());
+        let eta = self.eta(rec);
+        let (wm, reflection) = match self.half_vector(&wo, &wi, eta) {
+            Some(h) => h,
+            None => return v3!(0., 0., 0.),
+        };
+        let fresnel = fresnel_dielectric(wo.dot(&wm), eta);
+        let dg = self.distribution.d(&wm) * self.distribution.g(&wo, &wi);
+        let f = if reflection {
+            fresnel * dg / (4. * wo.z())
+        } else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+            (1. - fresnel) * dg * (wi.dot(&wm) * wo.dot(&wm)).abs() / (wo.z() * denom * denom)
+        };
+        v3!(f, f, f)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.;
+        }
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = uvw.to_local(&direction.unit_vector());
+        let eta = self.eta(rec);
+        let (wm, reflection) = match self.half_vector(&wo, &wi, eta) {
+            Some(h) => h,
+            None => return 0.,
+        };
+        let fresnel = fresnel_dielectric(wo.dot(&wm), eta);
+        let visible = self.distribution.visible_d(&wo, &wm);
+        if reflection {
+            fresnel * visible / (4. * wo.dot(&wm))
+        } else {
+            let denom = wi.dot(&wm) + wo.dot(&wm) / eta;
+            (1. - fresnel) * visible * wi.dot(&wm).abs() / (denom * denom)
+        }
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        v3!(1., 1., 1.)
+    }
+
+    fn transmittance(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        // what the smooth surface would let through, which the rough one
+        // lets through about as well
+        let cos_theta = (-r_in.direction().unit_vector()).dot(&rec.normal);
+        let t = 1. - fresnel_dielectric(cos_theta, self.eta(rec));
+        v3!(t, t, t)
+    }
+}
+
+/// A coating, such as lacquer or varnish, over a base, made of any two
+/// materials. The coating is thin enough that light crossing it comes out
+/// where it went in and in the same direction, so the base sees the same
+/// directions as the coating. Light reflected back down by the underside of
+/// the coating is lost rather than bounced again, which keeps the pair from
+/// ever reflecting more than comes in.
+pub struct Layered {
+    coating: Arc<dyn Material>,
+    base: Arc<dyn Material>,
+}
+
+impl Layered {
+    pub fn new(coating: Arc<dyn Material>, base: Arc<dyn Material>) -> Self {
+        Self { coating, base }
+    }
+
+    /// Chance of sampling the coating's reflection rather than the base:
+    /// what the coating doesn't let through.
+    fn reflect_probability(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let t = self.coating.transmittance(r_in, rec);
+        (1. - (t.x() + t.y() + t.z()) / 3.).clamp(0., 1.)
+    }
+
+    /// Share of the light reaching the base through the coating and leaving
+    /// along `direction` through it again.
+    fn through(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let out = Ray {
+            wavelength: r_in.wavelength,
+            ..Ray::new(rec.p + *direction, -*direction)
+        };
+        self.coating.transmittance(r_in, rec) * self.coating.transmittance(&out, rec)
+    }
+}
+
+impl Material for Layered {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let reflect = self.reflect_probability(r_in, rec);
+        let (srec, chance) = if random_double() < reflect {
+            // what the coating lets through is the other branch's to sample
+            let srec = self.coating.scatter(r_in, rec)?;
+            if srec.scattered.direction().dot(&rec.normal) <= 0. {
+                return None;
+            }
+            (srec, reflect)
+        } else {
+            let srec = self.base.scatter(r_in, rec)?;
+            let direction = *srec.scattered.direction();
+            if direction.dot(&rec.normal) <= 0. {
+                return None;
+            }
+            let attenuation = self.through(r_in, rec, &direction) * srec.attenuation;
+            (ScatterRecord { attenuation, ..srec }, 1. - reflect)
+        };
+        if srec.is_specular {
+            return Some(ScatterRecord {
+                attenuation: srec.attenuation / chance,
+                ..srec
+            });
+        }
+        // weighted against the other branch's density too
+        let direction = *srec.scattered.direction();
+        let pdf = self.scattering_pdf(r_in, rec, &direction);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.eval(r_in, rec, &direction) / pdf,
+            ..srec
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        if direction.dot(&rec.normal) <= 0. {
+            return v3!(0., 0., 0.);
+        }
+        let coating = self.coating.eval(r_in, rec, direction);
+        let through = self.through(r_in, rec, direction);
+        if through.near_zero() {
+            return coating;
+        }
+        coating + through * self.base.eval(r_in, rec, direction)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        if direction.dot(&rec.normal) <= 0. {
+            return 0.;
+        }
+        let reflect = self.reflect_probability(r_in, rec);
+        reflect * self.coating.scattering_pdf(r_in, rec, direction) + (1. - reflect) * self.base.scattering_pdf(r_in, rec, direction)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.coating.is_dispersive() || self.base.is_dispersive()
+    }
+}
+
+/// One of two materials at each hit, `b` with the chance given by `weight`
+/// there and `a` otherwise, which blends them on average. A texture as the
+/// weight masks one material with the other.
+pub struct MixMaterial {
+    a: Arc<dyn Material>,
+    b: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: Arc<dyn Texture>) -> Self {
+        Self { a, b, weight }
+    }
+
+    pub fn constant(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: f64) -> Self {
+        Self::new(a, b, Arc::new(Constant(weight)))
+    }
+
+    fn weight(&self, rec: &HitRecord) -> f64 {
+        self.weight.value(rec).clamp(0., 1.)
+    }
+
+    fn blend<T: std::ops::Add<Output = T> + std::ops::Mul<f64, Output = T>>(&self, rec: &HitRecord, a: impl FnOnce() -> T, b: impl FnOnce() -> T) -> T {
+        let w = self.weight(rec);
+        // skip the work for whichever material isn't there
+        if w <= 0. {
+            a()
+        } else if w >= 1. {
+            b()
+        } else {
+            a() * (1. - w) + b() * w
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if random_double() < self.weight(rec) {
+            self.b.scatter(r_in, rec)
+        } else {
+            self.a.scatter(r_in, rec)
+        }
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        self.blend(rec, || self.a.eval(r_in, rec, direction), || self.b.eval(r_in, rec, direction))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        self.blend(rec, || self.a.scattering_pdf(r_in, rec, direction), || self.b.scattering_pdf(r_in, rec, direction))
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.blend(rec, || self.a.emitted(r_in, rec), || self.b.emitted(r_in, rec))
+    }
+
+    fn emitted_spectrum(&self, r_in: &Ray, rec: &HitRecord, wavelengths: &Wavelengths) -> Color {
+        self.blend(rec, || self.a.emitted_spectrum(r_in, rec, wavelengths), || self.b.emitted_spectrum(r_in, rec, wavelengths))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.blend(rec, || self.a.albedo(rec), || self.b.albedo(rec))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.a.is_emissive() || self.b.is_emissive()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.a.is_dispersive() || self.b.is_dispersive()
+    }
+}
+
+/// Emits `emit` from the front face of whatever it is attached to and
+/// absorbs all incoming light.
+pub struct DiffuseLight {
+    emit: Color,
+    /// temperature of a black body, whose spectrum spectral rendering uses
+    /// rather than one lifted from `emit`
+    temperature: Option<f64>,
+}
+
+impl DiffuseLight {
+    pub fn new(c: &Color) -> Self {
+        Self {
+            emit: *c,
+            temperature: None,
+        }
+    }
+
+    /// A black body at `temperature` Kelvin, with radiance `luminance`.
+    pub fn blackbody(temperature: f64, luminance: f64) -> Self {
+        Self {
+            temperature: Some(temperature),
+            ..Self::new(&(luminance * blackbody(temperature)))
+        }
+    }
+
+    /// Colour `c` bright enough that a surface of `area` gives off `power`
+    /// in all, weighted by the eye's sensitivity as luminance is. A black
+    /// `c` gives off nothing.
+    pub fn with_power(c: &Color, power: f64, area: f64) -> Self {
+        let luminance = c.luminance();
+        if luminance > 0. {
+            // a Lambertian emitter of radiance L gives off pi L per unit area
+            Self::new(&(*c / luminance * power / (PI * area)))
+        } else {
+            Self::new(&v3!(0., 0., 0.))
+        }
+    }
+
+    /// `with_power`, from the lumens on a lamp's box.
+    pub fn with_lumens(c: &Color, lumens: f64, area: f64) -> Self {
+        Self::with_power(c, lumens / LUMENS_PER_WATT, area)
+    }
+
+    /// `blackbody`, from the lumens on a lamp's box.
+    pub fn blackbody_lumens(temperature: f64, lumens: f64, area: f64) -> Self {
+        Self {
+            temperature: Some(temperature),
+            ..Self::with_lumens(&blackbody(temperature), lumens, area)
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord) -> Color {
+        if rec.front_face {self.emit} else {v3!(0., 0., 0.)}
+    }
+
+    fn emitted_spectrum(&self, r_in: &Ray, rec: &HitRecord, wavelengths: &Wavelengths) -> Color {
+        match self.temperature {
+            Some(temperature) if rec.front_face => {
+                // Planck's law at the luminance `emit` has
+                let scale = self.emit.luminance() / planck_luminance(temperature);
+                let [a, b, c] = wavelengths.lambda.map(|lambda| scale * planck(lambda, temperature));
+                v3!(a, b, c)
+            }
+            _ => wavelengths.lift(&self.emitted(r_in, rec)),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::{random_double, PI};
+    use vec3::{v3, Vec3};
+
+    use crate::{
+        hittable::{HitRecord, Hittable},
+        microfacet::ThinFilm,
+        ray::Ray,
+        spectrum::{cie_xyz, lift, planck, Wavelengths},
+        sphere::Sphere,
+        texture::Checker,
+    };
+
+    use super::{Conductor, Dielectric, DiffuseLight, Lambertian, Layered, Material, Metal, MixMaterial, OrenNayar, RoughDielectric};
+
+    fn brushed_gold() -> Conductor {
+        Conductor::new(&v3!(0.143, 0.374, 1.442), &v3!(3.983, 2.385, 1.603), 0.2, 0.7)
+    }
+
+    #[test]
+    fn test_anisotropy_follows_the_tangent() {
+        let material = brushed_gold();
+        let ray = Ray::new(v3!(0.6, 0.8, 0.), v3!(-0.6, -0.8, 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &material);
+        let along_x = rec.clone().with_tangent(v3!(2., 0., 0.));
+        let along_z = rec.with_tangent(v3!(0., 0., 2.));
+        // the same lobe turned a quarter around the normal along with the
+        // tangent, and a different one for the same directions
+        let direction = v3!(-0.5, 0.7, 0.3);
+        let turned = |v: Vec3| v3!(-v.z(), v.y(), v.x());
+        let turned_ray = Ray::new(turned(ray.orig), turned(ray.dir));
+        let turned_rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), turned_ray, &material).with_tangent(v3!(0., 0., 2.));
+        let a = material.eval(&ray, &along_x, &direction);
+        assert!((a - material.eval(&turned_ray, &turned_rec, &turned(direction))).length() < 1e-9 * a.length());
+        assert!((a - material.eval(&ray, &along_z, &direction)).length() > 0.1 * a.length());
+    }
+
+    #[test]
+    fn test_anisotropy_has_no_seam() {
+        // on a sphere, either side of where a frame built from the normal
+        // alone would swap its helper axis
+        let material = Arc::new(brushed_gold());
+        let sphere = Sphere::new(v3!(0., 0., 0.), 1., material.clone());
+        let eval = |nx: f64| {
+            let n = v3!(nx, (1. - nx * nx).sqrt() * 0.6, (1. - nx * nx).sqrt() * 0.8);
+            // lit and seen from well off the normal, where the lobe's
+            // stretch shows
+            let p = 1.0001 * n;
+            let ray = Ray::new(p + v3!(0., 0.3, 1.), -v3!(0., 0.3, 1.));
+            let rec = sphere.hit(&ray, 0.001, utils::INFINITY).unwrap();
+            assert!((rec.p - n).length() < 1e-3);
+            material.eval(&ray, &rec, &v3!(0.9, 0.5, -0.2))
+        };
+        let (a, b) = (eval(0.8999), eval(0.9001));
+        assert!((a - b).length() < 0.01 * a.length(), "{:?} {:?}", a, b);
+    }
+
+    #[test]
+    fn test_beer_lambert_absorption() {
+        let sigma = v3!(0.1, 0.5, 2.);
+        let glass = Dielectric::new(1.5).with_absorption(&sigma);
+        // from inside, 3 along a ray whose direction is 1.5 long
+        let ray = Ray::new(v3!(0., -3., 0.), v3!(0., 1.5, 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 2., v3!(0., 1., 0.), ray, &glass);
+        assert!(!rec.front_face);
+        let expected = v3!((-0.3_f64).exp(), (-1.5_f64).exp(), (-6_f64).exp());
+        for _ in 0..10 {
+            // whether it is reflected or refracted
+            let srec = glass.scatter(&ray, &rec).unwrap();
+            assert!((srec.attenuation - expected).length() < 1e-12, "{:?}", srec.attenuation);
+        }
+        // nothing is lost on the way in
+        let ray = Ray::new(v3!(0., 3., 0.), v3!(0., -1.5, 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 2., v3!(0., 1., 0.), ray, &glass);
+        assert_eq!(glass.scatter(&ray, &rec).unwrap().attenuation, v3!(1., 1., 1.));
+    }
+
+    #[test]
+    fn test_thin_film_follows_the_wavelength() {
+        let (eta, k) = (v3!(0.143, 0.374, 1.442), v3!(3.983, 2.385, 1.603));
+        let film = ThinFilm { thickness: 300., ior: 1.33 };
+        let gold = Conductor::new(&eta, &k, 0., 0.).with_thin_film(300., 1.33);
+        assert!(gold.is_dispersive());
+        let mut ray = Ray::new(v3!(0., 2., 0.), v3!(0., -1., 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 2., v3!(0., 1., 0.), ray, &gold);
+        assert_eq!(gold.scatter(&ray, &rec).unwrap().attenuation, film.reflectance_rgb(1., 1., &eta, &k));
+        for lambda in [420., 500., 580., 660.] {
+            ray.wavelength = Some(lambda);
+            let r = film.reflectance(1., 1., lift(&eta, lambda), lift(&k, lambda), lambda);
+            assert_eq!(gold.scatter(&ray, &rec).unwrap().attenuation, v3!(r, r, r));
+        }
+
+        // a soap bubble reflects as much as the film does at the ray's wavelength
+        let bubble = Dielectric::new(1.).with_thin_film(300., 1.33);
+        assert!(bubble.is_dispersive());
+        let reflected = |lambda: f64| {
+            let ray = Ray { wavelength: Some(lambda), ..ray };
+            let rec = HitRecord::new(v3!(0., 0., 0.), 2., v3!(0., 1., 0.), ray, &bubble);
+            let n = 20000;
+            (0..n).filter(|_| bubble.scatter(&ray, &rec).unwrap().scattered.direction().y() > 0.).count() as f64 / n as f64
+        };
+        let expected = |lambda: f64| film.reflectance(1., 1., 1., 0., lambda);
+        for lambda in [420., 530., 640.] {
+            assert!((reflected(lambda) - expected(lambda)).abs() < 0.015, "{} {}", reflected(lambda), expected(lambda));
+        }
+    }
+
+    #[test]
+    fn test_layered() {
+        let white = || Arc::new(Lambertian::new(&v3!(1., 1., 1.)));
+        let materials = [
+            Layered::new(Arc::new(Dielectric::new(1.5)), white()),
+            Layered::new(Arc::new(RoughDielectric::new(1.5, 0.3)), white()),
+            Layered::new(Arc::new(RoughDielectric::new(1.5, 0.6)), Arc::new(Conductor::gold(0.5))),
+        ];
+        for material in &materials {
+            for cos in [1., 0.5, 0.1_f64] {
+                let ray = Ray::new(v3!((1. - cos * cos).sqrt(), cos, 0.), v3!(-(1. - cos * cos).sqrt(), -cos, 0.));
+                let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, material);
+                let (strata, n) = (300, 300 * 300);
+                let mut reflected: Vec3 = v3!(0., 0., 0.);
+                let mut glossy = 0;
+                let mut density = 0.;
+                for i in 0..n {
+                    if let Some(srec) = material.scatter(&ray, &rec) {
+                        reflected = reflected + srec.attenuation;
+                        let direction = srec.scattered.direction();
+                        if !srec.is_specular {
+                            // the weights are what `eval` and the density give
+                            let weight = material.eval(&ray, &rec, direction) / material.scattering_pdf(&ray, &rec, direction);
+                            assert!((srec.attenuation - weight).length() < 1e-9);
+                            glossy += 1;
+                        }
+                    }
+                    let z = 2. * ((i / strata) as f64 + random_double()) / strata as f64 - 1.;
+                    let phi = 2. * PI * ((i % strata) as f64 + random_double()) / strata as f64;
+                    let r = (1. - z * z).sqrt();
+                    density += material.scattering_pdf(&ray, &rec, &v3!(r * phi.cos(), z, r * phi.sin())) * 4. * PI;
+                }
+                // nothing comes out brighter than it went in, even when white
+                let reflected = reflected / n as f64;
+                assert!(reflected.max_component() < 1.01, "{} {:?}", cos, reflected);
+                // and the density is that of the directions sampled, which
+                // at grazing angles the strata only roughly integrate
+                let (glossy, density) = (glossy as f64 / n as f64, density / n as f64);
+                assert!((glossy - density).abs() < 0.04, "{} {} {}", cos, glossy, density);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mix_material() {
+        let paint: Arc<dyn Material> = Arc::new(Lambertian::new(&v3!(0.7, 0.05, 0.05)));
+        let metal: Arc<dyn Material> = Arc::new(Conductor::gold(0.3));
+        let mix = MixMaterial::constant(paint.clone(), metal.clone(), 0.3);
+        let ray = Ray::new(v3!(0.6, 0.8, 0.), v3!(-0.6, -0.8, 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &mix);
+        for direction in [v3!(-0.6, 0.8, 0.), v3!(0., 1., 0.), v3!(0.3, 0.5, -0.8)] {
+            let blend = 0.7 * paint.eval(&ray, &rec, &direction) + 0.3 * metal.eval(&ray, &rec, &direction);
+            assert!((mix.eval(&ray, &rec, &direction) - blend).length() < 1e-12);
+            let pdf = 0.7 * paint.scattering_pdf(&ray, &rec, &direction) + 0.3 * metal.scattering_pdf(&ray, &rec, &direction);
+            assert!((mix.scattering_pdf(&ray, &rec, &direction) - pdf).abs() < 1e-12);
+        }
+        // and `scatter` picks each as often as it weighs
+        let n = 100000;
+        let specular = Metal::new(&v3!(1., 1., 1.), 0.);
+        let mix = MixMaterial::constant(paint.clone(), Arc::new(specular), 0.3);
+        let picked = (0..n).filter(|_| mix.scatter(&ray, &rec).unwrap().is_specular).count() as f64 / n as f64;
+        assert!((picked - 0.3).abs() < 0.01, "{}", picked);
+
+        // a texture masks one with the other
+        let masked = MixMaterial::new(paint.clone(), metal.clone(), Arc::new(Checker { frequency: 1. }));
+        let direction = v3!(0., 1., 0.);
+        let at = |u: f64| masked.eval(&ray, &rec.clone().with_uv(u, 0.5), &direction);
+        assert_eq!(at(0.5), paint.eval(&ray, &rec, &direction));
+        assert_eq!(at(1.5), metal.eval(&ray, &rec, &direction));
+    }
+
+    #[test]
+    fn test_oren_nayar() {
+        let albedo = v3!(0.9, 0.5, 0.2);
+        let smooth = OrenNayar::new(&albedo, 0.);
+        let lambertian = Lambertian::new(&albedo);
+        for cos in [1., 0.5, 0.1_f64] {
+            let ray = Ray::new(v3!((1. - cos * cos).sqrt(), cos, 0.), v3!(-(1. - cos * cos).sqrt(), -cos, 0.));
+            let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &smooth);
+            for direction in [v3!(-0.6, 0.8, 0.), v3!(0., 1., 0.), v3!(0.3, 0.5, -0.8), v3!(0., -1., 0.)] {
+                let (a, b) = (smooth.eval(&ray, &rec, &direction), lambertian.eval(&ray, &rec, &direction));
+                assert!((a - b).length() < 1e-12, "{:?} {:?}", a, b);
+            }
+            // rough ones never reflect more than their albedo
+            for sigma in [20., 45., 90.] {
+                let rough = OrenNayar::new(&albedo, sigma);
+                let n = 100000;
+                let reflected = (0..n).map(|_| rough.scatter(&ray, &rec).unwrap().attenuation).fold(v3!(0., 0., 0.), |a, b| a + b) / n as f64;
+                assert!(reflected.x() < albedo.x() && reflected.y() < albedo.y() && reflected.z() < albedo.z(), "{} {} {:?}", cos, sigma, reflected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_metal() {
+        let metal = Metal::new(&v3!(0.9, 0.6, 0.3), 0.5);
+        let ray = Ray::new(v3!(-0.8, 0.6, 0.), v3!(0.8, -0.6, 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &metal);
+        // directions binned into cells of equal area, with the poles off the
+        // plane of incidence
+        let (rows, columns) = (8, 16);
+        let cell = |d: &Vec3| {
+            let d = d.unit_vector();
+            let row = (((1. - d.z()) / 2. * rows as f64) as usize).min(rows - 1);
+            let column = ((d.y().atan2(d.x()) + PI) / (2. * PI) * columns as f64) as usize % columns;
+            row * columns + column
+        };
+
+        // how often scatter lands in each cell
+        let n = 400_000;
+        let mut found = vec![0.; rows * columns];
+        for _ in 0..n {
+            if let Some(srec) = metal.scatter(&ray, &rec) {
+                let direction = srec.scattered.direction();
+                let weight = metal.eval(&ray, &rec, direction) / metal.scattering_pdf(&ray, &rec, direction);
+                assert!((srec.attenuation - weight).length() < 1e-9, "{:?} {:?}", srec.attenuation, weight);
+                found[cell(direction)] += 1. / n as f64;
+            }
+        }
+        // and what the density says, from a finer grid of the same cells
+        let (fine_rows, fine_columns) = (50 * rows, 50 * columns);
+        let mut expected = vec![0.; rows * columns];
+        for i in 0..fine_rows {
+            let z = 1. - 2. * (i as f64 + 0.5) / fine_rows as f64;
+            let r = (1. - z * z).sqrt();
+            for j in 0..fine_columns {
+                let phi = 2. * PI * (j as f64 + 0.5) / fine_columns as f64 - PI;
+                let d = v3!(r * phi.cos(), r * phi.sin(), z);
+                expected[cell(&d)] += metal.scattering_pdf(&ray, &rec, &d) * 4. * PI / (fine_rows * fine_columns) as f64;
+            }
+        }
+        for (found, expected) in found.iter().zip(&expected) {
+            assert!((found - expected).abs() < 3e-3, "{} {}", found, expected);
+        }
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // blue bends more than red, by catalogue amounts at the d line
+        for (glass, nd) in [(Dielectric::bk7(), 1.5168), (Dielectric::sf11(), 1.7847), (Dielectric::cauchy(1.5046, 0.0042), 1.5168)] {
+            let (blue, red) = (glass.ir.at(450.), glass.ir.at(650.));
+            assert!(blue > red && red > 1., "{} {}", blue, red);
+            assert!((glass.ir.at(587.6) - nd).abs() < 1e-3, "{}", glass.ir.at(587.6));
+            assert!(glass.is_dispersive());
+        }
+        assert!(!Dielectric::new(1.5).is_dispersive());
+        // sf11 spreads colours further than bk7
+        let spread = |glass: Dielectric| glass.ir.at(450.) - glass.ir.at(650.);
+        assert!(spread(Dielectric::sf11()) > 2. * spread(Dielectric::bk7()));
+    }
+
+    #[test]
+    fn test_rough_dielectric() {
+        let glass = RoughDielectric::new(1.5, 0.5);
+        for front_face in [true, false] {
+            for cos in [1., 0.1_f64] {
+                let ray = Ray::new(v3!((1. - cos * cos).sqrt(), cos, 0.), v3!(-(1. - cos * cos).sqrt(), -cos, 0.));
+                let outward = if front_face {v3!(0., 1., 0.)} else {v3!(0., -1., 0.)};
+                let rec = HitRecord::new(v3!(0., 0., 0.), 1., outward, ray, &glass);
+                assert_eq!(rec.front_face, front_face);
+
+                let n = 200000;
+                let (mut total, mut refracted, mut valid) = (0., 0, 0);
+                for _ in 0..n {
+                    let srec = match glass.scatter(&ray, &rec) {
+                        Some(srec) => srec,
+                        None => continue,
+                    };
+                    let direction = *srec.scattered.direction();
+                    let expected = glass.eval(&ray, &rec, &direction) / glass.scattering_pdf(&ray, &rec, &direction);
+                    assert!((srec.attenuation - expected).length() < 1e-9, "{:?} {:?}", srec.attenuation, expected);
+                    refracted += (direction.y() < 0.) as u32;
+                    valid += 1;
+                    total += srec.attenuation.x();
+                }
+                assert!(refracted > 0, "{} {}", front_face, cos);
+                assert!(total / n as f64 <= 1. + 5e-3, "{} {} {}", front_face, cos, total / n as f64);
+
+                // summed over cells of equal area, with the poles off the plane
+                // of incidence, the density adds up to what isn't lost to
+                // shadowing
+                let (rows, columns) = (1000, 400);
+                let mut integral = 0.;
+                for i in 0..rows {
+                    let z = 1. - 2. * (i as f64 + 0.5) / rows as f64;
+                    let r = (1. - z * z).sqrt();
+                    for j in 0..columns {
+                        let phi = 2. * PI * (j as f64 + 0.5) / columns as f64;
+                        integral += glass.scattering_pdf(&ray, &rec, &v3!(r * phi.cos(), r * phi.sin(), z));
+                    }
+                }
+                integral *= 4. * PI / (rows * columns) as f64;
+                let kept = valid as f64 / n as f64;
+                assert!(kept > 0.85 && (integral - kept).abs() < 0.01, "{} {} {} {}", front_face, cos, integral, kept);
+            }
+        }
+    }
+
+    #[test]
+    fn test_diffuse_light() {
+        let ray = Ray::new(v3!(0., 1., 0.), v3!(0., -1., 0.));
+        let glow = |light: &DiffuseLight| light.emitted(&ray, &HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, light));
+        // no power from black, and no dividing by its luminance either
+        assert_eq!(glow(&DiffuseLight::with_power(&v3!(0., 0., 0.), 100., 1.)), v3!(0., 0., 0.));
+        let light = DiffuseLight::with_power(&v3!(1., 0.5, 0.), 100., 2.);
+        assert!((glow(&light).luminance() - 100. / (PI * 2.)).abs() < 1e-9);
+
+        // spectrally a black body follows Planck's law, as bright to the eye
+        // as in colour
+        let ember = DiffuseLight::blackbody(1500., 0.6);
+        let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &ember);
+        let (steps, mut y, mut white) = (300, 0., 0.);
+        for i in 0..steps {
+            let wavelengths = Wavelengths::sample(i as f64 / steps as f64);
+            let spectrum = ember.emitted_spectrum(&ray, &rec, &wavelengths);
+            for (j, lambda) in wavelengths.lambda.into_iter().enumerate() {
+                let ratio = spectrum[j] / planck(lambda, 1500.);
+                assert!((ratio / (spectrum[0] / planck(wavelengths.hero(), 1500.)) - 1.).abs() < 1e-9);
+                y += spectrum[j] * cie_xyz(lambda).y();
+                white += cie_xyz(lambda).y();
+            }
+        }
+        assert!((y / white - 0.6).abs() < 1e-3, "{}", y / white);
+        // other lights are lifted from their colour
+        let wavelengths = Wavelengths::sample(0.3);
+        let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &light);
+        assert_eq!(light.emitted_spectrum(&ray, &rec, &wavelengths), wavelengths.lift(&glow(&light)));
     }
 }
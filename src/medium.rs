@@ -0,0 +1,158 @@
+//! Homogeneous participating media filling the inside of objects, for
+//! subsurface scattering.
+
+use utils::{random_double, PI};
+use vec3::{v3, Color, Onb, Vec3};
+
+/// Scattering and absorption coefficients, per unit distance and per
+/// channel, with a Henyey-Greenstein phase function.
+#[derive(Debug, Clone, Copy)]
+pub struct Medium {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    /// mean cosine of the scattering angle, from -1 for light mostly thrown
+    /// back through 0 for even scattering to 1 for light mostly going on
+    pub g: f64,
+}
+
+impl Medium {
+    pub fn sigma_t(&self) -> Color {
+        self.sigma_a + self.sigma_s
+    }
+
+    /// Share of the light crossing `distance` without being absorbed or
+    /// scattered.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let sigma_t = self.sigma_t();
+        v3!((-sigma_t.x() * distance).exp(), (-sigma_t.y() * distance).exp(), (-sigma_t.z() * distance).exp())
+    }
+
+    /// Sample a new direction for light travelling along `direction`. The
+    /// phase function is sampled exactly, so it needs no weight.
+    pub fn sample_phase(&self, direction: &Vec3) -> Vec3 {
+        let g = self.g;
+        let u = random_double();
+        let cos = if g.abs() < 1e-3 {
+            1. - 2. * u
+        } else {
+            let s = (1. - g * g) / (1. + g - 2. * g * u);
+            (1. + g * g - s * s) / (2. * g)
+        };
+        let sin = (1. - cos * cos).max(0.).sqrt();
+        let phi = 2. * PI * random_double();
+        Onb::build_from_w(direction).local(&v3!(sin * phi.cos(), sin * phi.sin(), cos))
+    }
+}
+
+/// A random walk through a medium. Distances are all drawn for one channel,
+/// picked at random, and the walk as a whole is weighted against the other
+/// channels having drawn it; weighting each step on its own instead lets the
+/// colour of long walks drift wildly.
+pub struct Walk {
+    pub medium: Medium,
+    channel: usize,
+    /// product of the steps' throughput over their density for `channel`
+    weight: Color,
+    /// product of the steps' densities for each channel over that for
+    /// `channel`
+    ratio: Color,
+}
+
+impl Walk {
+    pub fn new(medium: Medium) -> Self {
+        Self {
+            medium,
+            channel: ((random_double() * 3.) as usize).min(2),
+            weight: v3!(1., 1., 1.),
+            ratio: v3!(1., 1., 1.),
+        }
+    }
+
+    /// Travel up to `max_distance`, returning the distance to the point the
+    /// walk scattered at, or `None` if it made it to the end.
+    pub fn step(&mut self, max_distance: f64) -> Option<f64> {
+        let sigma_t = self.medium.sigma_t();
+        let c = self.channel;
+        let distance = if sigma_t[c] > 0. {
+            -(1. - random_double()).ln() / sigma_t[c]
+        } else {
+            utils::INFINITY
+        };
+        let scattered = distance < max_distance;
+        let d = if scattered { distance } else { max_distance };
+        let transmittance = self.medium.transmittance(d);
+        // density of stopping here, or of getting past the end
+        let (f, pdf) = if scattered {
+            (self.medium.sigma_s * transmittance, sigma_t * transmittance)
+        } else {
+            (transmittance, transmittance)
+        };
+        if pdf[c] <= 0. {
+            // out to infinity through a medium that doesn't stop the channel
+            self.weight = v3!(0., 0., 0.);
+            return None;
+        }
+        self.weight = self.weight * f / pdf[c];
+        self.ratio = self.ratio * pdf / pdf[c];
+        scattered.then_some(distance)
+    }
+
+    /// Throughput of the walk so far.
+    pub fn throughput(&self) -> Color {
+        3. * self.weight / (self.ratio.x() + self.ratio.y() + self.ratio.z())
+    }
+
+    /// Scale the throughput, as russian roulette does.
+    pub fn scale(&mut self, s: f64) {
+        self.weight = self.weight * s;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vec3::{v3, Vec3};
+
+    use super::{Medium, Walk};
+
+    #[test]
+    fn test_phase_sampling() {
+        // Henyey-Greenstein samples have the mean cosine `g`
+        for g in [-0.5, 0., 0.8] {
+            let medium = Medium { sigma_a: v3!(0., 0., 0.), sigma_s: v3!(1., 1., 1.), g };
+            let direction = v3!(0.3, -0.4, 0.5).unit_vector();
+            let n = 200_000;
+            let mean: f64 = (0..n).map(|_| medium.sample_phase(&direction).dot(&direction)).sum::<f64>() / n as f64;
+            assert!((mean - g).abs() < 0.01, "{} {}", g, mean);
+        }
+    }
+
+    #[test]
+    fn test_walk_without_absorption() {
+        // every walk leaves a medium that only scatters in the end, so its
+        // throughput is 1 on average in every channel however unevenly the
+        // channels scatter
+        let medium = Medium { sigma_a: v3!(0., 0., 0.), sigma_s: v3!(1., 4., 10.), g: 0.3 };
+        let n = 40000;
+        let mut mean: Vec3 = v3!(0., 0., 0.);
+        for _ in 0..n {
+            // from the middle of a unit ball
+            let mut walk = Walk::new(medium);
+            let (mut p, mut direction) = (v3!(0., 0., 0.), medium.sample_phase(&v3!(0., 0., 1.)));
+            loop {
+                // distance to the surface of the ball along `direction`
+                let b = p.dot(&direction);
+                let exit = -b + (b * b - p.length_squared() + 1.).sqrt();
+                match walk.step(exit) {
+                    Some(distance) => {
+                        p = p + distance * direction;
+                        direction = medium.sample_phase(&direction);
+                    }
+                    None => break,
+                }
+            }
+            mean = mean + walk.throughput();
+        }
+        let mean = mean / n as f64;
+        assert!((mean - v3!(1., 1., 1.)).length() < 0.03, "{:?}", mean);
+    }
+}
@@ -0,0 +1,291 @@
+//! Measured isotropic BRDFs in the binary format of the MERL database, from
+//! Matusik et al., "A Data-Driven Reflectance Model".
+
+use std::{fs, io, path::Path};
+
+use utils::{random_double, PI};
+use vec3::{random_cosine_direction, v3, Color, Onb, Vec3};
+
+use crate::{
+    hittable::HitRecord,
+    material::{Material, ScatterRecord},
+    ray::Ray,
+};
+
+const THETA_H_RES: usize = 90;
+const THETA_D_RES: usize = 90;
+const PHI_D_RES: usize = 180;
+const SAMPLES: usize = THETA_H_RES * THETA_D_RES * PHI_D_RES;
+/// the tables are stored scaled by these, per channel
+const SCALE: [f64; 3] = [1. / 1500., 1.15 / 1500., 1.66 / 1500.];
+
+/// Resolution of the sampling tables: outgoing elevations, and incoming
+/// elevations and azimuths relative to the outgoing one.
+const SAMPLE_THETA_O: usize = 16;
+const SAMPLE_THETA_I: usize = 32;
+const SAMPLE_PHI_I: usize = 64;
+/// share of samples drawn from the cosine instead of the tables, so that
+/// directions the coarse tables miss are still found
+const COSINE_SHARE: f64 = 0.1;
+
+pub struct Merl {
+    /// reflectance per channel, indexed by `index`
+    table: Vec<[f64; 3]>,
+    /// for each outgoing elevation, the running sum of the chance of each
+    /// cell of incoming directions
+    cdfs: Vec<Vec<f64>>,
+}
+
+/// Table index of the reflectance for `wo` and `wi` in the local frame,
+/// after the half and difference angles of Rusinkiewicz.
+fn index(wo: &Vec3, wi: &Vec3) -> usize {
+    let half = (*wo + *wi).unit_vector();
+    let theta_h = half.z().clamp(-1., 1.).acos();
+    let phi_h = half.y().atan2(half.x());
+    // turn the half vector onto the pole, taking `wi` along
+    let (sin_p, cos_p) = (-phi_h).sin_cos();
+    let d = v3!(wi.x() * cos_p - wi.y() * sin_p, wi.x() * sin_p + wi.y() * cos_p, wi.z());
+    let (sin_t, cos_t) = (-theta_h).sin_cos();
+    let diff = v3!(d.x() * cos_t + d.z() * sin_t, d.y(), -d.x() * sin_t + d.z() * cos_t);
+    let theta_d = diff.z().clamp(-1., 1.).acos();
+    let mut phi_d = diff.y().atan2(diff.x());
+    // reciprocity makes phi_d and phi_d + pi the same
+    if phi_d < 0. {
+        phi_d += PI;
+    }
+
+    // theta_h is spaced more finely near the pole, where highlights are
+    let theta_h_index = ((theta_h / (PI / 2.)).max(0.).sqrt() * THETA_H_RES as f64) as usize;
+    let theta_d_index = (theta_d / (PI / 2.) * THETA_D_RES as f64) as usize;
+    let phi_d_index = (phi_d / PI * PHI_D_RES as f64) as usize;
+    (phi_d_index.min(PHI_D_RES - 1))
+        + (theta_d_index.min(THETA_D_RES - 1)) * PHI_D_RES
+        + (theta_h_index.min(THETA_H_RES - 1)) * PHI_D_RES * THETA_D_RES
+}
+
+/// Local direction with elevation `theta` and azimuth `phi`.
+fn direction(theta: f64, phi: f64) -> Vec3 {
+    v3!(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+}
+
+impl Merl {
+    /// Read a `.binary` file: three 32 bit sizes, then the red, green and
+    /// blue tables of 64 bit floats, all little endian.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        if bytes.len() < 12 {
+            return Err(invalid("truncated header"));
+        }
+        let dims: Vec<i32> = bytes[..12].chunks(4).map(|c| i32::from_le_bytes(c.try_into().unwrap())).collect();
+        if dims != [THETA_H_RES, THETA_D_RES, PHI_D_RES].map(|d| d as i32) {
+            return Err(invalid("unexpected table size"));
+        }
+        if bytes.len() != 12 + 3 * SAMPLES * 8 {
+            return Err(invalid("truncated tables"));
+        }
+        let value = |i: usize| f64::from_le_bytes(bytes[12 + 8 * i..20 + 8 * i].try_into().unwrap());
+        // missing measurements are stored as negative numbers
+        let table = (0..SAMPLES)
+            .map(|i| [0, 1, 2].map(|c| (value(c * SAMPLES + i) * SCALE[c]).max(0.)))
+            .collect();
+        Ok(Self::from_table(table))
+    }
+
+    fn from_table(table: Vec<[f64; 3]>) -> Self {
+        let mut merl = Self { table, cdfs: vec![] };
+        let d_theta_o = PI / 2. / SAMPLE_THETA_O as f64;
+        let (d_theta, d_phi) = (PI / 2. / SAMPLE_THETA_I as f64, 2. * PI / SAMPLE_PHI_I as f64);
+        merl.cdfs = (0..SAMPLE_THETA_O)
+            .map(|o| {
+                let wo = direction((o as f64 + 0.5) * d_theta_o, 0.);
+                let mut sum = 0.;
+                let mut cdf = Vec::with_capacity(SAMPLE_THETA_I * SAMPLE_PHI_I);
+                for i in 0..SAMPLE_THETA_I {
+                    let theta = (i as f64 + 0.5) * d_theta;
+                    for j in 0..SAMPLE_PHI_I {
+                        let wi = direction(theta, (j as f64 + 0.5) * d_phi);
+                        // reflected light from the cell, over its solid angle
+                        sum += merl.f(&wo, &wi).luminance() * wi.z() * theta.sin() * d_theta * d_phi;
+                        cdf.push(sum);
+                    }
+                }
+                cdf
+            })
+            .collect();
+        merl
+    }
+
+    fn f(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let [r, g, b] = self.table[index(wo, wi)];
+        v3!(r, g, b)
+    }
+
+    fn cdf(&self, wo: &Vec3) -> &[f64] {
+        let theta_o = wo.z().clamp(0., 1.).acos();
+        &self.cdfs[((theta_o / (PI / 2.) * SAMPLE_THETA_O as f64) as usize).min(SAMPLE_THETA_O - 1)]
+    }
+
+    /// Density of sampling local `wi` for `wo`.
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wi.z() <= 0. {
+            return 0.;
+        }
+        let cosine = COSINE_SHARE * wi.z() / PI;
+        let cdf = self.cdf(wo);
+        let total = cdf[cdf.len() - 1];
+        if total <= 0. {
+            return cosine / COSINE_SHARE;
+        }
+        let (d_theta, d_phi) = (PI / 2. / SAMPLE_THETA_I as f64, 2. * PI / SAMPLE_PHI_I as f64);
+        let theta = wi.z().clamp(0., 1.).acos();
+        let phi = (wi.y().atan2(wi.x()) - wo.y().atan2(wo.x())).rem_euclid(2. * PI);
+        let i = ((theta / d_theta) as usize).min(SAMPLE_THETA_I - 1);
+        let j = ((phi / d_phi) as usize).min(SAMPLE_PHI_I - 1);
+        let k = i * SAMPLE_PHI_I + j;
+        let p = (cdf[k] - if k > 0 { cdf[k - 1] } else { 0. }) / total;
+        (1. - COSINE_SHARE) * p / (d_theta * d_phi * theta.sin().max(1e-6)) + cosine
+    }
+
+    /// Sample local `wi` for `wo` from the tables, or now and then the cosine.
+    fn sample(&self, wo: &Vec3) -> Vec3 {
+        let cdf = self.cdf(wo);
+        let total = cdf[cdf.len() - 1];
+        if total <= 0. || random_double() < COSINE_SHARE {
+            return random_cosine_direction();
+        }
+        let target = random_double() * total;
+        let k = cdf.partition_point(|&c| c <= target).min(cdf.len() - 1);
+        let (d_theta, d_phi) = (PI / 2. / SAMPLE_THETA_I as f64, 2. * PI / SAMPLE_PHI_I as f64);
+        let theta = ((k / SAMPLE_PHI_I) as f64 + random_double()) * d_theta;
+        let phi = ((k % SAMPLE_PHI_I) as f64 + random_double()) * d_phi + wo.y().atan2(wo.x());
+        direction(theta, phi)
+    }
+
+    fn local(r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> (Onb, Vec3, Vec3) {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = uvw.to_local(&direction.unit_vector());
+        (uvw, wo, wi)
+    }
+}
+
+impl Material for Merl {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let (uvw, wo, _) = Self::local(r_in, rec, &rec.normal);
+        if wo.z() <= 0. {
+            return None;
+        }
+        let wi = self.sample(&wo);
+        let pdf = self.pdf(&wo, &wi);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.f(&wo, &wi) * wi.z() / pdf,
+            scattered: Ray::new(rec.p, uvw.local(&wi)),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        let (_, wo, wi) = Self::local(r_in, rec, direction);
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return v3!(0., 0., 0.);
+        }
+        self.f(&wo, &wi) * wi.z()
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let (_, wo, wi) = Self::local(r_in, rec, direction);
+        if wo.z() <= 0. {
+            return 0.;
+        }
+        self.pdf(&wo, &wi)
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        // as if it were Lambertian, from the reflectance head on
+        let up = v3!(0., 0., 1.);
+        PI * self.f(&up, &up)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::PI;
+    use vec3::{random_unit_vector, v3};
+
+    use crate::{hittable::HitRecord, material::Material, ray::Ray};
+
+    use super::{index, Merl, PHI_D_RES, SAMPLES, SCALE, THETA_D_RES};
+
+    #[test]
+    fn test_load() {
+        // a Lambertian surface reflecting half, as a file
+        let mut bytes = vec![];
+        for dim in [90i32, 90, 180] {
+            bytes.extend(dim.to_le_bytes());
+        }
+        for scale in SCALE {
+            for _ in 0..SAMPLES {
+                bytes.extend((0.5 / PI / scale).to_le_bytes());
+            }
+        }
+        let path = std::env::temp_dir().join(format!("merl-test-{}.binary", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let merl = Merl::load(&path).unwrap();
+        assert!(Merl::load("no such file").is_err());
+        // sizes that don't match, however they multiply out
+        let mut wrong = bytes.clone();
+        for (k, dim) in [-1i32, -90, 180].into_iter().enumerate() {
+            wrong[4 * k..4 * k + 4].copy_from_slice(&dim.to_le_bytes());
+        }
+        std::fs::write(&path, &wrong).unwrap();
+        assert!(Merl::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let ray = Ray::new(v3!(0.6, 0.8, 0.), v3!(-0.6, -0.8, 0.));
+        let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &merl);
+        let f = merl.eval(&ray, &rec, &v3!(0., 1., 0.));
+        assert!((f - v3!(0.5, 0.5, 0.5) / PI).length() < 1e-9);
+        let n = 100_000;
+        let mean = (0..n).filter_map(|_| merl.scatter(&ray, &rec)).map(|s| s.attenuation.y()).sum::<f64>() / n as f64;
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn test_peaked_table() {
+        // a highlight around the mirror direction over a dim base
+        let table = (0..SAMPLES)
+            .map(|i| {
+                let theta_h = (i / (PHI_D_RES * THETA_D_RES)) as f64;
+                [0.02 + 2. * (-theta_h / 3.).exp(); 3]
+            })
+            .collect();
+        let merl = Merl::from_table(table);
+        for cos in [0.9, 0.4_f64] {
+            let ray = Ray::new(v3!((1. - cos * cos).sqrt(), cos, 0.), v3!(-(1. - cos * cos).sqrt(), -cos, 0.));
+            let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &merl);
+            for _ in 0..1000 {
+                let srec = merl.scatter(&ray, &rec).unwrap();
+                let direction = srec.scattered.direction();
+                let expected = merl.eval(&ray, &rec, direction) / merl.scattering_pdf(&ray, &rec, direction);
+                assert!((srec.attenuation - expected).length() < 1e-6 * expected.length(), "{:?} {:?}", srec.attenuation, expected);
+            }
+            let n = 400_000;
+            let mean = (0..n).map(|_| merl.scattering_pdf(&ray, &rec, &random_unit_vector())).sum::<f64>() / n as f64;
+            assert!((4. * PI * mean - 1.).abs() < 0.02, "{} {}", cos, 4. * PI * mean);
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        // head on is the first entry, and reciprocity holds
+        let up = v3!(0., 0., 1.);
+        assert_eq!(index(&up, &up), 0);
+        let wo = v3!(0.3, 0.2, 0.9).unit_vector();
+        let wi = v3!(-0.5, 0.4, 0.7).unit_vector();
+        assert_eq!(index(&wo, &wi), index(&wi, &wo));
+    }
+}
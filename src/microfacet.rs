@@ -0,0 +1,340 @@
+//! GGX (Trowbridge-Reitz) microfacet distribution and Fresnel terms for the
+//! physically based materials. Directions are in the local shading frame,
+//! with the macro surface normal along +z.
+
+use utils::{random_double, PI};
+use vec3::{v3, Color, Vec3};
+
+use crate::spectrum::lift;
+
+/// Below this roughness a surface is treated as perfectly smooth.
+pub const SMOOTH_ALPHA: f64 = 1e-3;
+
+/// Anisotropic GGX distribution of microfacet normals, with Smith's
+/// height-correlated masking-shadowing.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    /// Density of microfacet normals `wm`, per unit projected area.
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2 = wm.z() * wm.z();
+        if cos2 <= 0. {
+            return 0.;
+        }
+        let e = (wm.x() * wm.x() / (self.alpha_x * self.alpha_x) + wm.y() * wm.y() / (self.alpha_y * self.alpha_y)) / cos2;
+        1. / (PI * self.alpha_x * self.alpha_y * cos2 * cos2 * (1. + e) * (1. + e))
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 <= 0. {
+            return utils::INFINITY;
+        }
+        let alpha2_tan2 = (self.alpha_x * self.alpha_x * w.x() * w.x() + self.alpha_y * self.alpha_y * w.y() * w.y()) / cos2;
+        ((1. + alpha2_tan2).sqrt() - 1.) / 2.
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1. / (1. + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of microfacet normals visible from `wo`, which is what
+    /// `sample_visible` draws from.
+    pub fn visible_d(&self, wo: &Vec3, wm: &Vec3) -> f64 {
+        self.g1(wo) / wo.z().abs() * self.d(wm) * wo.dot(wm).max(0.)
+    }
+
+    /// Sample a microfacet normal visible from `wo`, after Heitz, "Sampling
+    /// the GGX Distribution of Visible Normals".
+    pub fn sample_visible(&self, wo: &Vec3) -> Vec3 {
+        // stretch to the hemisphere configuration
+        let mut wh = v3!(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit_vector();
+        if wh.z() < 0. {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {
+            v3!(0., 0., 1.).cross(&wh).unit_vector()
+        } else {
+            v3!(1., 0., 0.)
+        };
+        let t2 = wh.cross(&t1);
+
+        // uniform point on the disk, warped onto the visible half
+        let r = random_double().sqrt();
+        let phi = 2. * PI * random_double();
+        let p1 = r * phi.cos();
+        let mut p2 = r * phi.sin();
+        let s = (1. + wh.z()) / 2.;
+        p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * p2;
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * wh;
+
+        // and back to the ellipsoid
+        v3!(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1e-6)).unit_vector()
+    }
+}
+
+/// Fresnel reflectance of a dielectric interface for unpolarized light
+/// arriving at `cos_theta`, where `eta` is the index of refraction of the
+/// far side over that of the near side.
+pub fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let sin2_t = (1. - cos_theta * cos_theta) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parallel = (eta * cos_theta - cos_t) / (eta * cos_theta + cos_t);
+    let r_perpendicular = (cos_theta - eta * cos_t) / (cos_theta + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+/// Direction of `wo` refracted through a surface with normal `n` on the same
+/// side, or `None` on total internal reflection. `eta` is as for
+/// `fresnel_dielectric`.
+pub fn refract(wo: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = wo.dot(n);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(-*wo / eta + (cos_i / eta - cos_t) * *n)
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction
+/// `eta` + i `k`, per colour channel, for light arriving at `cos_theta`.
+pub fn fresnel_conductor(cos_theta: f64, eta: &Color, k: &Color) -> Color {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta * cos_theta;
+        let sin2 = 1. - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_theta * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        (rs + rp) / 2.
+    };
+    v3!(channel(eta.x(), k.x()), channel(eta.y(), k.y()), channel(eta.z(), k.z()))
+}
+
+/// Wavelengths in nanometres standing for the red, green and blue channels
+/// where a colour is worked out from wave optics.
+pub const RGB_WAVELENGTHS: [f64; 3] = [630., 532., 465.];
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    fn add(self, o: Self) -> Self {
+        Self::new(self.re + o.re, self.im + o.im)
+    }
+
+    fn sub(self, o: Self) -> Self {
+        Self::new(self.re - o.re, self.im - o.im)
+    }
+
+    fn mul(self, o: Self) -> Self {
+        Self::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+
+    fn div(self, o: Self) -> Self {
+        let d = o.norm();
+        Self::new((self.re * o.re + self.im * o.im) / d, (self.im * o.re - self.re * o.im) / d)
+    }
+
+    /// Squared magnitude.
+    fn norm(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Principal square root, with a non-negative real part.
+    fn sqrt(self) -> Self {
+        let r = self.norm().sqrt();
+        let re = ((r + self.re) / 2.).max(0.).sqrt();
+        let im = ((r - self.re) / 2.).max(0.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+
+    /// `exp(i self)`
+    fn exp_i(self) -> Self {
+        let scale = (-self.im).exp();
+        Self::new(scale * self.re.cos(), scale * self.re.sin())
+    }
+}
+
+/// A thin transparent coating, such as a soap film or a layer of oil, whose
+/// reflections interfere and colour the surface under it.
+#[derive(Debug, Clone, Copy)]
+pub struct ThinFilm {
+    /// in nanometres
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    /// Reflectance for unpolarized light of `wavelength` arriving at
+    /// `cos_theta` through a medium of index `eta_i`, onto the film lying on
+    /// a base with complex index `eta_t` + i `k_t`. Sums the waves bouncing
+    /// between the two interfaces with Airy's formula.
+    pub fn reflectance(&self, cos_theta: f64, eta_i: f64, eta_t: f64, k_t: f64, wavelength: f64) -> f64 {
+        let one = Complex::new(1., 0.);
+        let n0 = Complex::new(eta_i, 0.);
+        let n1 = Complex::new(self.ior, 0.);
+        let n2 = Complex::new(eta_t, k_t);
+        let cos0 = Complex::new(cos_theta, 0.);
+        // Snell's law, n sin = eta_i sin_theta, for the cosines in each layer
+        let sin2 = Complex::new(eta_i * eta_i * (1. - cos_theta * cos_theta), 0.);
+        let cos_in = |n: Complex| one.sub(sin2.div(n.mul(n))).sqrt();
+        let (cos1, cos2) = (cos_in(n1), cos_in(n2));
+
+        let fresnel_s = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            na.mul(ca).sub(nb.mul(cb)).div(na.mul(ca).add(nb.mul(cb)))
+        };
+        let fresnel_p = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            nb.mul(ca).sub(na.mul(cb)).div(nb.mul(ca).add(na.mul(cb)))
+        };
+        // phase gained over one round trip through the film
+        let delta = n1.mul(cos1).mul(Complex::new(4. * PI * self.thickness / wavelength, 0.));
+        let phase = delta.exp_i();
+        let airy = |r01: Complex, r12: Complex| r01.add(r12.mul(phase)).div(one.add(r01.mul(r12).mul(phase))).norm();
+        let rs = airy(fresnel_s(n0, cos0, n1, cos1), fresnel_s(n1, cos1, n2, cos2));
+        let rp = airy(fresnel_p(n0, cos0, n1, cos1), fresnel_p(n1, cos1, n2, cos2));
+        ((rs + rp) / 2.).min(1.)
+    }
+
+    /// `reflectance` at the wavelengths of the colour channels, onto a base
+    /// given per channel.
+    pub fn reflectance_rgb(&self, cos_theta: f64, eta_i: f64, eta_t: &Color, k_t: &Color) -> Color {
+        let channel = |i: usize| self.reflectance(cos_theta, eta_i, eta_t[i], k_t[i], RGB_WAVELENGTHS[i]);
+        v3!(channel(0), channel(1), channel(2))
+    }
+
+    /// `reflectance` at `wavelength`, the same in every channel, onto the
+    /// base lifted to it, for a spectral path carrying one wavelength; else
+    /// `reflectance_rgb`.
+    pub fn reflectance_at(&self, cos_theta: f64, eta_i: f64, eta_t: &Color, k_t: &Color, wavelength: Option<f64>) -> Color {
+        match wavelength {
+            Some(lambda) => {
+                let r = self.reflectance(cos_theta, eta_i, lift(eta_t, lambda), lift(k_t, lambda), lambda);
+                v3!(r, r, r)
+            }
+            None => self.reflectance_rgb(cos_theta, eta_i, eta_t, k_t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::{random_double, PI};
+    use vec3::{v3, Vec3};
+
+    use super::{fresnel_conductor, fresnel_dielectric, refract, Ggx, ThinFilm};
+
+    fn random_hemisphere() -> Vec3 {
+        let z = random_double();
+        let phi = 2. * PI * random_double();
+        let r = (1. - z * z).sqrt();
+        v3!(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    #[test]
+    fn test_ggx_normalized() {
+        // projected microfacet area adds up to the macro surface, both seen
+        // from above and from an angle
+        let ggx = Ggx::new(0.3, 0.6);
+        let wo = v3!(0.6, 0., 0.8);
+        let n = 200_000;
+        let (mut projected, mut visible) = (0., 0.);
+        for _ in 0..n {
+            let wm = random_hemisphere();
+            projected += ggx.d(&wm) * wm.z() * 2. * PI;
+            visible += ggx.visible_d(&wo, &wm) * 2. * PI;
+        }
+        assert!((projected / n as f64 - 1.).abs() < 0.03, "{}", projected / n as f64);
+        assert!((visible / n as f64 - 1.).abs() < 0.03, "{}", visible / n as f64);
+    }
+
+    #[test]
+    fn test_sample_visible_matches_density() {
+        // the share of samples in a cone around +z matches the integral of
+        // `visible_d` over it
+        let ggx = Ggx::new(0.4, 0.4);
+        let wo = v3!(0.5, 0.3, 0.8).unit_vector();
+        let n = 200_000;
+        let cos_cone = 0.95;
+        let sampled = (0..n).filter(|_| ggx.sample_visible(&wo).z() > cos_cone).count() as f64 / n as f64;
+        let mut integral = 0.;
+        for _ in 0..n {
+            // uniform over the cone alone
+            let z = 1. - (1. - cos_cone) * random_double();
+            let phi = 2. * PI * random_double();
+            let r = (1. - z * z).sqrt();
+            integral += ggx.visible_d(&wo, &v3!(r * phi.cos(), r * phi.sin(), z)) * 2. * PI * (1. - cos_cone);
+        }
+        let integral = integral / n as f64;
+        assert!((sampled - integral).abs() < 0.03 * integral, "{} {}", sampled, integral);
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        // ((1 - 1.5) / (1 + 1.5))^2 head on, total reflection past the
+        // critical angle on the way out
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+        let wo = v3!(0.6, 0., 0.8);
+        let wi = refract(&wo, &v3!(0., 0., 1.), 1.5).unwrap();
+        assert!((wi.length() - 1.).abs() < 1e-12);
+        // Snell's law
+        assert!((wi.x().abs() * 1.5 - wo.x()).abs() < 1e-12);
+        assert!(refract(&wo, &v3!(0., 0., 1.), 0.5).is_none());
+    }
+
+    #[test]
+    fn test_thin_film() {
+        // a film of no thickness, or one matching the medium around it,
+        // leaves the bare interface
+        let wo = v3!(0.6, 0., 0.8);
+        let none = ThinFilm { thickness: 0., ior: 1.4 };
+        let matched = ThinFilm { thickness: 300., ior: 1. };
+        assert!((none.reflectance(wo.z(), 1., 1.5, 0., 550.) - fresnel_dielectric(wo.z(), 1.5)).abs() < 1e-9);
+        assert!((matched.reflectance(wo.z(), 1., 1.5, 0., 550.) - fresnel_dielectric(wo.z(), 1.5)).abs() < 1e-9);
+        let gold = fresnel_conductor(wo.z(), &v3!(0.143, 0.143, 0.143), &v3!(3.983, 3.983, 3.983));
+        assert!((none.reflectance(wo.z(), 1., 0.143, 3.983, 630.) - gold.x()).abs() < 1e-9);
+        // a quarter wave coating of index sqrt(1.5) on glass cancels the
+        // reflection head on
+        let coating = ThinFilm { thickness: 550. / (4. * 1.5f64.sqrt()), ior: 1.5f64.sqrt() };
+        assert!(coating.reflectance(1., 1., 1.5, 0., 550.) < 1e-9);
+        // and a soap film in air reflects some colours and not others
+        let soap = ThinFilm { thickness: 300., ior: 1.33 };
+        let r = soap.reflectance_rgb(1., 1., &v3!(1., 1., 1.), &v3!(0., 0., 0.));
+        assert!(r.max_component() > 1.5 * r.x().min(r.y()).min(r.z()), "{:?}", r);
+    }
+}
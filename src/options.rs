@@ -0,0 +1,116 @@
+//! Command line options.
+//!
+//! ```text
+//! ray_tracing_in_one_week [SCENE] [--integrator NAME] [--rr-depth N] [--photons N]
+//!                         [--seed N] [--ao-radius R] [--aovs] [--brdf FILE]
+//! ```
+
+pub const USAGE: &str = "usage: ray_tracing_in_one_week [SCENE] [--integrator NAME] [--rr-depth N] [--photons N]
+                                [--seed N] [--ao-radius R] [--aovs] [--brdf FILE]
+
+SCENE               random (default), cornell, lamps, mis, delta, bulb, caustics,
+                    materials, prism, subsurface, kelvin, shapes,
+                    quartic, sdf, csg or measured
+--integrator NAME   path (default), spectral, bdpt, photon or mlt, or for inspecting the
+                    scene ao, normals, depth, uv, material or bvh
+--rr-depth N        bounces traced before russian roulette may end a path (default 5)
+--photons N         photons shot for the caustic map of `photon` (default 500000)
+--seed N            seed of `mlt`, which renders the same image for the same seed
+                    (default random)
+--ao-radius R       distance within which `ao` looks for occluders (default a
+                    tenth of the distance to the middle of the image)
+--aovs              also save albedo, normal, depth, position, object id, direct
+                    and indirect light as test.<pass>.pfm
+--brdf FILE         MERL .binary BRDF shown by the measured scene";
+
+pub struct Options {
+    pub scene: String,
+    pub integrator: String,
+    /// number of bounces a path always survives before russian roulette
+    /// starts terminating it
+    pub rr_depth: u32,
+    /// photons emitted to build the caustic photon map
+    pub photons: usize,
+    /// seed of the Metropolis integrator, `None` for a random one
+    pub seed: Option<u64>,
+    /// occlusion range of the ambient occlusion integrator, `None` to derive
+    /// it from how far away the scene is
+    pub ao_radius: Option<f64>,
+    /// whether to save the AOV buffers next to the image
+    pub aovs: bool,
+    /// measured BRDF for the `measured` scene
+    pub brdf: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene: "random".to_string(),
+            integrator: "path".to_string(),
+            rr_depth: 5,
+            photons: 500_000,
+            seed: None,
+            ao_radius: None,
+            aovs: false,
+            brdf: None,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--integrator" => options.integrator = parse_value(&arg, args.next())?,
+                "--rr-depth" => options.rr_depth = parse_value(&arg, args.next())?,
+                "--photons" => options.photons = parse_value(&arg, args.next())?,
+                "--seed" => options.seed = Some(parse_value(&arg, args.next())?),
+                "--ao-radius" => options.ao_radius = Some(parse_value(&arg, args.next())?),
+                "--aovs" => options.aovs = true,
+                "--brdf" => options.brdf = Some(parse_value(&arg, args.next())?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => options.scene = arg,
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} expects a value", name))?;
+    value.parse().map_err(|_| format!("invalid value {:?} for {}", value, name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Options;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_rr_depth() {
+        assert_eq!(parse(&[]).unwrap().rr_depth, 5);
+        let options = parse(&["cornell", "--rr-depth", "12", "--integrator", "bdpt"]).unwrap();
+        assert_eq!(options.rr_depth, 12);
+        assert_eq!(options.scene, "cornell");
+        assert_eq!(options.integrator, "bdpt");
+        assert_eq!(parse(&["--rr-depth", "0"]).unwrap().rr_depth, 0);
+        assert!(parse(&["--rr-depth"]).is_err());
+        assert!(parse(&["--rr-depth", "-1"]).is_err());
+        assert!(parse(&["--rr-depth", "many"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(parse(&[]).unwrap().seed, None);
+        assert_eq!(parse(&["--seed", "42"]).unwrap().seed, Some(42));
+        assert!(parse(&["--seed", "-1"]).is_err());
+    }
+}
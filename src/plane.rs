@@ -0,0 +1,253 @@
+use std::sync::Arc;
+
+use utils::{random_double, PI};
+use vec3::{v3, Onb, Point3, Vec3};
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable}, material::Material, ray::Ray};
+
+/// Where `r` crosses the plane through `point` with unit `normal`, if it
+/// does within `t_min..t_max`.
+fn hit_plane(point: &Point3, normal: &Vec3, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+    let denom = normal.dot(r.direction());
+    // ray is parallel to the plane
+    if denom.abs() < 1e-8 {
+        return None;
+    }
+    let t = (*point - *r.origin()).dot(normal) / denom;
+    (t_min..=t_max).contains(&t).then_some(t)
+}
+
+/// Box around a circle of `radius` about `center` in the plane with unit
+/// `normal`.
+pub(crate) fn circle_box(center: &Point3, normal: &Vec3, radius: f64) -> Aabb {
+    let extent = |n: f64| radius * (1. - n * n).max(0.).sqrt();
+    let e = v3!(extent(normal.x()), extent(normal.y()), extent(normal.z()));
+    Aabb::new(*center - e, *center + e)
+}
+
+/// Infinite plane through `point`, facing along `normal`. The surface
+/// coordinates are distances along the plane, so they grow without bound.
+pub struct Plane {
+    point: Point3,
+    uvw: Onb,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, m: Arc<dyn Material>) -> Self {
+        Self {
+            point,
+            uvw: Onb::build_from_w(&normal),
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let normal = self.uvw.w();
+        let t = hit_plane(&self.point, &normal, r, t_min, t_max)?;
+        let p = r.at(t);
+        let local = self.uvw.to_local(&(p - self.point));
+        Some(HitRecord::new(p, t, normal, *r, &*self.mat_ptr).with_uv(local.x(), local.y()).with_tangent(self.uvw.u()))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// Flat ring between `inner` and `outer` radii around `center`, facing
+/// along `normal`. `u` goes once around and `v` from the inner edge out.
+pub struct Annulus {
+    center: Point3,
+    uvw: Onb,
+    inner: f64,
+    outer: f64,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Annulus {
+    pub fn new(center: Point3, normal: Vec3, inner: f64, outer: f64, m: Arc<dyn Material>) -> Self {
+        Self {
+            center,
+            uvw: Onb::build_from_w(&normal),
+            inner: inner.min(outer),
+            outer: outer.max(inner),
+            mat_ptr: m,
+        }
+    }
+
+    /// Point at a fraction `s` of the area out from the inner edge and at
+    /// angle `phi`, so that uniform `s` gives uniform points.
+    fn point(&self, s: f64, phi: f64) -> Point3 {
+        let r = (self.inner * self.inner + s * (self.outer * self.outer - self.inner * self.inner)).sqrt();
+        self.center + self.uvw.local(&v3!(r * phi.cos(), r * phi.sin(), 0.))
+    }
+}
+
+impl Hittable for Annulus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let normal = self.uvw.w();
+        let t = hit_plane(&self.center, &normal, r, t_min, t_max)?;
+        let p = r.at(t);
+        let local = self.uvw.to_local(&(p - self.center));
+        let radius = local.x().hypot(local.y());
+        if radius < self.inner || self.outer < radius {
+            return None;
+        }
+        let u = (local.y().atan2(local.x()) + PI) / (2. * PI);
+        let v = if self.outer > self.inner { (radius - self.inner) / (self.outer - self.inner) } else { 0. };
+        let dpdu = 2. * PI * self.uvw.local(&v3!(-local.y(), local.x(), 0.));
+        Some(HitRecord::new(p, t, normal, *r, &*self.mat_ptr).with_uv(u, v).with_tangent(dpdu))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(circle_box(&self.center, &self.uvw.w(), self.outer))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(*origin, *direction), 0.001, utils::INFINITY) {
+            Some(rec) => {
+                let distance_squared = rec.t * rec.t * direction.length_squared();
+                let cosine = (direction.dot(&rec.normal) / direction.length()).abs();
+                distance_squared / (cosine * self.area())
+            }
+            None => 0.,
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.point(random_double(), 2. * PI * random_double()) - origin
+    }
+
+    fn area(&self) -> f64 {
+        PI * (self.outer * self.outer - self.inner * self.inner)
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord<'_>> {
+        let p = self.point(random_double(), 2. * PI * random_double());
+        let normal = self.uvw.w();
+        Some(HitRecord::new(p, 0., normal, Ray::new(p + normal, -normal), &*self.mat_ptr))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+/// Round disk of `radius` around `center`, facing along `normal`: an annulus
+/// without a hole.
+pub struct Disk(Annulus);
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, m: Arc<dyn Material>) -> Self {
+        Self(Annulus::new(center, normal, 0., radius, m))
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.0.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.0.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.0.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.0.random(origin)
+    }
+
+    fn area(&self) -> f64 {
+        self.0.area()
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord<'_>> {
+        self.0.sample_surface()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.0.is_emissive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::PI;
+    use vec3::v3;
+
+    use crate::{hittable::Hittable, material::Lambertian, ray::Ray};
+
+    use super::{circle_box, Annulus, Disk, Plane};
+
+    #[test]
+    fn test_plane() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let plane = Plane::new(v3!(0., 1., 0.), v3!(0., 1., 0.), m);
+        let rec = plane.hit(&Ray::new(v3!(2., 3., -1.), v3!(0., -2., 0.)), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.).abs() < 1e-9 && (rec.normal - v3!(0., 1., 0.)).length() < 1e-9);
+        // u and v measure the way along the plane
+        let back = plane.point + rec.u * plane.uvw.u() + rec.v * plane.uvw.v();
+        assert!((back - rec.p).length() < 1e-9, "{:?} {:?}", back, rec.p);
+        // from below it is seen from behind, and sideways not at all
+        assert!(!plane.hit(&Ray::new(v3!(0., -1., 0.), v3!(0.1, 1., 0.)), 0.001, f64::INFINITY).unwrap().front_face);
+        assert!(plane.hit(&Ray::new(v3!(0., 2., 0.), v3!(1., 0., 1.)), 0.001, f64::INFINITY).is_none());
+        assert!(plane.hit(&Ray::new(v3!(0., 2., 0.), v3!(0., -1., 0.)), 0.001, 0.5).is_none());
+    }
+
+    #[test]
+    fn test_annulus_and_disk() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let annulus = Annulus::new(v3!(0., 0., 0.), v3!(0., 1., 0.), 1., 2., m.clone());
+        let disk = Disk::new(v3!(0., 0., 0.), v3!(0., 1., 0.), 2., m);
+        let down = |x: f64| Ray::new(v3!(x, 3., 0.), v3!(0., -1., 0.));
+
+        // through the hole, the ring and past the outer edge
+        assert!(annulus.hit(&down(0.5), 0.001, f64::INFINITY).is_none());
+        assert!(disk.hit(&down(0.5), 0.001, f64::INFINITY).is_some());
+        let rec = annulus.hit(&down(1.5), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 3.).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9, "{} {}", rec.t, rec.v);
+        assert!(annulus.hit(&down(2.5), 0.001, f64::INFINITY).is_none());
+        assert!(disk.hit(&Ray::new(v3!(0., 3., 0.), v3!(1., 0., 0.)), 0.001, f64::INFINITY).is_none());
+
+        // u goes once around
+        for phi in [-3., -1., 0.5, 2.] {
+            let p = annulus.uvw.local(&v3!(1.5 * f64::cos(phi), 1.5 * f64::sin(phi), 0.));
+            let rec = annulus.hit(&Ray::new(p + v3!(0., 1., 0.), v3!(0., -1., 0.)), 0.001, f64::INFINITY).unwrap();
+            assert!((rec.u - (phi + PI) / (2. * PI)).abs() < 1e-9, "{} {}", phi, rec.u);
+        }
+
+        // sampled directions hit the shape, and undo their density over the
+        // solid angle it covers
+        let origin = v3!(0., 3., 0.);
+        for (shape, inner) in [(&annulus as &dyn Hittable, 1.), (&disk, 0.)] {
+            let cone = |r: f64| 2. * PI * (1. - 3. / (9. + r * r).sqrt());
+            let n = 100000;
+            let mut solid_angle = 0.;
+            for _ in 0..n {
+                let direction = shape.random(&origin);
+                let pdf = shape.pdf_value(&origin, &direction);
+                assert!(pdf > 0., "{:?}", direction);
+                solid_angle += 1. / pdf;
+            }
+            let expected = cone(2.) - cone(inner);
+            assert!((solid_angle / n as f64 - expected).abs() < 0.01 * expected, "{} {}", solid_angle / n as f64, expected);
+        }
+    }
+
+    #[test]
+    fn test_circle_box() {
+        let flat = circle_box(&v3!(1., 0., 0.), &v3!(0., 1., 0.), 2.);
+        assert!((flat.min - v3!(-1., 0., -2.)).length() < 1e-9 && (flat.max - v3!(3., 0., 2.)).length() < 1e-9);
+        let tilted = circle_box(&v3!(0., 0., 0.), &(v3!(1., 1., 0.) / 2_f64.sqrt()), 2.);
+        let e = v3!(2_f64.sqrt(), 2_f64.sqrt(), 2.);
+        assert!((tilted.min + e).length() < 1e-9 && (tilted.max - e).length() < 1e-9, "{:?}", tilted);
+    }
+}
@@ -0,0 +1,308 @@
+//! The Disney principled BSDF, after Burley, "Physically-Based Shading at
+//! Disney" and its 2015 extension to transmission: one material whose few
+//! artist friendly parameters cover plastics, metals, fabrics and glass.
+
+use utils::{random_double, PI};
+use vec3::{random_cosine_direction, reflect, v3, Color, Onb, Vec3};
+
+use crate::{
+    hittable::HitRecord,
+    material::{Material, RoughDielectric, ScatterRecord},
+    microfacet::Ggx,
+    ray::Ray,
+};
+
+/// Roughness below which highlights get too sharp to sample reliably.
+const MIN_ROUGHNESS: f64 = 0.05;
+
+/// Disney BSDF. All parameters but `ior` go from 0 to 1.
+#[derive(Debug, Clone, Copy)]
+pub struct PrincipledParams {
+    pub base_color: Color,
+    /// blend from dielectric to metal, which tints its reflections with
+    /// `base_color` and has no diffuse part
+    pub metallic: f64,
+    pub roughness: f64,
+    /// reflectance head on, with 0.5 standing for the usual 4%
+    pub specular: f64,
+    /// how much the dielectric reflections take on `base_color`
+    pub specular_tint: f64,
+    /// soft grazing reflections, for cloth
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    /// strength of a second, colourless and glossy layer, as on car paint
+    pub clearcoat: f64,
+    /// 0 for a satin clearcoat up to 1 for a gloss one
+    pub clearcoat_gloss: f64,
+    /// blend from opaque to glass tinted by `base_color`, which light
+    /// passing in and back out of an object takes on once
+    pub transmission: f64,
+    pub ior: f64,
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        Self {
+            base_color: v3!(0.8, 0.8, 0.8),
+            metallic: 0.,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.,
+            sheen: 0.,
+            sheen_tint: 0.5,
+            clearcoat: 0.,
+            clearcoat_gloss: 1.,
+            transmission: 0.,
+            ior: 1.5,
+        }
+    }
+}
+
+pub struct Principled {
+    params: PrincipledParams,
+    specular: Ggx,
+    /// the transmissive part, which also reflects
+    glass: RoughDielectric,
+    /// chances of sampling the diffuse, specular, clearcoat and glass lobes
+    lobes: [f64; 4],
+}
+
+fn schlick_weight(cos: f64) -> f64 {
+    (1. - cos).clamp(0., 1.).powi(5)
+}
+
+fn mix(a: &Color, b: &Color, t: f64) -> Color {
+    (1. - t) * *a + t * *b
+}
+
+/// Burley's generalized Trowbridge-Reitz with exponent 1, which gives the
+/// clearcoat its long tail.
+fn gtr1(cos_m: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.) / (PI * a2.ln() * (1. + (a2 - 1.) * cos_m * cos_m))
+}
+
+fn sample_gtr1(alpha: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos = ((1. - a2.powf(1. - random_double())) / (1. - a2)).max(0.).sqrt();
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let phi = 2. * PI * random_double();
+    v3!(sin * phi.cos(), sin * phi.sin(), cos)
+}
+
+impl Principled {
+    pub fn new(params: PrincipledParams) -> Self {
+        let roughness = params.roughness.max(MIN_ROUGHNESS);
+        let alpha = roughness * roughness;
+        let opaque = 1. - (1. - params.metallic) * params.transmission;
+        let lobes = [
+            opaque * (1. - params.metallic),
+            opaque,
+            0.25 * params.clearcoat,
+            1. - opaque,
+        ];
+        let total: f64 = lobes.iter().sum();
+        Self {
+            params,
+            specular: Ggx::new(alpha, alpha),
+            glass: RoughDielectric::new(params.ior, roughness),
+            lobes: lobes.map(|p| p / total),
+        }
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 + (0.001 - 0.1) * self.params.clearcoat_gloss
+    }
+
+    /// Hue of the base colour at unit luminance, for the tint parameters.
+    fn tint(&self) -> Color {
+        let luminance = self.params.base_color.luminance();
+        if luminance > 0. { self.params.base_color / luminance } else { v3!(1., 1., 1.) }
+    }
+
+    /// Colour reflected head on by the specular lobe.
+    fn specular_color(&self) -> Color {
+        let p = &self.params;
+        let tint = self.tint();
+        let dielectric = 0.08 * p.specular * mix(&v3!(1., 1., 1.), &tint, p.specular_tint);
+        mix(&dielectric, &p.base_color, p.metallic)
+    }
+
+    /// The opaque lobes for `wo` and `wi` on the outside, as BSDF times
+    /// cosine, and the density of sampling `wi` from them.
+    fn eval_opaque(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        let p = &self.params;
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        let wm = (*wo + *wi).unit_vector();
+        let cos_d = wi.dot(&wm);
+        let mut f = v3!(0., 0., 0.);
+        let mut pdf = 0.;
+
+        let [diffuse, specular, clearcoat, _] = self.lobes;
+        let opaque = 1. - (1. - p.metallic) * p.transmission;
+        if diffuse > 0. {
+            // retro-reflective diffuse, brightening rough surfaces at grazing angles
+            let fd90 = 0.5 + 2. * p.roughness * cos_d * cos_d;
+            let fd = (1. + (fd90 - 1.) * schlick_weight(cos_i)) * (1. + (fd90 - 1.) * schlick_weight(cos_o));
+            let tint = self.tint();
+            let sheen = p.sheen * schlick_weight(cos_d) * mix(&v3!(1., 1., 1.), &tint, p.sheen_tint);
+            f = f + opaque * (1. - p.metallic) * (p.base_color * fd / PI + sheen) * cos_i;
+            pdf += diffuse * cos_i / PI;
+        }
+        if specular > 0. {
+            let spec = self.specular_color();
+            let fresnel = spec + (v3!(1., 1., 1.) - spec) * schlick_weight(cos_d);
+            f = f + opaque * fresnel * self.specular.d(&wm) * self.specular.g(wo, wi) / (4. * cos_o);
+            pdf += specular * self.specular.visible_d(wo, &wm) / (4. * wo.dot(&wm));
+        }
+        if clearcoat > 0. {
+            let alpha = self.clearcoat_alpha();
+            let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            let g = Ggx::new(0.25, 0.25).g(wo, wi);
+            let d = gtr1(wm.z(), alpha);
+            f = f + v3!(1., 1., 1.) * 0.25 * p.clearcoat * fresnel * d * g / (4. * cos_o);
+            pdf += clearcoat * d * wm.z() / (4. * cos_d);
+        }
+        (f, pdf)
+    }
+
+    /// BSDF times cosine and sampling density of all lobes for light leaving
+    /// along `direction`.
+    fn eval_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> (Color, f64) {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let wi = uvw.to_local(&direction.unit_vector());
+        let mut f = v3!(0., 0., 0.);
+        let mut pdf = 0.;
+        // the opaque lobes only see the outside; rays inside a transmissive
+        // object meet the glass alone
+        if rec.front_face && wo.z() > 0. && wi.z() > 0. {
+            (f, pdf) = self.eval_opaque(&wo, &wi);
+        }
+        // and always sample it
+        let glass = if rec.front_face { self.lobes[3] } else { 1. };
+        if glass > 0. {
+            // half the tint going in and half coming out
+            let tint = if wi.z() < 0. { self.params.base_color.sqrt() } else { v3!(1., 1., 1.) };
+            let transmission = (1. - self.params.metallic) * self.params.transmission;
+            f = f + transmission * tint * self.glass.eval(r_in, rec, direction);
+            pdf += glass * self.glass.scattering_pdf(r_in, rec, direction);
+        }
+        (f, pdf)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&rec.normal);
+        let wo = uvw.to_local(&-r_in.direction().unit_vector());
+        let [diffuse, specular, clearcoat, _] = self.lobes;
+        let u = random_double();
+        let direction = if !rec.front_face || u >= diffuse + specular + clearcoat {
+            *self.glass.scatter(r_in, rec)?.scattered.direction()
+        } else {
+            let wi = if u < diffuse {
+                random_cosine_direction()
+            } else if u < diffuse + specular {
+                reflect(&-wo, &self.specular.sample_visible(&wo))
+            } else {
+                reflect(&-wo, &sample_gtr1(self.clearcoat_alpha()))
+            };
+            if wi.z() <= 0. {
+                return None;
+            }
+            uvw.local(&wi)
+        };
+        let (f, pdf) = self.eval_pdf(r_in, rec, &direction);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: f / pdf,
+            scattered: Ray::new(rec.p, direction),
+            is_specular: false,
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Color {
+        self.eval_pdf(r_in, rec, direction).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        self.eval_pdf(r_in, rec, direction).1
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.params.base_color
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::{random_double, PI};
+    use vec3::{v3, Vec3};
+
+    use crate::{hittable::HitRecord, material::Material, ray::Ray};
+
+    use super::{Principled, PrincipledParams};
+
+    #[test]
+    fn test_sampling_matches_eval() {
+        // the mean weight of sampled directions is what integrating `eval`
+        // over the sphere gives, and neither reflects more than comes in
+        let materials = [
+            PrincipledParams::default(),
+            PrincipledParams { metallic: 1., roughness: 0.5, ..Default::default() },
+            PrincipledParams { sheen: 1., clearcoat: 1., clearcoat_gloss: 0.5, ..Default::default() },
+            PrincipledParams { transmission: 1., roughness: 0.6, ..Default::default() },
+        ];
+        for params in materials {
+            let material = Principled::new(params);
+            let ray = Ray::new(v3!(0.6, 0.8, 0.), v3!(-0.6, -0.8, 0.));
+            let rec = HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &material);
+            let (strata, n) = (640, 640 * 640);
+            let mut sampled = 0.;
+            let mut integral = 0.;
+            for i in 0..n {
+                if let Some(srec) = material.scatter(&ray, &rec) {
+                    sampled += srec.attenuation.y();
+                }
+                // stratified over the sphere, which the peaky glass needs
+                let z = 2. * ((i / strata) as f64 + random_double()) / strata as f64 - 1.;
+                let phi = 2. * PI * ((i % strata) as f64 + random_double()) / strata as f64;
+                let r = (1. - z * z).sqrt();
+                let direction = v3!(r * phi.cos(), z, r * phi.sin());
+                integral += material.eval(&ray, &rec, &direction).y() * 4. * PI;
+            }
+            let (sampled, integral) = (sampled / n as f64, integral / n as f64);
+            assert!((sampled - integral).abs() < 0.04 * integral.max(0.1), "{:?} {} {}", params, sampled, integral);
+            assert!(sampled < 1.01, "{:?} {}", params, sampled);
+        }
+    }
+
+    #[test]
+    fn test_glass_is_tinted_once() {
+        let base_color = v3!(0.81, 0.25, 1.);
+        let material = Principled::new(PrincipledParams { base_color, transmission: 1., roughness: 0.3, ..Default::default() });
+        // in through the top of a slab and out through its bottom
+        let into = Ray::new(v3!(0.3, 1., 0.), v3!(-0.3, -1., 0.));
+        let out = Ray::new(v3!(0., 0., 0.), v3!(0., -1., 0.));
+        let mut through = v3!(1., 1., 1.);
+        for (ray, normal) in [(into, v3!(0., 1., 0.)), (out, v3!(0., -1., 0.))] {
+            let rec = HitRecord::new(v3!(0., 0., 0.), 1., normal, ray, &material);
+            assert_eq!(rec.front_face, normal.y() > 0.);
+            let mut mean: Vec3 = v3!(0., 0., 0.);
+            let mut n = 0;
+            while n < 1000 {
+                if let Some(srec) = material.scatter(&ray, &rec) {
+                    if srec.scattered.direction().dot(&rec.normal) < 0. {
+                        mean = mean + srec.attenuation / srec.attenuation.z();
+                        n += 1;
+                    }
+                }
+            }
+            through = through * mean / n as f64;
+        }
+        assert!((through - base_color).length() < 1e-9, "{:?}", through);
+    }
+}
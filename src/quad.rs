@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use utils::random_double;
+use vec3::{v3, Point3, Vec3};
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable, HittableList}, material::Material, ray::Ray};
+
+/// Parallelogram spanned by `u` and `v` from the corner `q`.
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    mat_ptr: Arc<dyn Material>,
+    normal: Vec3,
+    d: f64,
+    w: Vec3,
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, m: Arc<dyn Material>) -> Self {
+        let n = u.cross(&v);
+        let normal = n.unit_vector();
+        let d = normal.dot(&q);
+        let w = n / n.length_squared();
+        Self {
+            q,
+            u,
+            v,
+            mat_ptr: m,
+            normal,
+            d,
+            w,
+            area: n.length(),
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let denom = self.normal.dot(r.direction());
+        // ray is parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(r.origin())) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        // planar coordinates of the hit point in terms of u and v
+        let p = r.at(t);
+        let planar_hitpt = p - self.q;
+        let alpha = self.w.dot(&planar_hitpt.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar_hitpt));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+        Some(HitRecord::new(p, t, self.normal, *r, &*self.mat_ptr).with_uv(alpha, beta).with_tangent(self.u))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let diagonal = Aabb::new(self.q, self.q + self.u + self.v);
+        Some(diagonal.surrounding(&Aabb::new(self.q + self.u, self.q + self.v)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(*origin, *direction), 0.001, utils::INFINITY) {
+            Some(rec) => {
+                let distance_squared = rec.t * rec.t * direction.length_squared();
+                let cosine = (direction.dot(&rec.normal) / direction.length()).abs();
+                distance_squared / (cosine * self.area)
+            }
+            None => 0.,
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let p = self.q + random_double() * self.u + random_double() * self.v;
+        p - origin
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord<'_>> {
+        let p = self.q + random_double() * self.u + random_double() * self.v;
+        Some(HitRecord::new(p, 0., self.normal, Ray::new(p + self.normal, -self.normal), &*self.mat_ptr))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+/// Axis aligned box with opposite vertices `a` and `b`, made of six quads.
+pub fn make_box(a: Point3, b: Point3, m: Arc<dyn Material>) -> HittableList {
+    let mut sides = HittableList::new();
+
+    let min = v3!(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+    let max = v3!(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+
+    let dx = v3!(max.x() - min.x(), 0., 0.);
+    let dy = v3!(0., max.y() - min.y(), 0.);
+    let dz = v3!(0., 0., max.z() - min.z());
+
+    sides.add(Arc::new(Quad::new(v3!(min.x(), min.y(), max.z()), dx, dy, m.clone()))); // front
+    sides.add(Arc::new(Quad::new(v3!(max.x(), min.y(), max.z()), -dz, dy, m.clone()))); // right
+    sides.add(Arc::new(Quad::new(v3!(max.x(), min.y(), min.z()), -dx, dy, m.clone()))); // back
+    sides.add(Arc::new(Quad::new(v3!(min.x(), min.y(), min.z()), dz, dy, m.clone()))); // left
+    sides.add(Arc::new(Quad::new(v3!(min.x(), max.y(), max.z()), dx, -dz, m.clone()))); // top
+    sides.add(Arc::new(Quad::new(v3!(min.x(), min.y(), min.z()), dx, dz, m))); // bottom
+    sides
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::PI;
+    use vec3::{random_in_unit_sphere, v3};
+
+    use crate::{hittable::Hittable, material::Lambertian, ray::Ray};
+
+    use super::Quad;
+
+    #[test]
+    fn test_sampling() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let quad = Quad::new(v3!(0., 0., -1.), v3!(1., 0., 0.), v3!(0., 2., 0.), m);
+        let origin = v3!(0., 0., 0.);
+        // a rectangle a by b seen from h above its corner
+        let (a, b, h) = (1_f64, 2_f64, 1_f64);
+        let solid_angle = (a * b / (h * (h * h + a * a + b * b).sqrt())).atan();
+
+        // uniform directions find the quad as often as its solid angle says,
+        // and the density over them adds up to one
+        let n = 200_000;
+        let (mut seen, mut total) = (0, 0.);
+        for _ in 0..n {
+            let pdf = quad.pdf_value(&origin, &random_in_unit_sphere().unit_vector());
+            seen += (pdf > 0.) as usize;
+            total += pdf;
+        }
+        assert!((4. * PI * seen as f64 / n as f64 - solid_angle).abs() < 0.04 * solid_angle, "{}", seen);
+        assert!((4. * PI * total / n as f64 - 1.).abs() < 0.05, "{}", 4. * PI * total / n as f64);
+
+        // sampled directions all hit it, and undo their density over its
+        // solid angle
+        let mut covered = 0.;
+        for _ in 0..n {
+            let direction = quad.random(&origin);
+            assert!(quad.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).is_some());
+            covered += 1. / quad.pdf_value(&origin, &direction);
+        }
+        assert!((covered / n as f64 - solid_angle).abs() < 0.01 * solid_angle, "{}", covered / n as f64);
+
+        // points on the surface, spread evenly
+        assert!((quad.area() - 2.).abs() < 1e-12);
+        let mut mean = v3!(0., 0., 0.);
+        for _ in 0..n {
+            let rec = quad.sample_surface().unwrap();
+            assert!(rec.p.z() == -1. && (0. ..=1.).contains(&rec.p.x()) && (0. ..=2.).contains(&rec.p.y()));
+            assert!((rec.normal - v3!(0., 0., 1.)).length() < 1e-12 && rec.front_face);
+            mean = mean + rec.p / n as f64;
+        }
+        assert!((mean - v3!(0.5, 1., -1.)).length() < 0.01, "{:?}", mean);
+    }
+}
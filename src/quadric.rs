@@ -0,0 +1,248 @@
+//! Cylinders and cones, intersected in a frame with the axis along z and the
+//! base at the origin.
+
+use std::sync::Arc;
+
+use utils::PI;
+use vec3::{v3, Onb, Point3, Vec3};
+
+use crate::{aabb::Aabb, hittable::{walk_spans, HitRecord, Hittable, Span}, material::Material, plane::circle_box, ray::Ray};
+
+/// A hit in the local frame: distance along the ray, normal and surface
+/// coordinates.
+type LocalHit = (f64, Vec3, f64, f64);
+
+/// Roots of `a t^2 + 2 half_b t + c`, smaller first.
+fn solve_quadratic(a: f64, half_b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < 1e-12 {
+        return None;
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    let (t0, t1) = ((-half_b - sqrtd) / a, (-half_b + sqrtd) / a);
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// Angle around the axis of local point `p`, scaled to [0, 1].
+fn angle(p: &Vec3) -> f64 {
+    (p.y().atan2(p.x()) + PI) / (2. * PI)
+}
+
+/// Hit on the disk of `radius` across the axis at height `z`, facing along
+/// `normal_z`, for the local ray from `o` along `d`.
+fn cap(o: &Vec3, d: &Vec3, z: f64, radius: f64, normal_z: f64) -> Option<LocalHit> {
+    if d.z().abs() < 1e-12 {
+        return None;
+    }
+    let t = (z - o.z()) / d.z();
+    let p = *o + t * *d;
+    let r = p.x().hypot(p.y());
+    (r <= radius).then(|| (t, v3!(0., 0., normal_z), angle(&p), r / radius))
+}
+
+/// Frame of a shape standing on `base` with its axis towards `top`.
+struct Frame {
+    base: Point3,
+    uvw: Onb,
+    height: f64,
+}
+
+impl Frame {
+    fn new(base: Point3, top: Point3) -> Self {
+        let axis = top - base;
+        Self {
+            base,
+            uvw: Onb::build_from_w(&axis),
+            height: axis.length(),
+        }
+    }
+
+    /// The closest of `hits` within `t_min..t_max`, as a hit record in
+    /// world space.
+    fn closest<'a>(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        hits: impl Iterator<Item = LocalHit>,
+        material: &'a dyn Material,
+    ) -> Option<HitRecord<'a>> {
+        let (t, normal, u, v) = hits
+            .filter(|(t, ..)| (t_min..=t_max).contains(t))
+            .min_by(|a, b| a.0.total_cmp(&b.0))?;
+        let normal = self.uvw.local(&normal).unit_vector();
+        // `u` goes around the axis everywhere
+        let p = self.uvw.to_local(&(r.at(t) - self.base));
+        let dpdu = 2. * PI * self.uvw.local(&v3!(-p.y(), p.x(), 0.));
+        Some(HitRecord::new(r.at(t), t, normal, *r, material).with_uv(u, v).with_tangent(dpdu))
+    }
+
+    /// `r` in the local frame. Distances along it stay the same.
+    fn to_local(&self, r: &Ray) -> (Vec3, Vec3) {
+        (self.uvw.to_local(&(*r.origin() - self.base)), self.uvw.to_local(r.direction()))
+    }
+}
+
+/// Cylinder of `radius` from the centre of its `base` to that of its `top`,
+/// open or closed by flat caps. On the side `u` goes around and `v` up; on
+/// the caps `u` goes around and `v` out from the middle.
+pub struct Cylinder {
+    frame: Frame,
+    radius: f64,
+    capped: bool,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(base: Point3, top: Point3, radius: f64, capped: bool, m: Arc<dyn Material>) -> Self {
+        Self {
+            frame: Frame::new(base, top),
+            radius,
+            capped,
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.to_local(r);
+        let (h, radius) = (self.frame.height, self.radius);
+        let a = d.x() * d.x() + d.y() * d.y();
+        let half_b = o.x() * d.x() + o.y() * d.y();
+        let c = o.x() * o.x() + o.y() * o.y() - radius * radius;
+        let side = solve_quadratic(a, half_b, c).into_iter().flat_map(|(t0, t1)| [t0, t1]).filter_map(|t| {
+            let p = o + t * d;
+            (0. ..=h).contains(&p.z()).then(|| (t, v3!(p.x(), p.y(), 0.), angle(&p), p.z() / h))
+        });
+        let caps = if self.capped { [cap(&o, &d, 0., radius, -1.), cap(&o, &d, h, radius, 1.)] } else { [None, None] };
+        self.frame.closest(r, t_min, t_max, side.chain(caps.into_iter().flatten()), &*self.mat_ptr)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let axis = self.frame.uvw.w();
+        let top = self.frame.base + self.frame.height * axis;
+        Some(circle_box(&self.frame.base, &axis, self.radius).surrounding(&circle_box(&top, &axis, self.radius)))
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        // without caps there is no inside
+        self.capped.then(|| walk_spans(self, r, t_min, t_max))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.capped
+    }
+}
+
+/// Cone with a base of `radius` around `base`, narrowing to a point at
+/// `apex`, with or without the base closed. Surface coordinates are as on a
+/// cylinder.
+pub struct Cone {
+    frame: Frame,
+    radius: f64,
+    capped: bool,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(base: Point3, apex: Point3, radius: f64, capped: bool, m: Arc<dyn Material>) -> Self {
+        Self {
+            frame: Frame::new(base, apex),
+            radius,
+            capped,
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (o, d) = self.frame.to_local(r);
+        let (h, radius) = (self.frame.height, self.radius);
+        // x^2 + y^2 = (k (h - z))^2, with k the radius shrinking per unit up
+        let k2 = (radius / h).powi(2);
+        let rest = h - o.z();
+        let a = d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z();
+        let half_b = o.x() * d.x() + o.y() * d.y() + k2 * rest * d.z();
+        let c = o.x() * o.x() + o.y() * o.y() - k2 * rest * rest;
+        // rays parallel to a line of the cone cross it only once
+        let roots = if a.abs() < 1e-12 && half_b.abs() > 1e-12 {
+            let t = -c / (2. * half_b);
+            Some((t, t))
+        } else {
+            solve_quadratic(a, half_b, c)
+        };
+        let side = roots.into_iter().flat_map(|(t0, t1)| [t0, t1]).filter_map(|t| {
+            let p = o + t * d;
+            // the other nappe of the double cone lies above the apex
+            let normal = v3!(p.x(), p.y(), k2 * (h - p.z()));
+            ((0. ..=h).contains(&p.z()) && !normal.near_zero()).then(|| (t, normal, angle(&p), p.z() / h))
+        });
+        let base = if self.capped { cap(&o, &d, 0., radius, -1.) } else { None };
+        self.frame.closest(r, t_min, t_max, side.chain(base), &*self.mat_ptr)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let apex = self.frame.base + self.frame.height * self.frame.uvw.w();
+        Some(circle_box(&self.frame.base, &self.frame.uvw.w(), self.radius).surrounding(&Aabb::new(apex, apex)))
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        // without caps there is no inside
+        self.capped.then(|| walk_spans(self, r, t_min, t_max))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.capped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::v3;
+
+    use crate::{hittable::Hittable, material::Lambertian, ray::Ray};
+
+    use super::{Cone, Cylinder};
+
+    #[test]
+    fn test_cylinder_and_cone() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let cylinder = Cylinder::new(v3!(0., 0., 0.), v3!(0., 2., 0.), 1., true, m.clone());
+        let open = Cylinder::new(v3!(0., 0., 0.), v3!(0., 2., 0.), 1., false, m.clone());
+        let cone = Cone::new(v3!(0., 0., 0.), v3!(0., 2., 0.), 1., true, m);
+
+        // side on, through the axis
+        let across = Ray::new(v3!(-3., 1., 0.), v3!(1., 0., 0.));
+        let rec = cylinder.hit(&across, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.).abs() < 1e-9 && (rec.normal - v3!(-1., 0., 0.)).length() < 1e-9);
+        assert!((cone.hit(&across, 0.001, f64::INFINITY).unwrap().t - 2.5).abs() < 1e-9);
+
+        // down the axis, meeting the cap or, when open, the floor behind
+        let down = Ray::new(v3!(0.5, 5., 0.), v3!(0., -1., 0.));
+        let rec = cylinder.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 3.).abs() < 1e-9 && (rec.normal - v3!(0., 1., 0.)).length() < 1e-9);
+        assert!(open.hit(&down, 0.001, f64::INFINITY).is_none());
+        assert!((cone.hit(&down, 0.001, f64::INFINITY).unwrap().t - 4.).abs() < 1e-9);
+
+        // along a line of the cone, which only crosses it once
+        let parallel = Ray::new(v3!(-1.5, 2., 0.), v3!(1., -2., 0.));
+        let rec = cone.hit(&parallel, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 0.75).abs() < 1e-9 && (rec.p - v3!(-0.75, 0.5, 0.)).length() < 1e-9, "{:?}", rec.p);
+
+        // from inside, the far wall faces back at the ray
+        let inside = Ray::new(v3!(0., 1., 0.), v3!(0., 0., 1.));
+        let rec = open.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.).abs() < 1e-9 && !rec.front_face);
+
+        // boxes hold the shapes
+        let b = cylinder.bounding_box().unwrap();
+        assert!((b.min - v3!(-1., 0., -1.)).length() < 1e-9 && (b.max - v3!(1., 2., 1.)).length() < 1e-9);
+    }
+}
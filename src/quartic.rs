@@ -0,0 +1,205 @@
+//! Surfaces of degree four, which a ray meets where a quartic in its
+//! distance is zero.
+
+use std::sync::Arc;
+
+use utils::{solve_quartic, PI};
+use vec3::{v3, Onb, Point3, Vec3};
+
+use crate::{aabb::Aabb, hittable::{walk_spans, HitRecord, Hittable, Span}, material::Material, ray::Ray};
+
+/// Ring of a tube of radius `minor` swept around a circle of radius `major`
+/// about `center`, with `axis` through the hole. `u` goes around the hole
+/// and `v` around the tube.
+pub struct Torus {
+    center: Point3,
+    uvw: Onb,
+    major: f64,
+    minor: f64,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(center: Point3, axis: Vec3, major: f64, minor: f64, m: Arc<dyn Material>) -> Self {
+        Self {
+            center,
+            uvw: Onb::build_from_w(&axis),
+            major,
+            minor,
+            mat_ptr: m,
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if !self.bounding_box()?.hit(r, t_min, t_max) {
+            return None;
+        }
+        let length = r.direction().length();
+        let d = self.uvw.to_local(r.direction()) / length;
+        let o = self.uvw.to_local(&(*r.origin() - self.center));
+        // solve from the point of the ray nearest the centre, so that far
+        // away origins don't swamp the coefficients
+        let shift = -o.dot(&d);
+        let o = o + shift * d;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along p = o + s d
+        let (r2, big_r2) = (self.minor * self.minor, self.major * self.major);
+        let k = 2. * o.dot(&d);
+        let l = o.length_squared() + big_r2 - r2;
+        let g = 4. * big_r2 * (d.x() * d.x() + d.y() * d.y());
+        let h = 8. * big_r2 * (o.x() * d.x() + o.y() * d.y());
+        let i = 4. * big_r2 * (o.x() * o.x() + o.y() * o.y());
+        let t = solve_quartic(1., 2. * k, k * k + 2. * l - g, 2. * k * l - h, l * l - i)
+            .into_iter()
+            .map(|s| (s + shift) / length)
+            .find(|t| (t_min..=t_max).contains(t))?;
+
+        let p = r.at(t);
+        let local = self.uvw.to_local(&(p - self.center));
+        // away from the nearest point of the circle the tube is swept around
+        let ring = local.x().hypot(local.y());
+        let spine = if ring > 0. { self.major / ring * v3!(local.x(), local.y(), 0.) } else { v3!(0., 0., 0.) };
+        let normal = self.uvw.local(&(local - spine)).unit_vector();
+        let u = (local.y().atan2(local.x()) + PI) / (2. * PI);
+        let v = (local.z().atan2(ring - self.major) + PI) / (2. * PI);
+        let dpdu = 2. * PI * self.uvw.local(&v3!(-local.y(), local.x(), 0.));
+        Some(HitRecord::new(p, t, normal, *r, &*self.mat_ptr).with_uv(u, v).with_tangent(dpdu))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let w = self.uvw.w();
+        let extent = |n: f64| self.major * (1. - n * n).max(0.).sqrt() + self.minor;
+        let e = v3!(extent(w.x()), extent(w.y()), extent(w.z()));
+        Some(Aabb::new(self.center - e, self.center + e))
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        Some(walk_spans(self, r, t_min, t_max))
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+}
+
+/// Implicit surface where the polynomial `f`, of degree four at most in the
+/// coordinates, is zero, within `bounds`. Its outside is where `f` is
+/// positive. Any such surface meets a ray in at most four points, so five
+/// values of `f` along the ray pin it down.
+pub struct QuarticSurface {
+    f: Box<dyn Fn(&Point3) -> f64 + Send + Sync>,
+    bounds: Aabb,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl QuarticSurface {
+    /// `f` has to be positive all over the faces of `bounds`, so that the
+    /// box holds the whole of the surface. Otherwise the box cuts it open
+    /// and it has no inside to tell `spans` about.
+    pub fn new(f: impl Fn(&Point3) -> f64 + Send + Sync + 'static, bounds: Aabb, m: Arc<dyn Material>) -> Self {
+        Self {
+            f: Box::new(f),
+            bounds,
+            mat_ptr: m,
+        }
+    }
+
+    fn gradient(&self, p: &Point3) -> Vec3 {
+        let h = 1e-6 * (self.bounds.max - self.bounds.min).length();
+        let diff = |e: Vec3| (self.f)(&(*p + h * e)) - (self.f)(&(*p - h * e));
+        v3!(diff(v3!(1., 0., 0.)), diff(v3!(0., 1., 0.)), diff(v3!(0., 0., 1.))) / (2. * h)
+    }
+}
+
+impl Hittable for QuarticSurface {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t0, t1) = self.bounds.clip(r, t_min, t_max)?;
+        // the quartic in s from -1 to 1 across the box, from its values at
+        // five evenly spaced points
+        let (mid, half) = ((t0 + t1) / 2., (t1 - t0) / 2.);
+        let [a, b, c, d, e] = [-1., -0.5, 0., 0.5, 1.].map(|s| (self.f)(&r.at(mid + s * half)));
+        let (even_half, even_one) = ((b + d) / 2. - c, (a + e) / 2. - c);
+        let c4 = 4. / 3. * (even_one - 4. * even_half);
+        let c2 = even_one - c4;
+        let (odd_half, odd_one) = ((d - b) / 2., (e - a) / 2.);
+        let c3 = 4. / 3. * (odd_one - 2. * odd_half);
+        let c1 = odd_one - c3;
+        let t = solve_quartic(c4, c3, c2, c1, c)
+            .into_iter()
+            .map(|s| mid + s * half)
+            .find(|t| (t0..=t1).contains(t) && *t >= t_min)?;
+
+        let p = r.at(t);
+        let normal = self.gradient(&p).unit_vector();
+        Some(HitRecord::new(p, t, normal, *r, &*self.mat_ptr))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        Some(walk_spans(self, r, t_min, t_max))
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::v3;
+
+    use crate::{aabb::Aabb, hittable::Hittable, material::Lambertian, ray::Ray};
+
+    use super::{QuarticSurface, Torus};
+
+    #[test]
+    fn test_torus() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let torus = Torus::new(v3!(0., 0., 0.), v3!(0., 0., 1.), 2., 0.5, m.clone());
+
+        // straight through the tube and the hole, from far away
+        let far = Ray::new(v3!(-1e4, 0., 0.), v3!(1., 0., 0.));
+        let rec = torus.hit(&far, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - v3!(-2.5, 0., 0.)).length() < 1e-6, "{:?}", rec.p);
+        assert!((rec.normal - v3!(-1., 0., 0.)).length() < 1e-6);
+        let rec = torus.hit(&far, rec.t + 1e-3, f64::INFINITY).unwrap();
+        assert!((rec.p - v3!(-1.5, 0., 0.)).length() < 1e-6 && !rec.front_face);
+
+        // from inside the tube, the wall faces back at the ray
+        let inside = Ray::new(v3!(2., 0., 0.), v3!(0., 0., 2.));
+        let rec = torus.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - v3!(2., 0., 0.5)).length() < 1e-9 && !rec.front_face);
+
+        // skimming the top of the ring, just below and just above
+        let below = Ray::new(v3!(-5., 0., 0.5 - 1e-6), v3!(1., 0., 0.));
+        let rec = torus.hit(&below, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p.x() + 2.).abs() < 1e-2 && rec.normal.z() > 0.99);
+        let above = Ray::new(v3!(-5., 0., 0.5 + 1e-6), v3!(1., 0., 0.));
+        assert!(torus.hit(&above, 0.001, f64::INFINITY).is_none());
+
+        // a tilted torus stays in its box
+        let tilted = Torus::new(v3!(1., 2., 3.), v3!(1., 1., 0.), 2., 0.5, m);
+        let b = tilted.bounding_box().unwrap();
+        let e = 2. * 0.5_f64.sqrt() + 0.5;
+        assert!((b.max - v3!(1. + e, 2. + e, 5.5)).length() < 1e-9);
+    }
+
+    #[test]
+    fn test_quartic_surface() {
+        // a sphere is a quartic too, with the top terms zero
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let bounds = Aabb::new(v3!(-1.5, -1.5, -1.5), v3!(1.5, 1.5, 1.5));
+        let sphere = QuarticSurface::new(|p| p.length_squared() - 1., bounds, m);
+        let r = Ray::new(v3!(0.6, 0., -5.), v3!(0., 0., 1.));
+        let rec = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.2).abs() < 1e-9 && (rec.normal - v3!(0.6, 0., -0.8)).length() < 1e-6);
+        assert!(sphere.hit(&Ray::new(v3!(1.1, 0., -5.), v3!(0., 0., 1.)), 0.001, f64::INFINITY).is_none());
+    }
+}
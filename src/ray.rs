@@ -4,6 +4,8 @@ use vec3::{Vec3, Point3};
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
+    /// in nanometres, set when rendering spectrally
+    pub wavelength: Option<f64>,
 }
 
 impl Ray {
@@ -11,6 +13,7 @@ impl Ray {
         Ray {
             orig: origin,
             dir: direction,
+            wavelength: None,
         }
     }
 
@@ -0,0 +1,706 @@
+use std::sync::Arc;
+
+use utils::{random_double, random_double_range};
+use vec3::{v3, Color, Vec3};
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    camera::Camera,
+    csg::Csg,
+    hittable::{HitRecord, Hittable, HittableList},
+    light::{DeltaLight, DirectionalLight, PointLight, SpotLight},
+    material::{Conductor, Dielectric, DiffuseLight, Lambertian, Layered, Material, Metal, MixMaterial, OrenNayar, RoughDielectric, Subsurface},
+    plane::{Annulus, Disk, Plane},
+    principled::{Principled, PrincipledParams},
+    quad::{make_box, Quad},
+    quadric::{Cone, Cylinder},
+    quartic::{QuarticSurface, Torus},
+    ray::Ray,
+    sdf::{Sdf, SdfBox, SdfCapsule, SdfFn, SdfShape, SdfSphere, SdfTorus, SmoothIntersection, SmoothSubtraction, SmoothUnion},
+    sphere::Sphere,
+    texture::{Checker, Noise},
+    triangle::Triangle,
+};
+
+/// What a ray sees when it leaves the scene.
+pub enum Background {
+    /// the white to blue gradient from the book
+    Sky,
+    Solid(Color),
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky => {
+                let unit_direction = ray.direction().unit_vector();
+                let t = 0.5 * (unit_direction.y() + 1.);
+                (1. - t) * v3!(1., 1., 1.) + t * v3!(0.5, 0.7, 1.0)
+            }
+            Background::Solid(c) => *c,
+        }
+    }
+
+    /// Whether no light comes from the background.
+    pub fn is_black(&self) -> bool {
+        match self {
+            Background::Sky => false,
+            Background::Solid(c) => c.near_zero(),
+        }
+    }
+}
+
+pub struct Scene {
+    pub world: Bvh,
+    /// emissive objects of `world`, sampled directly at every diffuse hit
+    pub lights: HittableList,
+    /// point, spot and directional lights, reached only by light sampling
+    pub delta_lights: Vec<Arc<dyn DeltaLight>>,
+    pub camera: Camera,
+    pub background: Background,
+}
+
+impl Scene {
+    pub fn new(world: HittableList, camera: Camera, background: Background) -> Self {
+        let lights = world.lights();
+        Self {
+            world: Bvh::new(world),
+            lights,
+            delta_lights: vec![],
+            camera,
+            background,
+        }
+    }
+
+    /// Whether `rec`, found along `ray`, lies on one of `lights`.
+    pub fn is_light(&self, ray: &Ray, rec: &HitRecord) -> bool {
+        match self.lights.hit(ray, 0.001, rec.t + 1e-6) {
+            Some(light_rec) => (light_rec.t - rec.t).abs() < 1e-6,
+            None => false,
+        }
+    }
+
+    pub fn add_light(&mut self, light: Arc<dyn DeltaLight>) {
+        self.delta_lights.push(light);
+    }
+
+    /// Look up a scene by name, as given on the command line.
+    pub fn by_name(name: &str, aspect_ratio: f64) -> Option<Self> {
+        match name {
+            "random" => Some(random_scene(aspect_ratio)),
+            "cornell" => Some(cornell_box(aspect_ratio)),
+            "lamps" => Some(lamps(aspect_ratio)),
+            "mis" => Some(veach_mis(aspect_ratio)),
+            "delta" => Some(delta_lights(aspect_ratio)),
+            "bulb" => Some(glass_bulb(aspect_ratio)),
+            "caustics" => Some(caustics(aspect_ratio)),
+            "materials" => Some(materials(aspect_ratio)),
+            "prism" => Some(prism(aspect_ratio)),
+            "subsurface" => Some(subsurface(aspect_ratio)),
+            "kelvin" => Some(kelvin(aspect_ratio)),
+            "shapes" => Some(shapes(aspect_ratio)),
+            "quartic" => Some(quartic(aspect_ratio)),
+            "sdf" => Some(sdf(aspect_ratio)),
+            "csg" => Some(csg(aspect_ratio)),
+            _ => None,
+        }
+    }
+}
+
+/// The spheres from the cover of the book.
+fn random_spheres() -> HittableList {
+    let mut world = HittableList::new();
+    let ground_material = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), ground_material)));
+
+    for a in -11..=11 {
+        for b in -11..=11 {
+            let choose_mat = random_double();
+            let center = v3!(a as f64 + 0.9 * random_double(), 0.2, b as f64 + 0.9 * random_double());
+            if (center - v3!(4., 0.2, 0.)).length() > 0.9 {
+                let sphere_material: Arc<dyn Material> = if choose_mat < 0.8 {
+                    let albedo = Vec3::random() * Vec3::random();
+                    Arc::new(Lambertian::new(&albedo))
+                } else if choose_mat < 0.95 {
+                    let albedo = Vec3::random_range(0.5, 1.);
+                    let fuzz = random_double_range(0., 0.5);
+                    Arc::new(Metal::new(&albedo, fuzz))
+                } else {
+                    Arc::new(Dielectric::new(1.5))
+                };
+                world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
+            }
+        }
+    }
+    let material1 = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(v3!(0., 1., 0.), 1., material1)));
+    let material2 = Arc::new(Lambertian::new(&v3!(0.4, 0.2, 0.1)));
+    world.add(Arc::new(Sphere::new(v3!(-4., 1., 0.), 1., material2)));
+    let material3 = Arc::new(Metal::new(&v3!(0.7, 0.6, 0.5), 0.0));
+    world.add(Arc::new(Sphere::new(v3!(4., 1., 0.), 1., material3)));
+    world
+}
+
+pub fn random_scene(aspect_ratio: f64) -> Scene {
+    let world = random_spheres();
+
+    let lookfrom = v3!(13., 2., 3.);
+    let lookat = v3!(0., 0., 0.);
+    let vup = v3!(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.1;
+    let camera = Camera::new(
+        lookfrom, lookat ,vup, 20., aspect_ratio, aperture, dist_to_focus);
+
+    Scene::new(world, camera, Background::Sky)
+}
+
+/// The book cover in low sunlight, so that the glass spheres cast caustics.
+pub fn caustics(aspect_ratio: f64) -> Scene {
+    let world = random_spheres();
+
+    let lookfrom = v3!(13., 2., 3.);
+    let lookat = v3!(0., 0., 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 20., aspect_ratio, 0.1, 10.);
+
+    let mut scene = Scene::new(world, camera, Background::Solid(v3!(0.15, 0.2, 0.3)));
+    scene.add_light(Arc::new(DirectionalLight::new(v3!(0.7, -0.5, 0.2), v3!(3., 2.8, 2.5))));
+    scene
+}
+
+/// The classic Cornell box, lit only by a small area light in the ceiling.
+pub fn cornell_box(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let red = Arc::new(Lambertian::new(&v3!(0.65, 0.05, 0.05)));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&v3!(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new(&v3!(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new(&v3!(15., 15., 15.)));
+
+    world.add(Arc::new(Quad::new(v3!(555., 0., 0.), v3!(0., 555., 0.), v3!(0., 0., 555.), green)));
+    world.add(Arc::new(Quad::new(v3!(0., 0., 0.), v3!(0., 555., 0.), v3!(0., 0., 555.), red)));
+    world.add(Arc::new(Quad::new(v3!(343., 554., 332.), v3!(-130., 0., 0.), v3!(0., 0., -105.), light)));
+    world.add(Arc::new(Quad::new(v3!(0., 0., 0.), v3!(555., 0., 0.), v3!(0., 0., 555.), white.clone())));
+    world.add(Arc::new(Quad::new(v3!(555., 555., 555.), v3!(-555., 0., 0.), v3!(0., 0., -555.), white.clone())));
+    world.add(Arc::new(Quad::new(v3!(0., 0., 555.), v3!(555., 0., 0.), v3!(0., 555., 0.), white.clone())));
+
+    world.add(Arc::new(make_box(v3!(265., 0., 295.), v3!(430., 330., 460.), white.clone())));
+    world.add(Arc::new(make_box(v3!(130., 0., 65.), v3!(295., 165., 230.), white)));
+
+    let lookfrom = v3!(278., 278., -800.);
+    let lookat = v3!(278., 278., 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 40., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// A closed room lit by a small spherical bulb and a triangular panel, which
+/// pure path tracing would almost never find.
+pub fn lamps(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let wall: Arc<dyn Material> = Arc::new(Lambertian::new(&v3!(0.7, 0.7, 0.65)));
+    world.add(Arc::new(make_box(v3!(-5., 0., -5.), v3!(5., 6., 5.), wall)));
+
+    let floor_ball = Arc::new(Lambertian::new(&v3!(0.2, 0.3, 0.7)));
+    world.add(Arc::new(Sphere::new(v3!(-1.5, 1., 0.), 1., floor_ball)));
+    let mirror_ball = Arc::new(Metal::new(&v3!(0.8, 0.8, 0.8), 0.05));
+    world.add(Arc::new(Sphere::new(v3!(1.5, 1., -0.5), 1., mirror_ball)));
+    let glass_ball = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(v3!(0.3, 0.6, 1.8), 0.6, glass_ball)));
+
+    let bulb = Arc::new(DiffuseLight::new(&v3!(120., 108., 90.)));
+    world.add(Arc::new(Sphere::new(v3!(2., 4.5, 2.), 0.15, bulb)));
+    // wound so that its normal, and so its emitting face, points down
+    let panel = Arc::new(DiffuseLight::new(&v3!(8., 10., 14.)));
+    world.add(Arc::new(Triangle::new(v3!(-3., 5.99, -3.), v3!(-1.5, 5.99, -3.), v3!(-3., 5.99, -1.5), panel)));
+
+    let lookfrom = v3!(0., 3., 4.9);
+    let lookat = v3!(0., 1.5, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 70., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// Veach's multiple importance sampling test: metal plates of increasing
+/// roughness reflecting spherical lights of increasing size but equal power.
+pub fn veach_mis(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let lookfrom = v3!(0., 2., 15.);
+    let light_row = v3!(0., 3.5, -4.);
+
+    let floor = Arc::new(Lambertian::new(&v3!(0.3, 0.3, 0.3)));
+    world.add(Arc::new(Quad::new(v3!(-20., -4., -20.), v3!(0., 0., 40.), v3!(40., 0., 0.), floor)));
+
+    for (i, fuzz) in [0.02, 0.08, 0.2, 0.45].into_iter().enumerate() {
+        let center = v3!(0., -3.2 + 0.75 * i as f64, 3. - 1.5 * i as f64);
+        // tilt each plate so it mirrors the lights towards the camera
+        let normal = ((lookfrom - center).unit_vector() + (light_row - center).unit_vector()).unit_vector();
+        let u = v3!(9., 0., 0.);
+        let v = 1.2 * normal.cross(&v3!(1., 0., 0.)).unit_vector();
+        let plate = Arc::new(Metal::new(&v3!(0.8, 0.8, 0.8), fuzz));
+        world.add(Arc::new(Quad::new(center - u / 2. - v / 2., u, v, plate)));
+    }
+
+    for (i, radius) in [0.03, 0.1, 0.3, 0.9_f64].into_iter().enumerate() {
+        let center = light_row + v3!(-3.75 + 2.5 * i as f64, 0., 0.);
+        let light = Arc::new(DiffuseLight::new(&(v3!(1., 0.9, 0.7) * (0.8 / (radius * radius)))));
+        world.add(Arc::new(Sphere::new(center, radius, light)));
+    }
+
+    let lookat = v3!(0., -0.5, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 38., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// A few spheres at dusk, lit by the sun, a spot light and a point light.
+pub fn delta_lights(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), ground)));
+    let diffuse = Arc::new(Lambertian::new(&v3!(0.7, 0.3, 0.2)));
+    world.add(Arc::new(Sphere::new(v3!(-2.2, 1., 0.), 1., diffuse)));
+    let metal = Arc::new(Metal::new(&v3!(0.8, 0.8, 0.9), 0.2));
+    world.add(Arc::new(Sphere::new(v3!(0., 1., 0.), 1., metal)));
+    let glass = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(v3!(2.2, 1., 0.), 1., glass)));
+
+    let lookfrom = v3!(0., 3., 9.);
+    let lookat = v3!(0., 0.8, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 35., aspect_ratio, 0., 10.);
+
+    let mut scene = Scene::new(world, camera, Background::Solid(v3!(0.02, 0.03, 0.08)));
+    scene.add_light(Arc::new(DirectionalLight::new(v3!(1., -0.4, -0.6), v3!(1.2, 0.7, 0.4))));
+    scene.add_light(Arc::new(SpotLight::new(v3!(-2.2, 5., 3.), v3!(-2.2, 0.5, 0.), v3!(40., 40., 36.), 25., 15.)));
+    scene.add_light(Arc::new(PointLight::new(v3!(2.5, 3., 2.), v3!(6., 8., 12.))));
+    scene
+}
+
+/// A room lit only by a lamp inside a glass globe. Light sampling from the
+/// walls is always blocked by the glass, so this needs bidirectional methods.
+pub fn glass_bulb(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let wall: Arc<dyn Material> = Arc::new(Lambertian::new(&v3!(0.75, 0.72, 0.68)));
+    world.add(Arc::new(make_box(v3!(-4., 0., -4.), v3!(4., 5., 4.), wall)));
+
+    let red = Arc::new(Lambertian::new(&v3!(0.7, 0.15, 0.1)));
+    world.add(Arc::new(make_box(v3!(-2.5, 0., -1.5), v3!(-1., 1.5, 0.), red)));
+    let blue = Arc::new(Lambertian::new(&v3!(0.15, 0.25, 0.7)));
+    world.add(Arc::new(Sphere::new(v3!(1.5, 0.8, 0.5), 0.8, blue)));
+
+    let globe = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(v3!(0., 3.6, 0.), 0.6, globe)));
+    let filament = Arc::new(DiffuseLight::new(&v3!(60., 50., 35.)));
+    world.add(Arc::new(Sphere::new(v3!(0., 3.6, 0.), 0.15, filament)));
+
+    let lookfrom = v3!(0., 2.2, 3.9);
+    let lookat = v3!(0., 1.8, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 75., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// Rows of spheres, one for each kind of material, under the sky and a
+/// large soft light.
+pub fn materials(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(&v3!(0.4, 0.4, 0.4)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), ground)));
+    let light = Arc::new(DiffuseLight::new(&v3!(4., 4., 4.)));
+    world.add(Arc::new(Quad::new(v3!(-5., 15., -5.), v3!(10., 0., 0.), v3!(0., 0., 10.), light)));
+
+    let materials: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Conductor::gold(0.2)),
+        Arc::new(Conductor::copper(0.4)),
+        Arc::new(Conductor::aluminium(0.6)),
+        Arc::new(Conductor::new(&v3!(1.657, 0.880, 0.521), &v3!(9.224, 6.270, 4.837), 0.2, 0.7)),
+        Arc::new(Dielectric::new(1.5)),
+        Arc::new(RoughDielectric::new(1.5, 0.3)),
+        Arc::new(RoughDielectric::new(1.5, 0.5)),
+        Arc::new(RoughDielectric::new(1.5, 0.8)),
+        Arc::new(Dielectric::new(1.5).with_absorption(&v3!(0.1, 0.5, 1.2))),
+        Arc::new(Dielectric::new(1.33).with_absorption(&v3!(1.2, 0.25, 0.1))),
+        Arc::new(Dielectric::new(1.).with_thin_film(350., 1.33)),
+        // anodised titanium
+        Arc::new(Conductor::new(&v3!(2.74, 2.54, 2.27), &v3!(3.82, 3.43, 3.04), 0.15, 0.15).with_thin_film(120., 2.3)),
+        // red plastic, car paint, velvet and green glass
+        Arc::new(Principled::new(PrincipledParams {
+            base_color: v3!(0.7, 0.05, 0.05),
+            roughness: 0.3,
+            ..Default::default()
+        })),
+        Arc::new(Principled::new(PrincipledParams {
+            base_color: v3!(0.05, 0.15, 0.5),
+            metallic: 0.6,
+            roughness: 0.5,
+            clearcoat: 1.,
+            ..Default::default()
+        })),
+        Arc::new(Principled::new(PrincipledParams {
+            base_color: v3!(0.3, 0.05, 0.25),
+            roughness: 1.,
+            sheen: 1.,
+            ..Default::default()
+        })),
+        Arc::new(Principled::new(PrincipledParams {
+            base_color: v3!(0.6, 0.9, 0.7),
+            roughness: 0.1,
+            transmission: 1.,
+            ..Default::default()
+        })),
+        // glossy lacquer over red paint, satin varnish over wood, clear coat
+        // over brushed gold and a matt coat over blue paint
+        Arc::new(Layered::new(Arc::new(Dielectric::new(1.5)), Arc::new(Lambertian::new(&v3!(0.6, 0.05, 0.05))))),
+        Arc::new(Layered::new(Arc::new(RoughDielectric::new(1.5, 0.3)), Arc::new(Lambertian::new(&v3!(0.45, 0.25, 0.1))))),
+        Arc::new(Layered::new(Arc::new(Dielectric::new(1.5)), Arc::new(Conductor::gold(0.5)))),
+        Arc::new(Layered::new(Arc::new(RoughDielectric::new(1.5, 0.6)), Arc::new(Lambertian::new(&v3!(0.1, 0.2, 0.5))))),
+        // rust on steel, dirty gold, a checkered mix of plastics and an even
+        // mix of paint and metal
+        Arc::new(MixMaterial::new(
+            Arc::new(Conductor::new(&v3!(2.87, 2.92, 2.58), &v3!(3.13, 2.93, 2.61), 0.3, 0.3)),
+            Arc::new(Lambertian::new(&v3!(0.35, 0.12, 0.04))),
+            Arc::new(Noise::new(2., 0.35, 1)),
+        )),
+        Arc::new(MixMaterial::new(
+            Arc::new(Conductor::gold(0.2)),
+            Arc::new(Lambertian::new(&v3!(0.15, 0.12, 0.08))),
+            Arc::new(Noise::new(4., 0.3, 2)),
+        )),
+        Arc::new(MixMaterial::new(
+            Arc::new(Lambertian::new(&v3!(0.8, 0.8, 0.8))),
+            Arc::new(Lambertian::new(&v3!(0.7, 0.05, 0.05))),
+            Arc::new(Checker { frequency: 8. }),
+        )),
+        Arc::new(MixMaterial::constant(
+            Arc::new(Lambertian::new(&v3!(0.1, 0.4, 0.1))),
+            Arc::new(Metal::new(&v3!(0.9, 0.9, 0.9), 0.1)),
+            0.5,
+        )),
+        // clay, smooth and then rougher, and concrete
+        Arc::new(Lambertian::new(&v3!(0.6, 0.35, 0.25))),
+        Arc::new(OrenNayar::new(&v3!(0.6, 0.35, 0.25), 20.)),
+        Arc::new(OrenNayar::new(&v3!(0.6, 0.35, 0.25), 45.)),
+        Arc::new(OrenNayar::new(&v3!(0.5, 0.5, 0.48), 60.)),
+    ];
+    let columns = 4;
+    let rows = materials.len().div_ceil(columns);
+    for (i, material) in materials.into_iter().enumerate() {
+        let x = (i % columns) as f64 * 2.2 - (columns - 1) as f64 * 1.1;
+        let z = (i / columns) as f64 * 2.2 - (rows - 1) as f64 * 1.1;
+        world.add(Arc::new(Sphere::new(v3!(x, 0.9, z), 0.9, material)));
+    }
+
+    // step back as rows are added
+    let lookfrom = v3!(0., 3. + 2. * rows as f64, 6. + 2.5 * rows as f64);
+    let lookat = v3!(0., 0., 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 40., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Sky)
+}
+
+/// A dense flint glass prism and two glass balls in front of a wall of thin
+/// white lights, which come out fringed with colour under the spectral
+/// integrator.
+pub fn prism(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let wall = Arc::new(Lambertian::new(&v3!(0.1, 0.1, 0.1)));
+    world.add(Arc::new(Quad::new(v3!(-12., -12., -5.), v3!(24., 0., 0.), v3!(0., 18., 0.), wall)));
+    let stripe = Arc::new(DiffuseLight::new(&v3!(4., 4., 4.)));
+    for i in 0..16 {
+        let y = -11. + i as f64;
+        world.add(Arc::new(Quad::new(v3!(-12., y, -4.99), v3!(24., 0., 0.), v3!(0., 0.1, 0.), stripe.clone())));
+    }
+
+    // a 40 degree prism lying along x, apex up, with every face turned out
+    let glass = Arc::new(Dielectric::sf11());
+    let (x0, length) = (-2., 4.);
+    let apex = v3!(0., 0.7, 0.);
+    let front = v3!(0., -0.7, 0.51);
+    let back = v3!(0., -0.7, -0.51);
+    let along = v3!(length, 0., 0.);
+    let at = |p: Vec3, x: f64| v3!(x, p.y(), p.z());
+    world.add(Arc::new(Quad::new(at(front, x0), along, apex - front, glass.clone())));
+    world.add(Arc::new(Quad::new(at(back, x0), apex - back, along, glass.clone())));
+    world.add(Arc::new(Quad::new(at(back, x0), along, front - back, glass.clone())));
+    world.add(Arc::new(Triangle::new(at(apex, x0), at(back, x0), at(front, x0), glass.clone())));
+    world.add(Arc::new(Triangle::new(at(apex, x0 + length), at(front, x0 + length), at(back, x0 + length), glass)));
+
+    // crown glass and fused silica, spreading colours far less
+    world.add(Arc::new(Sphere::new(v3!(-2.6, 1.4, 0.), 0.5, Arc::new(Dielectric::bk7()))));
+    world.add(Arc::new(Sphere::new(v3!(2.6, 1.4, 0.), 0.5, Arc::new(Dielectric::cauchy(1.4580, 0.00354)))));
+
+    let lookfrom = v3!(0., 0., 5.);
+    let lookat = v3!(0., 0., 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 45., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// Marble, skin and wax lit from behind, so that light shows through
+/// their edges.
+pub fn subsurface(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor)));
+
+    // coefficients per unit of distance, a sphere being a few centimetres
+    // across; skin absorbs as measured by Jensen et al., "A Practical Model
+    // for Subsurface Light Transport". Scattering the same in every channel
+    // keeps long walks from getting noisy in colour.
+    let marble = Arc::new(Subsurface::new(1.5, &v3!(0.02, 0.04, 0.07), &v3!(20., 20., 20.), 0.));
+    let skin = Arc::new(Subsurface::new(1.4, &v3!(0.32, 1.7, 4.8), &v3!(8.8, 8.8, 8.8), 0.));
+    let wax = Arc::new(Subsurface::new(1.45, &v3!(0.05, 0.15, 0.6), &v3!(6., 6., 6.), 0.3));
+    world.add(Arc::new(Sphere::new(v3!(-2.2, 1., 0.), 1., marble)));
+    world.add(Arc::new(Sphere::new(v3!(0., 1., 0.), 1., skin)));
+    world.add(Arc::new(Sphere::new(v3!(2.2, 1., 0.), 1., wax)));
+
+    let back = Arc::new(DiffuseLight::new(&v3!(6., 6., 6.)));
+    world.add(Arc::new(Quad::new(v3!(-4., 0.5, -3.), v3!(8., 0., 0.), v3!(0., 3., 0.), back)));
+    let fill = Arc::new(DiffuseLight::new(&v3!(1.5, 1.5, 1.5)));
+    world.add(Arc::new(Quad::new(v3!(-3., 6., 2.), v3!(6., 0., 0.), v3!(0., 0., 3.), fill)));
+
+    let lookfrom = v3!(0., 2.5, 8.);
+    let lookat = v3!(0., 1., 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 35., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// A sphere of a measured material next to one of the principled material
+/// with its default settings, lit as in `materials`.
+pub fn measured(aspect_ratio: f64, brdf: Arc<dyn Material>) -> Scene {
+    let mut world = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(&v3!(0.4, 0.4, 0.4)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), ground)));
+    let light = Arc::new(DiffuseLight::new(&v3!(4., 4., 4.)));
+    world.add(Arc::new(Quad::new(v3!(-5., 15., -5.), v3!(10., 0., 0.), v3!(0., 0., 10.), light)));
+
+    world.add(Arc::new(Sphere::new(v3!(-1.1, 1., 0.), 1., brdf)));
+    let reference = Arc::new(Principled::new(PrincipledParams::default()));
+    world.add(Arc::new(Sphere::new(v3!(1.1, 1., 0.), 1., reference)));
+
+    let lookfrom = v3!(0., 3., 7.);
+    let lookat = v3!(0., 0.8, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 30., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Sky)
+}
+
+/// White spheres under a row of equally bright lamps, from candle light on
+/// the left to a blue sky on the right, and an ember glowing red hot.
+pub fn kelvin(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(&v3!(0.8, 0.8, 0.8)));
+    world.add(Arc::new(Quad::new(v3!(-6., 0., -2.), v3!(0., 0., 6.), v3!(12., 0., 0.), white.clone())));
+    world.add(Arc::new(Quad::new(v3!(-6., 0., -2.), v3!(12., 0., 0.), v3!(0., 4., 0.), white.clone())));
+
+    let size = 0.4;
+    for (i, temperature) in [1900., 2700., 4000., 6500., 12000.].into_iter().enumerate() {
+        let x = -4. + 2. * i as f64;
+        world.add(Arc::new(Sphere::new(v3!(x, 0.6, 0.), 0.6, white.clone())));
+        // a lamp is as bright whatever its colour
+        let lamp = Arc::new(DiffuseLight::blackbody_lumens(temperature, 12_000., size * size));
+        world.add(Arc::new(Quad::new(v3!(x - size / 2., 2.5, -size / 2.), v3!(size, 0., 0.), v3!(0., 0., size), lamp)));
+    }
+    let ember = Arc::new(DiffuseLight::blackbody(1100., 0.6));
+    world.add(Arc::new(Sphere::new(v3!(0., 0.2, 2.), 0.2, ember)));
+
+    let lookfrom = v3!(0., 2., 9.);
+    let lookat = v3!(0., 1., 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 40., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0., 0., 0.)))
+}
+
+/// Each of the analytic shapes on an endless floor, under a round light and
+/// a ring light.
+pub fn shapes(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let floor = Arc::new(MixMaterial::new(
+        Arc::new(Lambertian::new(&v3!(0.8, 0.8, 0.8))),
+        Arc::new(Lambertian::new(&v3!(0.2, 0.3, 0.1))),
+        Arc::new(Checker { frequency: 1. }),
+    ));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor)));
+
+    let red = Arc::new(Lambertian::new(&v3!(0.7, 0.15, 0.1)));
+    world.add(Arc::new(Cylinder::new(v3!(-3., 0., 0.), v3!(-3., 1.6, 0.), 0.6, true, red)));
+    let copper = Arc::new(Metal::new(&v3!(0.95, 0.64, 0.54), 0.15));
+    world.add(Arc::new(Cone::new(v3!(-1., 0., 0.), v3!(-1., 1.8, 0.), 0.7, true, copper)));
+    // open at both ends, so that the inside shows
+    let pipe = Arc::new(Lambertian::new(&v3!(0.2, 0.4, 0.7)));
+    world.add(Arc::new(Cylinder::new(v3!(1., 0.5, -0.8), v3!(1., 0.5, 0.8), 0.5, false, pipe)));
+    let glass = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Cylinder::new(v3!(3., 0., 0.), v3!(3., 1.2, 0.), 0.6, true, glass)));
+    let gold = Arc::new(Metal::new(&v3!(1., 0.78, 0.34), 0.05));
+    world.add(Arc::new(Disk::new(v3!(0., 0.01, 2.), v3!(0., 1., 0.), 0.6, gold)));
+
+    let light = Arc::new(DiffuseLight::new(&v3!(6., 6., 6.)));
+    world.add(Arc::new(Disk::new(v3!(-2., 5., 1.), v3!(0., -1., 0.), 1., light)));
+    let ring = Arc::new(DiffuseLight::new(&v3!(3., 2.4, 1.8)));
+    world.add(Arc::new(Annulus::new(v3!(2.5, 4., 2.), v3!(0., -1., 0.), 0.6, 0.9, ring)));
+
+    let lookfrom = v3!(0., 3., 9.);
+    let lookat = v3!(0., 0.7, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 40., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Solid(v3!(0.05, 0.06, 0.08)))
+}
+
+/// Two linked rings, a glass one standing in a metal one, beside a
+/// tanglecube.
+pub fn quartic(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor)));
+
+    let steel = Arc::new(Metal::new(&v3!(0.8, 0.8, 0.85), 0.1));
+    world.add(Arc::new(Torus::new(v3!(-1.2, 0.3, 0.), v3!(0., 1., 0.), 1., 0.3, steel)));
+    let glass = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Torus::new(v3!(-0.2, 1.1, 0.), v3!(0., 0., 1.), 1., 0.25, glass)));
+
+    // x^4 - 5x^2 + y^4 - 5y^2 + z^4 - 5z^2 + 11.8 = 0, shrunk to fit
+    let (center, scale) = (v3!(2.3, 0.9, -0.5), 0.38);
+    let tangle = move |p: &Vec3| {
+        let q = (*p - center) / scale;
+        let term = |x: f64| x * x * x * x - 5. * x * x;
+        term(q.x()) + term(q.y()) + term(q.z()) + 11.8
+    };
+    let extent = v3!(2.3, 2.3, 2.3) * scale;
+    let ceramic = Arc::new(Lambertian::new(&v3!(0.8, 0.5, 0.2)));
+    world.add(Arc::new(QuarticSurface::new(tangle, Aabb::new(center - extent, center + extent), ceramic)));
+
+    let light = Arc::new(DiffuseLight::new(&v3!(4., 4., 4.)));
+    world.add(Arc::new(Disk::new(v3!(-1., 6., 3.), v3!(0., -1., 0.), 2., light)));
+
+    let lookfrom = v3!(0., 3., 8.);
+    let lookat = v3!(0.5, 0.8, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 35., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Sky)
+}
+
+/// Shapes built from distance functions: a rounded die with a ball carved
+/// out of it, a glass blob of a capsule melting into a ring, a lens where
+/// two balls overlap, and a ball with ripples written out by hand.
+pub fn sdf(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor)));
+
+    let die = Arc::new(SmoothSubtraction {
+        a: Arc::new(SdfBox { center: v3!(-2.2, 0.8, 0.), half_size: v3!(0.8, 0.8, 0.8), rounding: 0.15 }),
+        b: Arc::new(SdfSphere { center: v3!(-2.2, 1.4, 0.6), radius: 0.7 }),
+        k: 0.15,
+    });
+    let red = Arc::new(Lambertian::new(&v3!(0.7, 0.12, 0.1)));
+    world.add(Arc::new(SdfShape::new(die, red)));
+
+    let blob = Arc::new(SmoothUnion {
+        a: Arc::new(SdfCapsule { a: v3!(0., 0.4, 0.), b: v3!(0., 1.8, 0.), radius: 0.3 }),
+        b: Arc::new(SdfTorus { center: v3!(0., 0.3, 0.), major: 0.8, minor: 0.25 }),
+        k: 0.5,
+    });
+    let glass = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(SdfShape::new(blob, glass)));
+
+    let lens: Arc<dyn Sdf> = Arc::new(SmoothIntersection {
+        a: Arc::new(SdfSphere { center: v3!(1.6, 0.9, 0.), radius: 1.2 }),
+        b: Arc::new(SdfSphere { center: v3!(2.8, 0.9, 0.), radius: 1.2 }),
+        k: 0.1,
+    });
+    let gold = Arc::new(Metal::new(&v3!(1., 0.78, 0.34), 0.1));
+    world.add(Arc::new(SdfShape::new(lens, gold)));
+
+    // the ripples can change the distance almost as fast as it changes by
+    // itself, so halve it to keep it from overestimating
+    let center = v3!(1., 0.7, -2.5);
+    let ripples = move |p: &Vec3| {
+        let q = 10. * (*p - center);
+        0.5 * ((*p - center).length() - 0.6 + 0.05 * q.x().sin() * q.y().sin() * q.z().sin())
+    };
+    let extent = v3!(0.7, 0.7, 0.7);
+    let ball = Arc::new(SdfFn::new(ripples, Aabb::new(center - extent, center + extent)));
+    let blue = Arc::new(Metal::new(&v3!(0.3, 0.45, 0.8), 0.3));
+    world.add(Arc::new(SdfShape::new(ball, blue)));
+
+    let light = Arc::new(DiffuseLight::new(&v3!(4., 4., 4.)));
+    world.add(Arc::new(Disk::new(v3!(-1., 6., 3.), v3!(0., -1., 0.), 2., light)));
+
+    let lookfrom = v3!(0., 3., 8.);
+    let lookat = v3!(0., 0.8, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 35., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Sky)
+}
+
+/// Solids modelled by combining simpler ones: a glass lens where two balls
+/// overlap, a ball cut open to show its core, and a cube rounded by a ball
+/// and drilled through along each axis.
+pub fn csg(aspect_ratio: f64) -> Scene {
+    let mut world = HittableList::new();
+
+    let floor = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Plane::new(v3!(0., 0., 0.), v3!(0., 1., 0.), floor)));
+
+    let glass = Arc::new(Dielectric::new(1.5));
+    let lens = Csg::intersection(
+        Arc::new(Sphere::new(v3!(-2.2, 1.1, -1.7), 2., glass.clone())),
+        Arc::new(Sphere::new(v3!(-2.2, 1.1, 1.7), 2., glass)),
+    );
+    world.add(Arc::new(lens));
+
+    // a shell with an eighth taken out, the cut faces pale, around a red core
+    let shell = Arc::new(Sphere::new(v3!(0., 1., 0.), 1., Arc::new(Lambertian::new(&v3!(0.2, 0.35, 0.6)))));
+    let cut = Arc::new(Lambertian::new(&v3!(0.85, 0.82, 0.75)));
+    let octant = Arc::new(SdfShape::new(Arc::new(SdfBox { center: v3!(0.6, 1.6, 0.6), half_size: v3!(0.6, 0.6, 0.6), rounding: 0. }), cut));
+    let core = Arc::new(Sphere::new(v3!(0., 1., 0.), 0.5, Arc::new(Lambertian::new(&v3!(0.7, 0.1, 0.08)))));
+    world.add(Arc::new(Csg::union(Arc::new(Csg::difference(shell, octant)), core)));
+
+    let steel = Arc::new(Metal::new(&v3!(0.8, 0.8, 0.85), 0.2));
+    let center = v3!(2.3, 0.8, 0.);
+    let cube = Arc::new(SdfShape::new(Arc::new(SdfBox { center, half_size: v3!(0.8, 0.8, 0.8), rounding: 0. }), steel.clone()));
+    let mut solid: Arc<dyn Hittable> = Arc::new(Csg::intersection(cube, Arc::new(Sphere::new(center, 1.05, steel.clone()))));
+    for axis in [v3!(1., 0., 0.), v3!(0., 1., 0.), v3!(0., 0., 1.)] {
+        let drill = Arc::new(Cylinder::new(center - axis, center + axis, 0.4, true, steel.clone()));
+        solid = Arc::new(Csg::difference(solid, drill));
+    }
+    world.add(solid);
+
+    let light = Arc::new(DiffuseLight::new(&v3!(4., 4., 4.)));
+    world.add(Arc::new(Disk::new(v3!(-1., 6., 3.), v3!(0., -1., 0.), 2., light)));
+
+    let lookfrom = v3!(2., 3.5, 8.);
+    let lookat = v3!(0., 0.8, 0.);
+    let vup = v3!(0., 1., 0.);
+    let camera = Camera::new(lookfrom, lookat, vup, 35., aspect_ratio, 0., 10.);
+
+    Scene::new(world, camera, Background::Sky)
+}
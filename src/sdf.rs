@@ -0,0 +1,347 @@
+//! Shapes given by signed distance functions and found by sphere tracing:
+//! a ray can safely step as far as the distance to the nearest surface.
+
+use std::sync::Arc;
+
+use vec3::{v3, Point3, Vec3};
+
+use crate::{aabb::Aabb, hittable::{walk_spans, HitRecord, Hittable, Span}, material::Material, ray::Ray};
+
+/// Steps taken before a ray running along a surface without meeting it is
+/// given up on.
+const MAX_STEPS: usize = 1000;
+
+/// A signed distance function: negative inside the shape and positive
+/// outside, and never more than the distance to the surface, though it may
+/// be less.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: &Point3) -> f64;
+
+    /// Box the shape lies in.
+    fn bounding_box(&self) -> Aabb;
+}
+
+/// A distance function given as a closure, with the box it is zero in.
+pub struct SdfFn<F> {
+    f: F,
+    bounds: Aabb,
+}
+
+impl<F: Fn(&Point3) -> f64 + Send + Sync> SdfFn<F> {
+    pub fn new(f: F, bounds: Aabb) -> Self {
+        Self { f, bounds }
+    }
+}
+
+impl<F: Fn(&Point3) -> f64 + Send + Sync> Sdf for SdfFn<F> {
+    fn distance(&self, p: &Point3) -> f64 {
+        (self.f)(p)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+pub struct SdfSphere {
+    pub center: Point3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &Point3) -> f64 {
+        (*p - self.center).length() - self.radius
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = v3!(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+/// Axis aligned box reaching `half_size` either side of `center`, with its
+/// edges rounded off to `rounding`.
+pub struct SdfBox {
+    pub center: Point3,
+    pub half_size: Vec3,
+    pub rounding: f64,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: &Point3) -> f64 {
+        let d = *p - self.center;
+        let q = v3!(d.x().abs(), d.y().abs(), d.z().abs()) - self.half_size + v3!(1., 1., 1.) * self.rounding;
+        let outside = v3!(q.x().max(0.), q.y().max(0.), q.z().max(0.)).length();
+        let inside = q.x().max(q.y()).max(q.z()).min(0.);
+        outside + inside - self.rounding
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::new(self.center - self.half_size, self.center + self.half_size)
+    }
+}
+
+/// Ring lying flat, with the y axis through its hole.
+pub struct SdfTorus {
+    pub center: Point3,
+    pub major: f64,
+    pub minor: f64,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: &Point3) -> f64 {
+        let d = *p - self.center;
+        (d.x().hypot(d.z()) - self.major).hypot(d.y()) - self.minor
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let e = v3!(self.major + self.minor, self.minor, self.major + self.minor);
+        Aabb::new(self.center - e, self.center + e)
+    }
+}
+
+/// Points within `radius` of the segment from `a` to `b`.
+pub struct SdfCapsule {
+    pub a: Point3,
+    pub b: Point3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: &Point3) -> f64 {
+        let (pa, ba) = (*p - self.a, self.b - self.a);
+        let h = (pa.dot(&ba) / ba.length_squared()).clamp(0., 1.);
+        (pa - h * ba).length() - self.radius
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = v3!(self.radius, self.radius, self.radius);
+        Aabb::new(self.a - r, self.a + r).surrounding(&Aabb::new(self.b - r, self.b + r))
+    }
+}
+
+/// Minimum of `a` and `b`, rounded off where they are within `k` of each
+/// other, by at most `k / 4`.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0. {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.) / k;
+    a.min(b) - h * h * k / 4.
+}
+
+/// Box `b` grown by `k` on every side.
+fn grow(b: &Aabb, k: f64) -> Aabb {
+    let k = v3!(k, k, k);
+    Aabb::new(b.min - k, b.max + k)
+}
+
+/// Both shapes, blended together over about `k` where they meet.
+pub struct SmoothUnion {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: &Point3) -> f64 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        grow(&self.a.bounding_box().surrounding(&self.b.bounding_box()), self.k / 4.)
+    }
+}
+
+/// `a` with `b` carved out of it, the edges of the cut rounded over `k`.
+pub struct SmoothSubtraction {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothSubtraction {
+    fn distance(&self, p: &Point3) -> f64 {
+        -smooth_min(-self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.a.bounding_box()
+    }
+}
+
+/// What the shapes have in common, its edges rounded over `k`.
+pub struct SmoothIntersection {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothIntersection {
+    fn distance(&self, p: &Point3) -> f64 {
+        -smooth_min(-self.a.distance(p), -self.b.distance(p), self.k)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.a.bounding_box().intersection(&self.b.bounding_box())
+    }
+}
+
+/// Surface where a signed distance function is zero.
+pub struct SdfShape {
+    sdf: Arc<dyn Sdf>,
+    bounds: Aabb,
+    /// how close to the surface counts as on it
+    epsilon: f64,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl SdfShape {
+    /// The distance has to be positive all over the faces of
+    /// `sdf.bounding_box()`, so that the box holds the whole of the shape.
+    /// Otherwise the box cuts it open and it has no inside to tell `spans`
+    /// about.
+    pub fn new(sdf: Arc<dyn Sdf>, m: Arc<dyn Material>) -> Self {
+        let epsilon = 1e-5 * sdf.bounding_box().radius().max(1e-3);
+        Self {
+            // with some room, so that rays from outside start clear of
+            // surfaces lying on the box
+            bounds: grow(&sdf.bounding_box(), 100. * epsilon),
+            sdf,
+            epsilon,
+            mat_ptr: m,
+        }
+    }
+
+    /// Direction the distance grows fastest in, from four samples at the
+    /// corners of a tetrahedron.
+    fn normal(&self, p: &Point3) -> Vec3 {
+        let h = 10. * self.epsilon;
+        [v3!(1., -1., -1.), v3!(-1., -1., 1.), v3!(-1., 1., -1.), v3!(1., 1., 1.)]
+            .into_iter()
+            .fold(v3!(0., 0., 0.), |n, k| n + self.sdf.distance(&(*p + h * k)) * k)
+            .unit_vector()
+    }
+}
+
+impl Hittable for SdfShape {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (mut t, t_end) = self.bounds.clip(r, t_min, t_max)?;
+        let length = r.direction().length();
+        // a ray leaving the surface starts on it, so only count the surface
+        // once the ray has got clear of it
+        let mut clear = false;
+        for _ in 0..MAX_STEPS {
+            let d = self.sdf.distance(&r.at(t)).abs();
+            if d < self.epsilon && clear {
+                let p = r.at(t);
+                return Some(HitRecord::new(p, t, self.normal(&p), *r, &*self.mat_ptr));
+            }
+            clear |= d >= 2. * self.epsilon;
+            t += d.max(self.epsilon) / length;
+            if t > t_end {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        Some(walk_spans(self, r, t_min, t_max))
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::v3;
+
+    use crate::{hittable::Hittable, material::Lambertian, ray::Ray, sphere::Sphere};
+
+    use super::{Sdf, SdfBox, SdfShape, SdfSphere, SmoothIntersection, SmoothSubtraction, SmoothUnion};
+
+    #[test]
+    fn test_sphere_tracing() {
+        // the same hits as the analytic sphere, from outside and inside
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let shape = SdfShape::new(Arc::new(SdfSphere { center: v3!(1., 0., 0.), radius: 1. }), m.clone());
+        let sphere = Sphere::new(v3!(1., 0., 0.), 1., m.clone());
+        for r in [
+            Ray::new(v3!(-3., 0.3, 0.2), v3!(1., 0., 0.)),
+            Ray::new(v3!(1., -4., 0.5), v3!(0.1, 2., 0.)),
+            Ray::new(v3!(1., 0., 0.), v3!(0., 1., 1.)),
+        ] {
+            let (a, b) = (shape.hit(&r, 0.001, f64::INFINITY).unwrap(), sphere.hit(&r, 0.001, f64::INFINITY).unwrap());
+            assert!((a.t - b.t).abs() < 1e-4 && (a.normal - b.normal).length() < 1e-4);
+            assert_eq!(a.front_face, b.front_face);
+            // and leaving from the hit, the far side or nothing
+            let next = Ray::new(a.p, *r.direction());
+            let far = sphere.hit(&Ray::new(b.p, *r.direction()), 0.001, f64::INFINITY);
+            assert_eq!(shape.hit(&next, 0.001, f64::INFINITY).is_some(), far.is_some());
+        }
+        assert!(shape.hit(&Ray::new(v3!(-3., 1.1, 0.), v3!(1., 0., 0.)), 0.001, f64::INFINITY).is_none());
+
+        // faces lying on the bounding box are still found
+        let cube = SdfShape::new(Arc::new(SdfBox { center: v3!(0., 0., 0.), half_size: v3!(1., 1., 1.), rounding: 0. }), m);
+        let rec = cube.hit(&Ray::new(v3!(-5., 0.2, 0.3), v3!(1., 0., 0.)), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-4 && (rec.normal - v3!(-1., 0., 0.)).length() < 1e-4);
+    }
+
+    #[test]
+    fn test_smooth_union_bounds() {
+        // blending only adds, and stays inside the box
+        let a: Arc<dyn Sdf> = Arc::new(SdfSphere { center: v3!(-0.8, 0., 0.), radius: 0.5 });
+        let b: Arc<dyn Sdf> = Arc::new(SdfBox { center: v3!(0.8, 0., 0.), half_size: v3!(0.5, 0.5, 0.5), rounding: 0.1 });
+        let union = SmoothUnion { a: a.clone(), b: b.clone(), k: 0.8 };
+        let bounds = union.bounding_box();
+        for i in 0..=40 {
+            let p = v3!(-2. + 0.1 * i as f64, 0.3, 0.);
+            let d = union.distance(&p);
+            assert!(d <= a.distance(&p).min(b.distance(&p)));
+            if d <= 0. {
+                assert!(bounds.min.x() <= p.x() && p.x() <= bounds.max.x());
+            }
+        }
+    }
+
+    #[test]
+    fn test_smooth_subtraction_and_intersection_bounds() {
+        // away from where the surfaces meet they are the exact max, and
+        // inside they stay within the box
+        let a: Arc<dyn Sdf> = Arc::new(SdfBox { center: v3!(0., 0., 0.), half_size: v3!(1., 0.6, 0.8), rounding: 0.1 });
+        let b: Arc<dyn Sdf> = Arc::new(SdfSphere { center: v3!(0.7, 0.3, 0.), radius: 0.8 });
+        let k = 0.3;
+        let subtraction = SmoothSubtraction { a: a.clone(), b: b.clone(), k };
+        let intersection = SmoothIntersection { a: a.clone(), b: b.clone(), k };
+        // the second shape counts inside out when it is cut away
+        for (shape, sign) in [(&subtraction as &dyn Sdf, -1.), (&intersection, 1.)] {
+            let bounds = shape.bounding_box();
+            for i in 0..=30 {
+                for j in 0..=30 {
+                    for l in 0..=30 {
+                        let p = v3!(-1.5 + 0.1 * i as f64, -1.5 + 0.1 * j as f64, -1.5 + 0.1 * l as f64);
+                        let (da, db) = (a.distance(&p), sign * b.distance(&p));
+                        let (d, exact) = (shape.distance(&p), da.max(db));
+                        // the blend only ever cuts deeper, by k / 4 at most
+                        assert!(exact <= d && d <= exact + k / 4. + 1e-12, "{:?} {} {}", p, d, exact);
+                        if (da - db).abs() >= k {
+                            assert_eq!(d, exact, "{:?}", p);
+                        }
+                        if d <= 0. {
+                            assert!((0..3).all(|axis| bounds.min[axis] <= p[axis] && p[axis] <= bounds.max[axis]), "{:?}", p);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Spectral rendering with hero wavelength sampling, after Wilkie et al.,
+//! "Hero Wavelength Spectral Sampling".
+//!
+//! Each path carries three wavelengths in the channels of a `Color`: a
+//! randomly picked hero, which decides where the path goes, and two more
+//! spread evenly over the visible range. RGB colours of the scene are lifted
+//! to spectra with a smooth basis, and the radiance found is turned back
+//! into RGB through the CIE colour matching functions. Past a dispersive
+//! surface only the hero's direction is right, and the path tracer drops
+//! the other two.
+
+use std::sync::OnceLock;
+
+use vec3::{v3, Color};
+
+pub const LAMBDA_MIN: f64 = 360.;
+pub const LAMBDA_MAX: f64 = 830.;
+
+/// Luminous efficacy at the peak of the eye's sensitivity, for turning
+/// lumens into the renderer's units of power.
+pub const LUMENS_PER_WATT: f64 = 683.;
+
+/// Wavelength at which dispersive materials are evaluated when rendering in
+/// RGB: the helium d line, at which glass catalogues quote indices.
+pub const LAMBDA_RGB: f64 = 587.6;
+
+/// The wavelengths carried by a path, in nanometres.
+#[derive(Debug, Clone, Copy)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+}
+
+impl Wavelengths {
+    /// Hero wavelength at `u` in [0, 1) along the visible range, and two
+    /// others a third and two thirds of the range further, wrapping around.
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.; 3];
+        for (i, lambda) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f64 / 3.).fract();
+            *lambda = LAMBDA_MIN + offset * range;
+        }
+        Self { lambda }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Value of the spectrum lifted from `rgb` at each wavelength.
+    pub fn lift(&self, rgb: &Color) -> Color {
+        v3!(lift(rgb, self.lambda[0]), lift(rgb, self.lambda[1]), lift(rgb, self.lambda[2]))
+    }
+
+    /// Estimate of the RGB colour of radiance `l` sampled at these wavelengths.
+    pub fn rgb(&self, l: &Color) -> Color {
+        // each wavelength is drawn uniformly over the range
+        let pdf = 1. / (LAMBDA_MAX - LAMBDA_MIN);
+        let mut xyz = v3!(0., 0., 0.);
+        for i in 0..3 {
+            xyz = xyz + l[i] * cie_xyz(self.lambda[i]) / pdf;
+        }
+        xyz_to_rgb(&(xyz / 3.))
+    }
+}
+
+/// Spectral radiance of a black body at `temperature` Kelvin, by Planck's
+/// law, with `lambda` in nanometres.
+pub fn planck(lambda: f64, temperature: f64) -> f64 {
+    const H: f64 = 6.62607015e-34;
+    const C: f64 = 299_792_458.;
+    const K: f64 = 1.380649e-23;
+    let l = lambda * 1e-9;
+    2. * H * C * C / (l.powi(5) * ((H * C / (l * K * temperature)).exp() - 1.))
+}
+
+/// Luminance of `planck` at `temperature`, in units in which a spectrum of
+/// 1 everywhere has luminance 1.
+pub fn planck_luminance(temperature: f64) -> f64 {
+    let steps = 200;
+    let dlambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let (mut y, mut white) = (0., 0.);
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * dlambda;
+        y += planck(lambda, temperature) * cie_xyz(lambda).y();
+        white += cie_xyz(lambda).y();
+    }
+    y / white
+}
+
+/// Colour of a black body at `temperature` Kelvin, with unit luminance:
+/// about 1900 for a candle, 2700 for a tungsten bulb and 6500 for daylight.
+/// The colour is in linear sRGB, whose white is daylight, rather than in the
+/// basis `lift` uses, so that 6500 comes out white as lighting artists
+/// expect; colours past what it can show are clipped to it.
+pub fn blackbody(temperature: f64) -> Color {
+    let steps = 200;
+    let dlambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut xyz = v3!(0., 0., 0.);
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * dlambda;
+        xyz = xyz + planck(lambda, temperature) * cie_xyz(lambda);
+    }
+    let rgb = v3!(
+        3.2406 * xyz.x() - 1.5372 * xyz.y() - 0.4986 * xyz.z(),
+        -0.9689 * xyz.x() + 1.8758 * xyz.y() + 0.0415 * xyz.z(),
+        0.0557 * xyz.x() - 0.2040 * xyz.y() + 1.0570 * xyz.z()
+    );
+    let rgb = v3!(rgb.x().max(0.), rgb.y().max(0.), rgb.z().max(0.));
+    rgb / rgb.luminance()
+}
+
+/// Gaussian with different widths on either side of its peak.
+fn lobe(lambda: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let t = (lambda - mu) / if lambda < mu { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions, in the multi-lobe fit of Wyman et al.,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions".
+pub fn cie_xyz(lambda: f64) -> Color {
+    v3!(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8)
+    )
+}
+
+/// Blue, green and red basis spectra. They add up to one everywhere, so that
+/// white lifts to a constant spectrum and colours in [0, 1] stay in [0, 1].
+fn basis(lambda: f64) -> Color {
+    let b = lobe(lambda, 450., 30., 30.);
+    let g = lobe(lambda, 540., 35., 35.);
+    let r = lobe(lambda, 620., 40., 40.);
+    // keep the ends from dividing by almost nothing
+    let b = if lambda < 450. { 1. } else { b };
+    let r = if lambda > 620. { 1. } else { r };
+    let sum = r + g + b;
+    v3!(r / sum, g / sum, b / sum)
+}
+
+/// Value at `lambda` of the smooth spectrum standing for `rgb`.
+pub fn lift(rgb: &Color, lambda: f64) -> f64 {
+    basis(lambda).dot(rgb)
+}
+
+/// Convert XYZ back to the RGB the basis spectra stand for, so that colours
+/// survive being lifted and converted back. This is the renderer's own RGB,
+/// not linear sRGB: its primaries are the colours of the basis spectra, and
+/// its white the constant spectrum rather than D65. It would only be sRGB
+/// with basis spectra matching the sRGB primaries.
+fn xyz_to_rgb(xyz: &Color) -> Color {
+    static MATRIX: OnceLock<[Color; 3]> = OnceLock::new();
+    let m = MATRIX.get_or_init(|| {
+        // columns: XYZ of each basis spectrum
+        let mut columns = [v3!(0., 0., 0.); 3];
+        let steps = 2000;
+        let dlambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) * dlambda;
+            let (xyz, basis) = (cie_xyz(lambda), basis(lambda));
+            for (j, column) in columns.iter_mut().enumerate() {
+                *column = *column + basis[j] * dlambda * xyz;
+            }
+        }
+        invert(&columns)
+    });
+    v3!(m[0].dot(xyz), m[1].dot(xyz), m[2].dot(xyz))
+}
+
+/// Rows of the inverse of the matrix with `columns`.
+fn invert(columns: &[Color; 3]) -> [Color; 3] {
+    let [a, b, c] = columns;
+    let det = a.dot(&b.cross(c));
+    [b.cross(c) / det, c.cross(a) / det, a.cross(b) / det]
+}
+
+#[cfg(test)]
+mod test {
+    use vec3::v3;
+
+    use super::{blackbody, cie_xyz, lift, xyz_to_rgb, Wavelengths, LAMBDA_MAX, LAMBDA_MIN};
+
+    #[test]
+    fn test_colours_survive_lifting() {
+        for rgb in [v3!(1., 1., 1.), v3!(0.2, 0.5, 0.8), v3!(1., 0., 0.)] {
+            let steps = 1000;
+            let dlambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+            let mut xyz = v3!(0., 0., 0.);
+            for i in 0..steps {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * dlambda;
+                xyz = xyz + lift(&rgb, lambda) * dlambda * cie_xyz(lambda);
+            }
+            assert!((xyz_to_rgb(&xyz) - rgb).length() < 1e-3, "{:?} {:?}", rgb, xyz_to_rgb(&xyz));
+        }
+    }
+
+    #[test]
+    fn test_white_spectrum() {
+        // a flat spectrum is white, without going through `lift`
+        let steps = 1000;
+        let dlambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let xyz = (0..steps).map(|i| cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * dlambda) * dlambda).fold(v3!(0., 0., 0.), |a, b| a + b);
+        let rgb = xyz_to_rgb(&xyz);
+        assert!((rgb - v3!(1., 1., 1.)).length() < 1e-3, "{:?}", rgb);
+    }
+
+    #[test]
+    fn test_white_estimate() {
+        // a constant spectrum comes out white on average
+        let n = 100_000;
+        let mut rgb = v3!(0., 0., 0.);
+        for i in 0..n {
+            let wavelengths = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+            rgb = rgb + wavelengths.rgb(&v3!(1., 1., 1.));
+        }
+        assert!((rgb / n as f64 - v3!(1., 1., 1.)).length() < 1e-2, "{:?}", rgb / n as f64);
+    }
+
+    #[test]
+    fn test_blackbody() {
+        // warm lamps are orange, daylight is about white and hotter stars blue
+        let candle = blackbody(1900.);
+        assert!(candle.x() > candle.y() && candle.y() > candle.z(), "{:?}", candle);
+        let daylight = blackbody(6500.);
+        assert!((daylight - v3!(1., 1., 1.)).length() < 0.1, "{:?}", daylight);
+        let star = blackbody(15000.);
+        assert!(star.z() > star.y() && star.y() > star.x(), "{:?}", star);
+        for t in [1000., 2700., 4000., 10000.] {
+            assert!((blackbody(t).luminance() - 1.).abs() < 1e-9);
+        }
+    }
+}
@@ -1,8 +1,9 @@
 use std::sync::Arc;
 
-use vec3::Point3;
+use utils::PI;
+use vec3::{random_to_sphere, random_unit_vector, v3, Onb, Point3, Vec3};
 
-use crate::{hittable::{Hittable, HitRecord}, ray::Ray, material::Material};
+use crate::{aabb::Aabb, hittable::{Hittable, HitRecord, Span}, ray::Ray, material::Material};
 
 pub struct Sphere {
     pub center: Point3,
@@ -18,11 +19,27 @@ impl Sphere {
             mat_ptr: m,
         }
     }
+
+    fn record(&self, r: &Ray, t: f64) -> HitRecord<'_> {
+        let p = r.at(t);
+        let normal = (p - self.center) / self.radius;
+        let (u, v) = Self::uv(&normal);
+        // eastwards, the way the longitude grows
+        let dpdu = 2. * PI * self.radius * v3!(normal.z(), 0., -normal.x());
+        HitRecord::new(p, t, normal, *r, &*self.mat_ptr).with_uv(u, v).with_tangent(dpdu)
+    }
+
+    /// Longitude and latitude of a point on the unit sphere, scaled to [0, 1].
+    fn uv(p: &Point3) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+        (phi / (2. * PI), theta / PI)
+    }
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin() - &self.center;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let oc = r.origin() - self.center;
         let a = r.direction().length_squared();
         let half_b = oc.dot(r.direction());
         let c = oc.length_squared() - self.radius * self.radius;
@@ -40,10 +57,121 @@ impl Hittable for Sphere {
                 return None;
             }
         }
-        let t = root;
-        let p = r.at(t);
-        let normal = (p - self.center) / self.radius;
-        let hit_rec = HitRecord::new(p, t, normal, *r, &*self.mat_ptr);
-        Some(hit_rec)
+        Some(self.record(r, root))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = v3!(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    fn spans(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Vec<Span<'_>>> {
+        let oc = r.origin() - self.center;
+        let a = r.direction().length_squared();
+        let half_b = oc.dot(r.direction());
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant <= 0. {
+            return Some(vec![]);
+        }
+        let sqrtd = discriminant.sqrt();
+        let (enter, exit) = ((-half_b - sqrtd) / a, (-half_b + sqrtd) / a);
+        if exit < t_min || t_max < enter {
+            return Some(vec![]);
+        }
+        Some(vec![Span {
+            enter: self.record(r, enter),
+            exit: self.record(r, exit),
+        }])
+    }
+
+    fn is_closed(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.hit(&Ray::new(*origin, *direction), 0.001, utils::INFINITY).is_none() {
+            return 0.;
+        }
+        let distance_squared = (self.center - origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 0.;
+        }
+        let cos_theta_max = (1. - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2. * PI * (1. - cos_theta_max);
+        1. / solid_angle
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let uvw = Onb::build_from_w(&direction);
+        uvw.local(&random_to_sphere(self.radius, distance_squared))
+    }
+
+    fn area(&self) -> f64 {
+        4. * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord<'_>> {
+        let normal = random_unit_vector();
+        let p = self.center + self.radius * normal;
+        Some(HitRecord::new(p, 0., normal, Ray::new(p + normal, -normal), &*self.mat_ptr))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::PI;
+    use vec3::{random_in_unit_sphere, v3};
+
+    use crate::{hittable::Hittable, material::Lambertian, ray::Ray};
+
+    use super::Sphere;
+
+    #[test]
+    fn test_sampling() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let sphere = Sphere::new(v3!(0., 0., -2.), 1., m);
+        let origin = v3!(0., 0., 0.);
+        let solid_angle = 2. * PI * (1. - 0.75_f64.sqrt());
+
+        // uniform directions find the sphere as often as its solid angle says,
+        // and the density over them adds up to one
+        let n = 200_000;
+        let (mut seen, mut total) = (0, 0.);
+        for _ in 0..n {
+            let pdf = sphere.pdf_value(&origin, &random_in_unit_sphere().unit_vector());
+            seen += (pdf > 0.) as usize;
+            total += pdf;
+        }
+        assert!((4. * PI * seen as f64 / n as f64 - solid_angle).abs() < 0.04 * solid_angle, "{}", seen);
+        assert!((4. * PI * total / n as f64 - 1.).abs() < 0.04, "{}", 4. * PI * total / n as f64);
+
+        // sampled directions all hit it
+        for _ in 0..1000 {
+            let direction = sphere.random(&origin);
+            assert!(sphere.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).is_some());
+            assert!((sphere.pdf_value(&origin, &direction) - 1. / solid_angle).abs() < 1e-9);
+        }
+        // from inside it can't be sampled this way
+        assert_eq!(sphere.pdf_value(&v3!(0., 0., -2.), &v3!(1., 0., 0.)), 0.);
+
+        // points on the surface, facing out, spread evenly
+        assert!((sphere.area() - 4. * PI).abs() < 1e-12);
+        let mut mean = v3!(0., 0., 0.);
+        for _ in 0..n {
+            let rec = sphere.sample_surface().unwrap();
+            assert!(((rec.p - sphere.center).length() - 1.).abs() < 1e-9);
+            assert!((rec.normal - (rec.p - sphere.center)).length() < 1e-9 && rec.front_face);
+            mean = mean + rec.p / n as f64;
+        }
+        assert!((mean - sphere.center).length() < 0.01, "{:?}", mean);
     }
 }
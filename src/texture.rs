@@ -0,0 +1,179 @@
+//! Scalar textures: numbers that vary over a surface, for masks and
+//! weights.
+
+use utils::{Rng, SeedableRng, StdRng, PI};
+use vec3::{v3, Point3, Vec3};
+
+use crate::hittable::HitRecord;
+
+pub trait Texture: Send + Sync {
+    /// Value at the hit point `rec`, usually between 0 and 1.
+    fn value(&self, rec: &HitRecord) -> f64;
+}
+
+/// The same value everywhere.
+pub struct Constant(pub f64);
+
+impl Texture for Constant {
+    fn value(&self, _rec: &HitRecord) -> f64 {
+        self.0
+    }
+}
+
+/// Alternating 0 and 1 in squares of side `1 / frequency` across the
+/// surface coordinates.
+pub struct Checker {
+    pub frequency: f64,
+}
+
+impl Texture for Checker {
+    fn value(&self, rec: &HitRecord) -> f64 {
+        let cell = (rec.u * self.frequency).floor() + (rec.v * self.frequency).floor();
+        if cell.rem_euclid(2.) == 0. { 0. } else { 1. }
+    }
+}
+
+const POINT_COUNT: usize = 256;
+
+/// Perlin noise with random gradients at the lattice points, from
+/// "Ray Tracing: The Next Week". The same `seed` gives the same noise.
+struct Perlin {
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                let z: f64 = rng.gen_range(-1. ..1.);
+                let phi = 2. * PI * rng.gen::<f64>();
+                let r = (1. - z * z).sqrt();
+                v3!(r * phi.cos(), r * phi.sin(), z)
+            })
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            for i in (1..POINT_COUNT).rev() {
+                p.swap(i, rng.gen_range(0..=i));
+            }
+            p
+        };
+        Self {
+            gradients,
+            perm: [permutation(), permutation(), permutation()],
+        }
+    }
+
+    /// Smooth noise between about -1 and 1.
+    fn noise(&self, p: &Point3) -> f64 {
+        let floor = v3!(p.x().floor(), p.y().floor(), p.z().floor());
+        let f = *p - floor;
+        let mut accum = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let corner = |axis: usize, d: usize| (floor[axis] as i64 + d as i64).rem_euclid(POINT_COUNT as i64) as usize;
+                    let g = self.gradients[self.perm[0][corner(0, di)] ^ self.perm[1][corner(1, dj)] ^ self.perm[2][corner(2, dk)]];
+                    let weight = v3!(f.x() - di as f64, f.y() - dj as f64, f.z() - dk as f64);
+                    // Hermite smoothing of the distance to the corner
+                    let smooth = |t: f64, d: usize| {
+                        let s = t * t * (3. - 2. * t);
+                        if d == 1 { s } else { 1. - s }
+                    };
+                    accum += smooth(f.x(), di) * smooth(f.y(), dj) * smooth(f.z(), dk) * g.dot(&weight);
+                }
+            }
+        }
+        accum
+    }
+
+    /// Sum of `depth` octaves of noise, each at twice the frequency and half
+    /// the amplitude of the last.
+    fn turbulence(&self, p: &Point3, depth: u32) -> f64 {
+        let mut accum = 0.;
+        let mut p = *p;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            accum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2. * p;
+        }
+        accum.abs()
+    }
+}
+
+/// Blotchy noise over the position of the hit, 1 where the turbulence at
+/// `scale` exceeds `threshold` and fading to 0 below, for rust and dirt.
+/// Different `seed`s give different blotches.
+pub struct Noise {
+    perlin: Perlin,
+    scale: f64,
+    threshold: f64,
+}
+
+impl Noise {
+    pub fn new(scale: f64, threshold: f64, seed: u64) -> Self {
+        Self {
+            perlin: Perlin::new(seed),
+            scale,
+            threshold,
+        }
+    }
+}
+
+impl Texture for Noise {
+    fn value(&self, rec: &HitRecord) -> f64 {
+        let turbulence = self.perlin.turbulence(&(self.scale * rec.p), 7);
+        ((turbulence - self.threshold) * 10.).clamp(0., 1.)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use vec3::v3;
+
+    use crate::{hittable::HitRecord, material::Lambertian, ray::Ray};
+
+    use super::{Checker, Noise, Texture};
+
+    #[test]
+    fn test_checker() {
+        let m = Lambertian::new(&v3!(0.5, 0.5, 0.5));
+        let checker = Checker { frequency: 4. };
+        let at = |u: f64, v: f64| {
+            let ray = Ray::new(v3!(0., 1., 0.), v3!(0., -1., 0.));
+            checker.value(&HitRecord::new(v3!(0., 0., 0.), 1., v3!(0., 1., 0.), ray, &m).with_uv(u, v))
+        };
+        // neighbours across either edge differ, diagonal ones don't
+        assert_eq!(at(0.1, 0.1), 0.);
+        assert_eq!(at(0.35, 0.1), 1.);
+        assert_eq!(at(0.1, 0.35), 1.);
+        assert_eq!(at(0.35, 0.35), 0.);
+        // and so on past the unit square, either way
+        assert_eq!(at(-0.1, 0.1), 1.);
+        assert_eq!(at(1.1, 1.1), 0.);
+    }
+
+    #[test]
+    fn test_noise_is_seeded() {
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let values = |noise: &Noise| -> Vec<f64> {
+            (0..200)
+                .map(|i| {
+                    let p = v3!(0.37 * i as f64, 0.11 * i as f64, -0.23 * i as f64);
+                    let ray = Ray::new(p + v3!(0., 1., 0.), v3!(0., -1., 0.));
+                    noise.value(&HitRecord::new(p, 1., v3!(0., 1., 0.), ray, &*m))
+                })
+                .collect()
+        };
+        let a = values(&Noise::new(2., 0.35, 7));
+        assert_eq!(a, values(&Noise::new(2., 0.35, 7)));
+        assert_ne!(a, values(&Noise::new(2., 0.35, 8)));
+        assert!(a.iter().all(|v| (0. ..=1.).contains(v)));
+        assert!(a.contains(&0.) && a.contains(&1.));
+    }
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use utils::random_double;
+use vec3::{Point3, Vec3};
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable}, material::Material, ray::Ray};
+
+pub struct Triangle {
+    a: Point3,
+    e1: Vec3,
+    e2: Vec3,
+    mat_ptr: Arc<dyn Material>,
+    normal: Vec3,
+    area: f64,
+}
+
+impl Triangle {
+    pub fn new(a: Point3, b: Point3, c: Point3, m: Arc<dyn Material>) -> Self {
+        let e1 = b - a;
+        let e2 = c - a;
+        let n = e1.cross(&e2);
+        Self {
+            a,
+            e1,
+            e2,
+            mat_ptr: m,
+            normal: n.unit_vector(),
+            area: n.length() / 2.,
+        }
+    }
+
+    fn random_point(&self) -> Point3 {
+        let mut r1 = random_double();
+        let mut r2 = random_double();
+        // fold the unit square onto the triangle
+        if r1 + r2 > 1. {
+            r1 = 1. - r1;
+            r2 = 1. - r2;
+        }
+        self.a + r1 * self.e1 + r2 * self.e2
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Möller–Trumbore
+        let pvec = r.direction().cross(&self.e2);
+        let det = self.e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1. / det;
+        let tvec = r.origin() - self.a;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(&self.e1);
+        let v = r.direction().dot(&qvec) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = self.e2.dot(&qvec) * inv_det;
+        if t < t_min || t_max < t {
+            return None;
+        }
+        Some(HitRecord::new(r.at(t), t, self.normal, *r, &*self.mat_ptr).with_uv(u, v).with_tangent(self.e1))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let b = Aabb::new(self.a, self.a + self.e1);
+        Some(b.surrounding(&Aabb::new(self.a, self.a + self.e2)))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.hit(&Ray::new(*origin, *direction), 0.001, utils::INFINITY) {
+            Some(rec) => {
+                let distance_squared = rec.t * rec.t * direction.length_squared();
+                let cosine = (direction.dot(&rec.normal) / direction.length()).abs();
+                distance_squared / (cosine * self.area)
+            }
+            None => 0.,
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.random_point() - origin
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_surface(&self) -> Option<HitRecord<'_>> {
+        let p = self.random_point();
+        Some(HitRecord::new(p, 0., self.normal, Ray::new(p + self.normal, -self.normal), &*self.mat_ptr))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mat_ptr.is_emissive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use utils::PI;
+    use vec3::{random_in_unit_sphere, v3};
+
+    use crate::{hittable::Hittable, material::Lambertian, ray::Ray};
+
+    use super::Triangle;
+
+    #[test]
+    fn test_sampling() {
+        // from the origin this triangle covers an eighth of the sphere
+        let m = Arc::new(Lambertian::new(&v3!(0.5, 0.5, 0.5)));
+        let triangle = Triangle::new(v3!(1., 0., 0.), v3!(0., 1., 0.), v3!(0., 0., 1.), m);
+        let origin = v3!(0., 0., 0.);
+        let solid_angle = PI / 2.;
+
+        // uniform directions find the triangle as often as its solid angle
+        // says, and the density over them adds up to one
+        let n = 200_000;
+        let (mut seen, mut total) = (0, 0.);
+        for _ in 0..n {
+            let pdf = triangle.pdf_value(&origin, &random_in_unit_sphere().unit_vector());
+            seen += (pdf > 0.) as usize;
+            total += pdf;
+        }
+        assert!((4. * PI * seen as f64 / n as f64 - solid_angle).abs() < 0.02 * solid_angle, "{}", seen);
+        assert!((4. * PI * total / n as f64 - 1.).abs() < 0.02, "{}", 4. * PI * total / n as f64);
+
+        // sampled directions all hit it, and undo their density over its
+        // solid angle
+        let mut covered = 0.;
+        for _ in 0..n {
+            let direction = triangle.random(&origin);
+            assert!(triangle.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).is_some());
+            covered += 1. / triangle.pdf_value(&origin, &direction);
+        }
+        assert!((covered / n as f64 - solid_angle).abs() < 0.01 * solid_angle, "{}", covered / n as f64);
+
+        // points on the surface, spread evenly
+        assert!((triangle.area() - 3_f64.sqrt() / 2.).abs() < 1e-12);
+        let mut mean = v3!(0., 0., 0.);
+        for _ in 0..n {
+            let rec = triangle.sample_surface().unwrap();
+            assert!((rec.p.x() + rec.p.y() + rec.p.z() - 1.).abs() < 1e-12 && rec.p.x().min(rec.p.y()).min(rec.p.z()) >= 0.);
+            mean = mean + rec.p / n as f64;
+        }
+        assert!((mean - v3!(1., 1., 1.) / 3.).length() < 0.01, "{:?}", mean);
+    }
+}
@@ -1,4 +1,6 @@
-use rand::Rng;
+use std::{cell::RefCell, sync::atomic::{AtomicUsize, Ordering}};
+
+pub use rand::{rngs::StdRng, Rng, SeedableRng};
 
 pub const PI: f64 = core::f64::consts::PI;
 pub const INFINITY: f64 = f64::INFINITY;
@@ -7,10 +9,59 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.
 }
 
+/// Samples handed out by `random_double` inside `replay_samples`, how many
+/// of them have been used, and where more come from once they run out.
+struct Replay {
+    samples: Vec<f64>,
+    used: usize,
+    rng: StdRng,
+}
+
+/// Number of threads inside `replay_samples`, so that `random_double` only
+/// looks for samples to replay while there can be any.
+static REPLAYING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static REPLAY: RefCell<Option<Replay>> = const { RefCell::new(None) };
+}
+
 /// Returns a random real in [0, 1).
 pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen::<f64>()
+    if REPLAYING.load(Ordering::Relaxed) > 0 {
+        if let Some(u) = REPLAY.with(|replay| replay.borrow_mut().as_mut().map(Replay::next)) {
+            return u;
+        }
+    }
+    rand::thread_rng().gen::<f64>()
+}
+
+impl Replay {
+    fn next(&mut self) -> f64 {
+        if self.used == self.samples.len() {
+            self.samples.push(self.rng.gen::<f64>());
+        }
+        self.used += 1;
+        self.samples[self.used - 1]
+    }
+}
+
+/// Run `f` with `random_double` returning `samples` in order, then numbers
+/// from `rng` once they run out. Returns the result of `f` along with the
+/// samples it used, so that the same computation can be repeated or
+/// perturbed, as Metropolis sampling in primary sample space does. Other
+/// threads keep getting their own random numbers.
+pub fn replay_samples<R>(samples: Vec<f64>, rng: &mut StdRng, f: impl FnOnce() -> R) -> (R, Vec<f64>) {
+    // a placeholder, which costs nothing to make, while `rng` is lent out
+    let lent = std::mem::replace(rng, StdRng::from_seed([0; 32]));
+    let previous = REPLAY.with(|replay| replay.borrow_mut().replace(Replay { samples, used: 0, rng: lent }));
+    REPLAYING.fetch_add(1, Ordering::Relaxed);
+    let result = f();
+    REPLAYING.fetch_sub(1, Ordering::Relaxed);
+    let replay = REPLAY.with(|replay| std::mem::replace(&mut *replay.borrow_mut(), previous)).unwrap();
+    *rng = replay.rng;
+    let mut samples = replay.samples;
+    samples.truncate(replay.used);
+    (result, samples)
 }
 
 /// Returns a random real in [min, max).
@@ -28,9 +79,117 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     x
 }
 
+/// Balance heuristic weight for a sample drawn from a strategy with density
+/// `f_pdf`, when another strategy could have produced it with density `g_pdf`.
+pub fn balance_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    if f_pdf <= 0. {
+        return 0.;
+    }
+    f_pdf / (f_pdf + g_pdf)
+}
+
+/// Power heuristic (exponent 2) weight, see [`balance_heuristic`].
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    if f_pdf <= 0. {
+        return 0.;
+    }
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    f / (f + g)
+}
+
+/// Value of the polynomial with `coefficients`, lowest power first, at `x`,
+/// along with a bound on the rounding error in it.
+fn evaluate(coefficients: &[f64], x: f64) -> (f64, f64) {
+    let (mut value, mut magnitude) = (0., 0.);
+    for &c in coefficients.iter().rev() {
+        value = value * x + c;
+        magnitude = magnitude * x.abs() + c.abs();
+    }
+    (value, 1e-14 * magnitude)
+}
+
+/// Real roots of the polynomial with `coefficients`, lowest power first, in
+/// increasing order. The roots of the derivative split the line into
+/// stretches where the polynomial only rises or only falls, each holding at
+/// most one root, which a safeguarded Newton's method then finds without the loss of precision
+/// that closed formulas suffer from when roots are close together.
+fn polynomial_roots(coefficients: &[f64]) -> Vec<f64> {
+    let largest = coefficients.iter().fold(0_f64, |m, c| m.max(c.abs()));
+    let degree = match coefficients.iter().rposition(|c| c.abs() > 1e-14 * largest) {
+        Some(degree) => degree,
+        None => return vec![],
+    };
+    let c = &coefficients[..=degree];
+    if degree == 0 {
+        return vec![];
+    }
+    if degree == 1 {
+        return vec![-c[0] / c[1]];
+    }
+
+    let derivative: Vec<f64> = c.iter().enumerate().skip(1).map(|(i, c)| i as f64 * c).collect();
+    // all roots lie within this of zero
+    let bound = 1. + c[..degree].iter().fold(0_f64, |m, a| m.max((a / c[degree]).abs()));
+    let mut ends = vec![-bound];
+    ends.extend(polynomial_roots(&derivative).into_iter().filter(|x| x.abs() < bound));
+    ends.push(bound);
+
+    let mut roots: Vec<f64> = vec![];
+    for pair in ends.windows(2) {
+        let (mut lo, mut hi) = (pair[0], pair[1]);
+        let (p_lo, error_lo) = evaluate(c, lo);
+        let (p_hi, error_hi) = evaluate(c, hi);
+        // a turning point that touches zero is a repeated root
+        if p_lo.abs() <= error_lo {
+            if roots.last().is_none_or(|&r| r < lo) {
+                roots.push(lo);
+            }
+            continue;
+        }
+        if p_hi.abs() <= error_hi || (p_lo < 0.) == (p_hi < 0.) {
+            continue;
+        }
+        let rising = p_lo < 0.;
+        // Newton's method, falling back on bisection whenever it would leave
+        // the bracket, until the steps get lost in rounding
+        let mut x = 0.5 * (lo + hi);
+        for _ in 0..100 {
+            let p = evaluate(c, x).0;
+            if (p < 0.) == rising {
+                lo = x;
+            } else {
+                hi = x;
+            }
+            let slope = evaluate(&derivative, x).0;
+            let newton = x - p / slope;
+            let next = if lo < newton && newton < hi { newton } else { 0.5 * (lo + hi) };
+            if (next - x).abs() <= 1e-15 * x.abs() || p == 0. {
+                break;
+            }
+            x = next;
+        }
+        roots.push(x);
+    }
+    if let Some(&last) = ends.last() {
+        let (p, error) = evaluate(c, last);
+        if p.abs() <= error && roots.last().is_none_or(|&r| r < last) {
+            roots.push(last);
+        }
+    }
+    roots
+}
+
+/// Real roots of `a x^4 + b x^3 + c x^2 + d x + e`, in increasing order,
+/// each repeated root once. Leading coefficients of zero are fine, making it
+/// a cubic or lower.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    polynomial_roots(&[e, d, c, b, a])
+}
+
 #[cfg(test)]
 mod test {
-    use crate::random_double;
+    use crate::{balance_heuristic, power_heuristic, random_double, replay_samples, solve_quartic, SeedableRng, StdRng};
 
     #[test]
     fn test_random_double() {
@@ -38,4 +197,64 @@ mod test {
         let v2 = random_double();
         assert_ne!(v1, v2);
     }
+
+    #[test]
+    fn test_replay_samples() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let (first, samples) = replay_samples(vec![0.25], &mut rng, || (random_double(), random_double()));
+        assert_eq!(first.0, 0.25);
+        assert_eq!(samples, vec![first.0, first.1]);
+        let (second, samples) =
+            replay_samples(samples, &mut rng, || [random_double(), random_double(), random_double()]);
+        assert_eq!(second[..2], [first.0, first.1]);
+        assert_eq!(samples.len(), 3);
+        let (_, samples) = replay_samples(samples, &mut rng, random_double);
+        assert_eq!(samples, vec![0.25]);
+        assert_ne!(random_double(), random_double());
+
+        // fresh samples come from the seeded generator alone
+        let draw = || replay_samples(vec![], &mut StdRng::seed_from_u64(3), || [random_double(), random_double()]).0;
+        assert_eq!(draw(), draw());
+    }
+
+    #[test]
+    fn test_heuristics_sum_to_one() {
+        for (a, b) in [(0.3, 2.), (1., 1.), (5., 0.)] {
+            assert!((balance_heuristic(a, b) + balance_heuristic(b, a) - 1.).abs() < 1e-12);
+            assert!((power_heuristic(a, b) + power_heuristic(b, a) - 1.).abs() < 1e-12);
+        }
+        assert_eq!(power_heuristic(0., 0.), 0.);
+    }
+
+    #[test]
+    fn test_solve_quartic() {
+        // expand (x - r0)(x - r1)(x - r2)(x - r3), including close and
+        // repeated roots, and find them again
+        let cases: [&[f64]; 5] = [
+            &[-3., -1., 0.5, 2.],
+            &[1., 1.00001, 5., 7.],
+            &[-2., 1., 1., 3.],
+            &[-1e3, -2e-3, 1e-3, 4e2],
+            &[0.25, 0.25, 0.25, 0.25],
+        ];
+        for roots in cases {
+            let mut c = [1., 0., 0., 0., 0.];
+            for r in roots {
+                for i in (1..5).rev() {
+                    c[i] -= r * c[i - 1];
+                }
+            }
+            let mut expected = roots.to_vec();
+            expected.dedup();
+            let found = solve_quartic(c[0], c[1], c[2], c[3], c[4]);
+            assert_eq!(found.len(), expected.len(), "{:?} {:?}", roots, found);
+            for (f, e) in found.iter().zip(&expected) {
+                assert!((f - e).abs() < 1e-5 * (1. + e.abs()), "{:?} {:?}", roots, found);
+            }
+        }
+        // no real roots, and lower degrees
+        assert!(solve_quartic(1., 0., 0., 0., 1.).is_empty());
+        assert_eq!(solve_quartic(0., 0., 1., 0., -4.), vec![-2., 2.]);
+        assert_eq!(solve_quartic(0., 0., 0., 2., -1.), vec![0.5]);
+    }
 }
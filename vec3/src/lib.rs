@@ -48,6 +48,15 @@ impl Vec3 {
         )
     }
 
+    pub fn max_component(&self) -> Ty {
+        self.0.max(self.1).max(self.2)
+    }
+
+    /// brightness of a linear RGB color as perceived, with Rec. 709 weights
+    pub fn luminance(&self) -> Ty {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
     pub fn sqrt(&self) -> Self {
         Vec3(self.0.sqrt(), self.1.sqrt(), self.2.sqrt())
     }
@@ -86,9 +95,10 @@ pub fn random_in_unit_sphere() -> Vec3 {
     }
 }
 
+/// Uniformly distributed point on the unit disk in the xy plane.
 pub fn random_in_unit_disk() -> Vec3 {
     loop {
-        let p = Vec3::random_range(-1., 1.);
+        let p = v3!(random_double_range(-1., 1.), random_double_range(-1., 1.), 0.);
         if p.length_squared() >= 1. {
             continue;
         }
@@ -98,11 +108,12 @@ pub fn random_in_unit_disk() -> Vec3 {
 
 pub fn random_unit_vector() -> Vec3 {
     loop {
-        let p = v3!(random_double_range(-1., 1.), random_double_range(-1., 1.), 0.);
-        if p.length_squared() >= 1. {
+        let p = random_in_unit_sphere();
+        // too short to normalize reliably
+        if p.length_squared() < 1e-12 {
             continue;
         }
-        return p;
+        return p.unit_vector();
     }
 }
 
@@ -141,7 +152,81 @@ pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
     let cos_theta = (-uv).dot(n).min(1.);
     let r_out_perp = etai_over_etat * (uv + cos_theta * n);
     let r_out_parallel = -((1.0 - r_out_perp.length_squared()).abs().sqrt()) * n;
-    return r_out_parallel + r_out_perp;
+    r_out_parallel + r_out_perp
+}
+
+/// Random direction on the hemisphere around +z, distributed proportional to
+/// $\cos\theta$. Use with [`Onb::local`] to orient it around a normal.
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_double();
+    let r2 = random_double();
+    let phi = 2. * utils::PI * r1;
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
+    let z = (1. - r2).sqrt();
+    v3!(x, y, z)
+}
+
+/// Random direction around +z inside the cone subtended by a sphere of
+/// `radius` whose center lies `distance_squared` away.
+pub fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let r1 = random_double();
+    let r2 = random_double();
+    let z = 1. + r2 * ((1. - radius * radius / distance_squared).max(0.).sqrt() - 1.);
+    let phi = 2. * utils::PI * r1;
+    let x = phi.cos() * (1. - z * z).sqrt();
+    let y = phi.sin() * (1. - z * z).sqrt();
+    v3!(x, y, z)
+}
+
+/// Orthonormal basis whose `w` axis is aligned with a given vector.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w = n.unit_vector();
+        let a = if w.x().abs() > 0.9 { v3!(0., 1., 0.) } else { v3!(1., 0., 0.) };
+        let v = w.cross(&a).unit_vector();
+        let u = v.cross(&w);
+        Self { u, v, w }
+    }
+
+    /// Basis around `n` with its `u` axis along the part of `tangent` that
+    /// lies across `n`, or any `u` if there is none.
+    pub fn build_from_w_u(n: &Vec3, tangent: &Vec3) -> Self {
+        let w = n.unit_vector();
+        let u = *tangent - tangent.dot(&w) * w;
+        if u.length_squared() <= 1e-12 * tangent.length_squared() || u.near_zero() {
+            return Self::build_from_w(n);
+        }
+        let u = u.unit_vector();
+        Self { u, v: w.cross(&u), w }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.u
+    }
+    pub fn v(&self) -> Vec3 {
+        self.v
+    }
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    /// express local coordinates `a` in world space
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+
+    /// express world space `a` in local coordinates, the inverse of `local`
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        v3!(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
 }
 
 macro_rules! impl_binary_op {
@@ -205,6 +290,19 @@ macro_rules! impl_binary_op {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = Ty;
+
+    fn index(&self, axis: usize) -> &Ty {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl ops::Neg for Vec3 {
     type Output = Vec3;
 
@@ -238,5 +336,30 @@ mod test {
         assert_eq!(v1 / v2, v3!(0.25, 0.4, 0.5));
         assert_eq!(v1 * 3., v3!(3., 6., 9.));
     }
+
+    #[test]
+    fn test_onb() {
+        let onb = crate::Onb::build_from_w(&v3!(0., 3., 4.));
+        let w = onb.local(&v3!(0., 0., 1.));
+        assert!((w - v3!(0., 0.6, 0.8)).near_zero());
+        assert!(onb.u().dot(&onb.v()).abs() < 1e-12);
+        assert!(onb.u().dot(&onb.w()).abs() < 1e-12);
+        assert!((onb.u().cross(&onb.v()) - onb.w()).near_zero());
+        let a = v3!(0.3, -1., 2.);
+        assert!((onb.to_local(&onb.local(&a)) - a).near_zero());
+    }
+
+    #[test]
+    fn test_onb_from_tangent() {
+        let onb = crate::Onb::build_from_w_u(&v3!(0., 0., 2.), &v3!(3., 0., 1.));
+        assert!((onb.u() - v3!(1., 0., 0.)).near_zero());
+        assert!((onb.v() - v3!(0., 1., 0.)).near_zero());
+        assert!((onb.u().cross(&onb.v()) - onb.w()).near_zero());
+        // a tangent along the normal leaves any basis around it
+        let onb = crate::Onb::build_from_w_u(&v3!(0., 0., 2.), &v3!(0., 0., 1.));
+        assert!((onb.w() - v3!(0., 0., 1.)).near_zero());
+        let onb = crate::Onb::build_from_w_u(&v3!(0., 0., 2.), &v3!(0., 0., 0.));
+        assert!(onb.u().dot(&onb.w()).abs() < 1e-12);
+    }
 }
 